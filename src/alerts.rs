@@ -0,0 +1,547 @@
+use crate::gtfs_rt::{self, FeedMessage};
+use crate::negotiate::{self, Reply};
+use crate::planner::Plan;
+use crate::schedule::{self, Direction, Line, STATIONS};
+use crate::{gtfs, realtime, v2};
+use actix_web::http::{header, StatusCode};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local, TimeZone};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+// Service alerts, from three places: a JSON file that is read again whenever
+// it changes, alerts posted to the admin API, and an optional GTFS-Realtime
+// Alerts feed. Stations, lines and directions narrow down what an alert is
+// about; an alert naming none of them is about the whole system.
+
+#[derive(Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Severe,
+}
+
+fn default_severity() -> Severity {
+    Severity::Warning
+}
+
+// Where an alert came from; only admin alerts can be changed through the API.
+#[derive(Clone, Copy, Default, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    File,
+    #[default]
+    Admin,
+    Feed,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Alert {
+    pub id: String,
+    pub header: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+    // Either end may be left open.
+    pub active_from: Option<DateTime<Local>>,
+    pub active_until: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub stations: Vec<String>,
+    #[serde(default)]
+    pub lines: Vec<String>,
+    #[serde(default)]
+    pub directions: Vec<String>,
+    #[serde(skip_deserializing)]
+    pub source: Source,
+}
+
+impl Alert {
+    // Station names and aliases become ids, and anything unknown is refused.
+    fn check(&mut self) -> Result<(), String> {
+        if self.id.is_empty() || self.header.is_empty() {
+            return Err("id and header are required".to_string());
+        }
+        for station in self.stations.iter_mut() {
+            let found = schedule::find_station(station)
+                .ok_or_else(|| format!("unknown station '{}'", station))?;
+            *station = STATIONS[found].id.to_string();
+        }
+        if let Some(line) = self.lines.iter().find(|l| Line::parse(l).is_none()) {
+            return Err(format!("line must be 'red' or 'blue', not '{}'", line));
+        }
+        if let Some(direction) = self
+            .directions
+            .iter()
+            .find(|d| Direction::parse(d).is_none())
+        {
+            return Err(format!(
+                "direction must be 'east' or 'west', not '{}'",
+                direction
+            ));
+        }
+        if let (Some(from), Some(until)) = (self.active_from, self.active_until) {
+            if until < from {
+                return Err("active_until is before active_from".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Not over yet: in effect now or starting later.
+    pub fn current(&self, now: DateTime<Local>) -> bool {
+        self.active_until.is_none_or(|until| now <= until)
+    }
+
+    pub fn in_effect(&self, at: DateTime<Local>) -> bool {
+        self.active_from.is_none_or(|from| from <= at) && self.current(at)
+    }
+
+    // In effect at some point between `from` and `to`.
+    pub fn overlaps(&self, from: DateTime<Local>, to: DateTime<Local>) -> bool {
+        self.active_from.is_none_or(|start| start < to) && self.current(from)
+    }
+
+    // About any of the stations, and about the line and direction when
+    // they're given. No stations or lines means any.
+    pub fn touches(
+        &self,
+        stations: &[usize],
+        lines: &[Line],
+        direction: Option<Direction>,
+    ) -> bool {
+        let named =
+            |names: &[String], name: &str| names.is_empty() || names.iter().any(|a| a == name);
+        let station = stations.is_empty()
+            || stations
+                .iter()
+                .any(|s| named(&self.stations, STATIONS[*s].id));
+        let line = lines.is_empty() || lines.iter().any(|l| named(&self.lines, l.as_str()));
+        let direction = direction.is_none_or(|d| named(&self.directions, d.as_str()));
+        station && line && direction
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<Alert>, String> {
+    let contents = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut alerts: Vec<Alert> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    for i in 0..alerts.len() {
+        if alerts[..i].iter().any(|a| a.id == alerts[i].id) {
+            return Err(format!(
+                "{}: alert '{}' is listed twice",
+                path, alerts[i].id
+            ));
+        }
+        let alert = &mut alerts[i];
+        alert
+            .check()
+            .map_err(|e| format!("{}: alert '{}': {}", path, alert.id, e))?;
+        alert.source = Source::File;
+    }
+    Ok(alerts)
+}
+
+pub struct Alerts {
+    file: Option<String>,
+    // The file's modification time when last read, and what it held.
+    from_file: Mutex<(Option<SystemTime>, Vec<Alert>)>,
+    posted: RwLock<Vec<Alert>>,
+    from_feed: RwLock<Vec<Alert>>,
+}
+
+impl Alerts {
+    pub fn new(file: Option<&str>) -> Alerts {
+        Alerts {
+            file: file.map(String::from),
+            from_file: Mutex::new((None, Vec::new())),
+            posted: RwLock::new(Vec::new()),
+            from_feed: RwLock::new(Vec::new()),
+        }
+    }
+
+    // A file that fails to load leaves the last good one in use.
+    fn file_alerts(&self) -> Vec<Alert> {
+        let path = match &self.file {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+        let mut cached = self.from_file.lock().expect("alerts lock");
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != cached.0 {
+            match load(path) {
+                Ok(alerts) => *cached = (modified, alerts),
+                Err(e) => println!("alerts not reloaded: {}", e),
+            }
+        }
+        cached.1.clone()
+    }
+
+    pub fn all(&self) -> Vec<Alert> {
+        let mut all = self.file_alerts();
+        all.extend(self.posted.read().expect("alerts lock").iter().cloned());
+        all.extend(self.from_feed.read().expect("alerts lock").iter().cloned());
+        all
+    }
+
+    // In effect at `at` and about one of the stations.
+    pub fn at_stations(
+        &self,
+        stations: &[usize],
+        lines: &[Line],
+        direction: Option<Direction>,
+        at: DateTime<Local>,
+    ) -> Vec<Alert> {
+        self.all()
+            .into_iter()
+            .filter(|a| a.in_effect(at) && a.touches(stations, lines, direction))
+            .collect()
+    }
+
+    // In effect when the trip starts and about any leg: where it's boarded
+    // or left, on its line and in its direction.
+    pub fn on_trip(&self, plan: &Plan) -> Vec<Alert> {
+        let at = v2::local(schedule::service_datetime(plan.date, plan.departure()));
+        self.all()
+            .into_iter()
+            .filter(|a| {
+                a.in_effect(at)
+                    && plan
+                        .legs
+                        .iter()
+                        .any(|leg| a.touches(&[leg.from, leg.to], &[leg.line], Some(leg.direction)))
+            })
+            .collect()
+    }
+}
+
+// Alerts from a GTFS-Realtime feed list the routes, stops and directions
+// they inform as separate entities; here they are pooled into one set of
+// each. Alerts informing nothing on our lines, such as bus detours, are
+// dropped, and of several active periods the current or next one is kept.
+fn from_feed(
+    feed: &FeedMessage,
+    stops: &HashMap<String, usize>,
+    now: DateTime<Local>,
+) -> Vec<Alert> {
+    let time = |t: u64| Local.timestamp_opt(t as i64, 0).single();
+    let line = |route: &str| {
+        let route = route.to_lowercase();
+        Line::ALL
+            .iter()
+            .copied()
+            .find(|l| route.contains(l.as_str()))
+    };
+    let mut alerts = Vec::new();
+    for entity in feed.entity.iter().filter(|e| !e.is_deleted) {
+        let alert = match &entity.alert {
+            Some(a) => a,
+            None => continue,
+        };
+        let (mut stations, mut lines, mut directions) = (Vec::new(), Vec::new(), Vec::new());
+        let mut ours = alert.informed_entity.is_empty();
+        for informed in alert.informed_entity.iter() {
+            let station = match informed.stop_id.is_empty() {
+                true => None,
+                false => stops
+                    .get(&informed.stop_id)
+                    .copied()
+                    .or_else(|| gtfs::station_for(&informed.stop_id)),
+            };
+            let on = line(&informed.route_id);
+            let other_stop = !informed.stop_id.is_empty() && station.is_none();
+            let other_route = !informed.route_id.is_empty() && on.is_none();
+            if other_stop || other_route || (station.is_none() && on.is_none()) {
+                continue;
+            }
+            ours = true;
+            if let Some(s) = station {
+                stations.push(STATIONS[s].id.to_string());
+            }
+            if let Some(l) = on {
+                lines.push(l.as_str().to_string());
+            }
+            // The same numbering our own feed uses.
+            match informed.direction_id {
+                Some(0) => directions.push(Direction::East.as_str().to_string()),
+                Some(1) => directions.push(Direction::West.as_str().to_string()),
+                _ => {}
+            }
+        }
+        if !ours {
+            continue;
+        }
+        for names in [&mut stations, &mut lines, &mut directions] {
+            names.sort();
+            names.dedup();
+        }
+        let period = alert
+            .active_period
+            .iter()
+            .filter(|p| p.end.and_then(time).is_none_or(|end| now <= end))
+            .min_by_key(|p| p.start.unwrap_or(0));
+        if period.is_none() && !alert.active_period.is_empty() {
+            continue;
+        }
+        alerts.push(Alert {
+            id: entity.id.clone(),
+            header: alert.header_text.clone(),
+            description: alert.description_text.clone(),
+            severity: match alert.severity_level {
+                gtfs_rt::INFO => Severity::Info,
+                gtfs_rt::SEVERE => Severity::Severe,
+                _ => Severity::Warning,
+            },
+            active_from: period.and_then(|p| p.start).and_then(time),
+            active_until: period.and_then(|p| p.end).and_then(time),
+            stations,
+            lines,
+            directions,
+            source: Source::Feed,
+        });
+    }
+    alerts
+}
+
+// Polls a GTFS-Realtime Alerts feed for as long as the server runs. A failed
+// fetch keeps the alerts from the last good one. Must be called from within
+// the actix runtime.
+pub fn start(
+    alerts: web::Data<Alerts>,
+    source: String,
+    stops: HashMap<String, usize>,
+    interval: std::time::Duration,
+) {
+    actix_web::rt::spawn(async move {
+        loop {
+            match realtime::fetch(&source)
+                .await
+                .and_then(|bytes| gtfs_rt::decode(&bytes))
+            {
+                Ok(feed) => {
+                    *alerts.from_feed.write().expect("alerts lock") =
+                        from_feed(&feed, &stops, Local::now())
+                }
+                Err(e) => println!("alerts feed {} failed: {}", source, e),
+            }
+            actix_web::rt::time::delay_for(interval).await;
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct AlertsQuery {
+    station: Option<String>,
+    line: Option<String>,
+    direction: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AlertsResponse {
+    alerts: Vec<Alert>,
+}
+
+impl Reply for Alert {
+    const NAME: &'static str = "alert";
+
+    fn text(&self) -> String {
+        format!("{}: {}", self.id, self.header)
+    }
+}
+
+impl Reply for AlertsResponse {
+    const NAME: &'static str = "alerts";
+
+    fn text(&self) -> String {
+        match self.alerts.is_empty() {
+            true => "no alerts".to_string(),
+            false => self
+                .alerts
+                .iter()
+                .map(|a| format!("{}: {}", a.id, a.header))
+                .collect::<Vec<_>>()
+                .join("; "),
+        }
+    }
+}
+
+// Alerts that haven't ended, optionally only those about a station, line or
+// direction.
+#[get("/alerts")]
+pub async fn list(
+    http: HttpRequest,
+    alerts: web::Data<Alerts>,
+    query: web::Query<AlertsQuery>,
+) -> HttpResponse {
+    let station = match query.station.as_deref().map(schedule::find_station) {
+        Some(None) => {
+            return v2::error(
+                &http,
+                StatusCode::NOT_FOUND,
+                "unknown_station",
+                "that station is not in the schedule",
+            )
+        }
+        Some(Some(s)) => Some(s),
+        None => None,
+    };
+    let line = match query.line.as_deref().map(Line::parse) {
+        Some(None) => {
+            return v2::error(
+                &http,
+                StatusCode::BAD_REQUEST,
+                "invalid_line",
+                "line must be 'red' or 'blue'",
+            )
+        }
+        other => other.flatten(),
+    };
+    let direction = match query.direction.as_deref().map(Direction::parse) {
+        Some(None) => {
+            return v2::error(
+                &http,
+                StatusCode::BAD_REQUEST,
+                "invalid_direction",
+                "direction must be 'east' or 'west'",
+            )
+        }
+        other => other.flatten(),
+    };
+    let now = Local::now();
+    let stations: Vec<usize> = station.into_iter().collect();
+    let lines: Vec<Line> = line.into_iter().collect();
+    let alerts = alerts
+        .all()
+        .into_iter()
+        .filter(|a| a.current(now) && a.touches(&stations, &lines, direction))
+        .collect();
+    negotiate::reply(&http, StatusCode::OK, &AlertsResponse { alerts })
+}
+
+// The admin API, mounted at /admin when --admin-token is set. Requests must
+// carry "Authorization: Bearer <token>".
+pub struct Admin {
+    pub token: String,
+}
+
+impl Admin {
+    fn allows(&self, http: &HttpRequest) -> bool {
+        let given = http
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .unwrap_or("");
+        // Compared without stopping at the first difference.
+        given.len() == self.token.len()
+            && given
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+pub fn configure_admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(create).service(replace).service(remove);
+}
+
+fn unauthorized(http: &HttpRequest) -> HttpResponse {
+    v2::error(
+        http,
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+        "a valid admin token is required",
+    )
+}
+
+#[post("/alerts")]
+async fn create(
+    http: HttpRequest,
+    admin: web::Data<Admin>,
+    alerts: web::Data<Alerts>,
+    alert: web::Json<Alert>,
+) -> HttpResponse {
+    if !admin.allows(&http) {
+        return unauthorized(&http);
+    }
+    let mut alert = alert.into_inner();
+    if let Err(e) = alert.check() {
+        return v2::error(&http, StatusCode::BAD_REQUEST, "invalid_alert", &e);
+    }
+    if alerts.all().iter().any(|a| a.id == alert.id) {
+        return v2::error(
+            &http,
+            StatusCode::CONFLICT,
+            "duplicate_id",
+            "an alert with that id already exists",
+        );
+    }
+    alert.source = Source::Admin;
+    alerts
+        .posted
+        .write()
+        .expect("alerts lock")
+        .push(alert.clone());
+    negotiate::reply(&http, StatusCode::CREATED, &alert)
+}
+
+#[put("/alerts/{id}")]
+async fn replace(
+    http: HttpRequest,
+    admin: web::Data<Admin>,
+    alerts: web::Data<Alerts>,
+    id: web::Path<String>,
+    alert: web::Json<Alert>,
+) -> HttpResponse {
+    if !admin.allows(&http) {
+        return unauthorized(&http);
+    }
+    let mut alert = alert.into_inner();
+    alert.id = id.into_inner();
+    if let Err(e) = alert.check() {
+        return v2::error(&http, StatusCode::BAD_REQUEST, "invalid_alert", &e);
+    }
+    alert.source = Source::Admin;
+    let mut posted = alerts.posted.write().expect("alerts lock");
+    match posted.iter_mut().find(|a| a.id == alert.id) {
+        Some(existing) => {
+            *existing = alert.clone();
+            negotiate::reply(&http, StatusCode::OK, &alert)
+        }
+        None => v2::error(
+            &http,
+            StatusCode::NOT_FOUND,
+            "unknown_alert",
+            "no alert with that id was posted here",
+        ),
+    }
+}
+
+#[delete("/alerts/{id}")]
+async fn remove(
+    http: HttpRequest,
+    admin: web::Data<Admin>,
+    alerts: web::Data<Alerts>,
+    id: web::Path<String>,
+) -> HttpResponse {
+    if !admin.allows(&http) {
+        return unauthorized(&http);
+    }
+    let mut posted = alerts.posted.write().expect("alerts lock");
+    match posted.iter().position(|a| a.id == *id) {
+        Some(i) => {
+            posted.remove(i);
+            HttpResponse::NoContent().finish()
+        }
+        None => v2::error(
+            &http,
+            StatusCode::NOT_FOUND,
+            "unknown_alert",
+            "no alert with that id was posted here",
+        ),
+    }
+}
@@ -0,0 +1,430 @@
+use crate::alerts::Alerts;
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::speech;
+use actix_web::client::Client;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::sync::Mutex;
+use url::Url;
+
+// Alexa Skills Kit endpoint. Intents fill "station", "direction" and "line"
+// slots; an intent with "last" or "first" in its name asks for that train.
+//
+// Requests are checked the way Amazon requires of skills hosted outside
+// Lambda: a fresh timestamp, a signing certificate fetched from Amazon's S3
+// bucket and chaining to a trusted root, and an RSA SHA-256 signature over the
+// body. For local fixtures the root and the chain can be supplied as files.
+
+// Amazon's tolerance for request timestamps, in seconds.
+const MAX_AGE: i64 = 150;
+
+const SIGNING_NAME: &str = "echo-api.amazon.com";
+
+const ASK_STATION: &str = "Which station would you like trains for?";
+
+const SLOTS: [&str; 3] = ["station", "direction", "line"];
+
+pub struct Alexa {
+    skill_id: Option<String>,
+    verify: bool,
+    trust_root: Option<Vec<u8>>,
+    cert_chain: Option<Vec<Vec<u8>>>,
+    // Downloaded chains by URL, DER encoded with the signing certificate first.
+    certs: Mutex<HashMap<String, Vec<Vec<u8>>>>,
+}
+
+fn pem_certs(pem: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    match rustls::internal::pemfile::certs(&mut BufReader::new(pem)) {
+        Ok(certs) if !certs.is_empty() => Ok(certs.into_iter().map(|c| c.0).collect()),
+        _ => Err("no PEM certificates found".to_string()),
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    pem_certs(&pem).map_err(|e| format!("{}: {}", path, e))
+}
+
+// https://developer.amazon.com/en-US/docs/alexa/custom-skills/host-a-custom-skill-as-a-web-service.html
+fn valid_cert_url(url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(url) => {
+            url.scheme() == "https"
+                && url.host_str() == Some("s3.amazonaws.com")
+                && url.path().starts_with("/echo.api/")
+                && url.port_or_known_default() == Some(443)
+        }
+        Err(_) => false,
+    }
+}
+
+impl Alexa {
+    pub fn new(
+        skill_id: Option<&str>,
+        verify: bool,
+        trust_root: Option<&str>,
+        cert_chain: Option<&str>,
+    ) -> Result<Alexa, String> {
+        let trust_root = match trust_root.map(read_pem) {
+            Some(certs) => Some(certs?.remove(0)),
+            None => None,
+        };
+        Ok(Alexa {
+            skill_id: skill_id.map(String::from),
+            verify,
+            trust_root,
+            cert_chain: cert_chain.map(read_pem).transpose()?,
+            certs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn chain(&self, url: &str) -> Result<Vec<Vec<u8>>, String> {
+        if !valid_cert_url(url) {
+            return Err(
+                "SignatureCertChainUrl is not an Amazon signing certificate URL".to_string(),
+            );
+        }
+        if let Some(chain) = &self.cert_chain {
+            return Ok(chain.clone());
+        }
+        if let Some(chain) = self.certs.lock().expect("certificate cache lock").get(url) {
+            return Ok(chain.clone());
+        }
+        let mut res = Client::default()
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let pem = res.body().limit(1 << 16).await.map_err(|e| e.to_string())?;
+        let chain = pem_certs(&pem)?;
+        self.certs
+            .lock()
+            .expect("certificate cache lock")
+            .insert(url.to_string(), chain.clone());
+        Ok(chain)
+    }
+
+    async fn check_signature(
+        &self,
+        http: &HttpRequest,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let header = |name| {
+            http.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+        };
+        let signature = base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            header("Signature-256"),
+        )
+        .map_err(|_| "Signature-256 is not base64".to_string())?;
+        let chain = self.chain(header("SignatureCertChainUrl")).await?;
+        let custom;
+        let anchors = match &self.trust_root {
+            Some(root) => {
+                custom = [webpki::trust_anchor_util::cert_der_as_trust_anchor(root)
+                    .map_err(|e| format!("{:?}", e))?];
+                &custom[..]
+            }
+            None => webpki_roots::TLS_SERVER_ROOTS.0,
+        };
+        let cert = webpki::EndEntityCert::from(&chain[0]).map_err(|e| format!("{:?}", e))?;
+        let intermediates: Vec<&[u8]> = chain[1..].iter().map(|c| c.as_slice()).collect();
+        let algorithms = [&webpki::RSA_PKCS1_2048_8192_SHA256];
+        let time = webpki::Time::from_seconds_since_unix_epoch(now.timestamp() as u64);
+        cert.verify_is_valid_tls_server_cert(
+            &algorithms,
+            &webpki::TLSServerTrustAnchors(anchors),
+            &intermediates,
+            time,
+        )
+        .map_err(|e| format!("signing certificate: {:?}", e))?;
+        let name = webpki::DNSNameRef::try_from_ascii_str(SIGNING_NAME).expect("valid DNS name");
+        cert.verify_is_valid_for_dns_name(name)
+            .map_err(|_| "signing certificate is not for echo-api.amazon.com".to_string())?;
+        cert.verify_signature(&webpki::RSA_PKCS1_2048_8192_SHA256, body, &signature)
+            .map_err(|_| "request signature does not match".to_string())
+    }
+
+    fn check_envelope(&self, envelope: &Envelope, now: DateTime<Utc>) -> Result<(), String> {
+        if (now - envelope.request.timestamp).num_seconds().abs() > MAX_AGE {
+            return Err("request timestamp is too old".to_string());
+        }
+        match &self.skill_id {
+            Some(id) if envelope.application_id() != Some(id.as_str()) => {
+                Err("request is for another skill".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Envelope {
+    session: Option<Session>,
+    context: Option<Context>,
+    request: Request,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    application: Application,
+}
+
+#[derive(Deserialize)]
+struct Context {
+    #[serde(rename = "System")]
+    system: Session,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Application {
+    application_id: String,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(rename = "type")]
+    kind: String,
+    timestamp: DateTime<Utc>,
+    intent: Option<Intent>,
+}
+
+#[derive(Deserialize)]
+struct Intent {
+    name: String,
+    #[serde(default)]
+    slots: HashMap<String, Slot>,
+}
+
+#[derive(Deserialize)]
+struct Slot {
+    value: Option<String>,
+    resolutions: Option<Resolutions>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Resolutions {
+    #[serde(default)]
+    resolutions_per_authority: Vec<Authority>,
+}
+
+#[derive(Deserialize)]
+struct Authority {
+    #[serde(default)]
+    values: Vec<ResolvedValue>,
+}
+
+#[derive(Deserialize)]
+struct ResolvedValue {
+    value: Value,
+}
+
+impl Envelope {
+    fn application_id(&self) -> Option<&str> {
+        self.context
+            .as_ref()
+            .map(|c| c.system.application.application_id.as_str())
+            .or_else(|| {
+                self.session
+                    .as_ref()
+                    .map(|s| s.application.application_id.as_str())
+            })
+    }
+}
+
+// Entity resolution gives the canonical value when the skill's slot type
+// lists synonyms; otherwise use what was heard.
+impl Slot {
+    fn text(&self) -> Option<&str> {
+        let resolved = self
+            .resolutions
+            .iter()
+            .flat_map(|r| r.resolutions_per_authority.iter())
+            .flat_map(|a| a.values.iter())
+            .find_map(|v| v.value["name"].as_str());
+        resolved.or(self.value.as_deref()).filter(|v| !v.is_empty())
+    }
+}
+
+fn speak(ssml: &str, text: &str, end_session: bool) -> HttpResponse {
+    let mut response = json!({
+        "outputSpeech": { "type": "SSML", "ssml": ssml },
+        "shouldEndSession": end_session,
+    });
+    if end_session {
+        response["card"] = json!({ "type": "Simple", "title": "MetroLink", "content": text });
+    } else {
+        response["reprompt"] = json!({ "outputSpeech": { "type": "PlainText", "text": text } });
+    }
+    HttpResponse::Ok().json(json!({ "version": "1.0", "response": response }))
+}
+
+fn ask_station() -> HttpResponse {
+    speak(
+        &format!("<speak>{}</speak>", ASK_STATION),
+        ASK_STATION,
+        false,
+    )
+}
+
+pub async fn skill(
+    http: HttpRequest,
+    alexa: web::Data<Alexa>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let now = Utc::now();
+    if alexa.verify {
+        if let Err(e) = alexa.check_signature(&http, &body, now).await {
+            return HttpResponse::BadRequest()
+                .reason("invalid Alexa signature")
+                .body(e);
+        }
+    }
+    let envelope: Envelope = match serde_json::from_slice(&body) {
+        Ok(e) => e,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .reason("not an Alexa request")
+                .finish()
+        }
+    };
+    if alexa.verify {
+        if let Err(e) = alexa.check_envelope(&envelope, now) {
+            return HttpResponse::BadRequest()
+                .reason("invalid Alexa request")
+                .body(e);
+        }
+    }
+    let intent = match (envelope.request.kind.as_str(), &envelope.request.intent) {
+        ("LaunchRequest", _) => return ask_station(),
+        ("IntentRequest", Some(intent)) => intent,
+        _ => return HttpResponse::Ok().json(json!({ "version": "1.0", "response": {} })),
+    };
+    match intent.name.as_str() {
+        "AMAZON.HelpIntent" => return ask_station(),
+        "AMAZON.StopIntent" | "AMAZON.CancelIntent" => {
+            return speak("<speak>Goodbye.</speak>", "Goodbye.", true)
+        }
+        _ => {}
+    }
+    let slots: Vec<&str> = SLOTS
+        .iter()
+        .filter_map(|name| intent.slots.get(*name).and_then(Slot::text))
+        .collect();
+    match speech::from_slots(
+        &tt,
+        &realtime,
+        &alerts,
+        &intent.name,
+        &slots,
+        Local::now().naive_local(),
+    ) {
+        Some(speech) => speak(&speech.ssml, &speech.text, true),
+        None => ask_station(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    // tests/fixtures/alexa/make.sh makes these: a root, a chain from it to a
+    // certificate for echo-api.amazon.com, and a request signed with it.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/alexa/");
+
+    const CHAIN_URL: &str = "https://s3.amazonaws.com/echo.api/echo-api-cert.pem";
+
+    fn fixture(name: &str) -> String {
+        format!("{}{}", FIXTURES, name)
+    }
+
+    fn alexa() -> Alexa {
+        Alexa::new(
+            Some("amzn1.ask.skill.test"),
+            true,
+            Some(&fixture("root.pem")),
+            Some(&fixture("chain.pem")),
+        )
+        .unwrap()
+    }
+
+    fn signed() -> (HttpRequest, Vec<u8>) {
+        let signature = std::fs::read_to_string(fixture("request.sig")).unwrap();
+        let http = TestRequest::default()
+            .header("Signature-256", signature.trim())
+            .header("SignatureCertChainUrl", CHAIN_URL)
+            .to_http_request();
+        (http, std::fs::read(fixture("request.json")).unwrap())
+    }
+
+    fn check(
+        alexa: Alexa,
+        http: HttpRequest,
+        body: Vec<u8>,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        actix_web::rt::System::new("alexa").block_on(async move {
+            alexa.check_signature(&http, &body, now).await?;
+            let envelope: Envelope = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+            alexa.check_envelope(&envelope, now)
+        })
+    }
+
+    fn sent() -> DateTime<Utc> {
+        "2026-11-02T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn accepts_a_signed_request() {
+        let (http, body) = signed();
+        assert_eq!(
+            check(alexa(), http, body, sent() + chrono::Duration::seconds(5)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_a_changed_body() {
+        let (http, body) = signed();
+        let body = String::from_utf8(body)
+            .unwrap()
+            .replace("LaunchRequest", "SessionEndedRequest");
+        let result = check(alexa(), http, body.into_bytes(), sent());
+        assert_eq!(result, Err("request signature does not match".to_string()));
+    }
+
+    #[test]
+    fn rejects_a_stale_timestamp() {
+        let (http, body) = signed();
+        let result = check(
+            alexa(),
+            http,
+            body,
+            sent() + chrono::Duration::seconds(MAX_AGE + 1),
+        );
+        assert_eq!(result, Err("request timestamp is too old".to_string()));
+    }
+
+    #[test]
+    fn rejects_an_untrusted_chain() {
+        let (http, body) = signed();
+        let alexa = Alexa::new(None, true, None, Some(&fixture("chain.pem"))).unwrap();
+        assert!(check(alexa, http, body, sent())
+            .unwrap_err()
+            .starts_with("signing certificate"));
+    }
+}
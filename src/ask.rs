@@ -0,0 +1,601 @@
+use crate::alerts::{Alert, Alerts};
+use crate::negotiate::{self, Reply};
+use crate::planner::{self, Plan};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{
+    self, Departure, Direction, Line, Timetable, MINUTES_PER_DAY, SERVICE_DAY_START, STATIONS,
+};
+use crate::v2::{self, Arrival, StationRef, TripPlan};
+use crate::{overlay, speech};
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, Weekday};
+use schemars::JsonSchema;
+
+// Answers free-text questions such as "next red line to the airport from cwe"
+// with a small fixed grammar: station names and aliases, the words around
+// them, and a handful of keywords for direction, line, intent and time.
+
+// Landmarks riders use instead of station names.
+const LANDMARKS: [(&str, &str); 8] = [
+    ("airport", "lambert"),
+    ("terminal 1", "lambert"),
+    ("terminal 2", "lambert2"),
+    ("the loop", "delmar"),
+    ("busch stadium", "stadium"),
+    ("ballpark", "stadium"),
+    ("scott air force base", "shiloh-scott"),
+    ("scott afb", "shiloh-scott"),
+];
+
+// The longest station phrase, in words.
+const MAX_PHRASE: usize = 4;
+
+// Latest service-day minute a train can run at.
+const END_OF_SERVICE: u32 = MINUTES_PER_DAY + SERVICE_DAY_START - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Intent {
+    Next,
+    First,
+    Last,
+}
+
+impl Intent {
+    fn as_str(self) -> &'static str {
+        match self {
+            Intent::Next => "next",
+            Intent::First => "first",
+            Intent::Last => "last",
+        }
+    }
+}
+
+pub struct Query {
+    pub intent: Intent,
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    pub direction: Option<Direction>,
+    pub line: Option<Line>,
+    // Service-day minutes.
+    pub after: Option<u32>,
+    pub before: Option<u32>,
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AskRequest {
+    text: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Interpretation {
+    intent: String,
+    from: Option<StationRef>,
+    to: Option<StationRef>,
+    direction: Option<String>,
+    line: Option<String>,
+    #[schemars(schema_with = "crate::openapi::time_schema")]
+    after: Option<String>,
+    #[schemars(schema_with = "crate::openapi::time_schema")]
+    before: Option<String>,
+    service_date: NaiveDate,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AskDeparture {
+    #[schemars(schema_with = "crate::openapi::direction_schema")]
+    direction: String,
+    #[serde(flatten)]
+    arrival: Arrival,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AskResponse {
+    query: String,
+    interpretation: Interpretation,
+    answer: String,
+    trip: Option<TripPlan>,
+    departures: Vec<AskDeparture>,
+    alerts: Vec<Alert>,
+}
+
+impl Reply for AskResponse {
+    const NAME: &'static str = "ask";
+
+    fn text(&self) -> String {
+        self.answer.clone()
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut cleaned = String::with_capacity(text.len());
+    for c in text.to_lowercase().chars() {
+        match c {
+            '&' => cleaned.push_str(" and "),
+            '\'' | '\u{2019}' | '.' => {}
+            c if c.is_alphanumeric() || c == ':' => cleaned.push(c),
+            _ => cleaned.push(' '),
+        }
+    }
+    cleaned.split_whitespace().map(|w| w.to_string()).collect()
+}
+
+// Every phrase that names a station: ids, the aliases from the schedule, the
+// official names, and landmarks.
+fn station_phrases() -> Vec<(String, usize)> {
+    let mut phrases = Vec::new();
+    for (i, station) in STATIONS.iter().enumerate() {
+        phrases.push((tokenize(&station.id.replace('-', " ")).join(" "), i));
+        for alias in station.aliases.iter() {
+            phrases.push((tokenize(alias).join(" "), i));
+        }
+        let name = station
+            .name
+            .strip_suffix(" Station")
+            .unwrap_or(station.name);
+        phrases.push((tokenize(name).join(" "), i));
+        phrases.push((tokenize(&speech::spoken_name(i)).join(" "), i));
+    }
+    for (landmark, id) in LANDMARKS.iter() {
+        if let Some(i) = STATIONS.iter().position(|s| s.id == *id) {
+            phrases.push((landmark.to_string(), i));
+        }
+    }
+    phrases
+}
+
+// "10pm", "10:30", "10:30 pm", "noon". Returns minutes after midnight and how
+// many tokens were used; `evening` picks PM for times without AM/PM.
+fn parse_clock(tokens: &[String], evening: bool) -> Option<(u32, usize)> {
+    let first = tokens.first()?;
+    match first.as_str() {
+        "noon" => return Some((12 * 60, 1)),
+        "midnight" => return Some((0, 1)),
+        _ => {}
+    }
+    let digits_end = first
+        .find(|c: char| !(c.is_ascii_digit() || c == ':'))
+        .unwrap_or(first.len());
+    let (clock, suffix) = first.split_at(digits_end);
+    if clock.is_empty() {
+        return None;
+    }
+    let mut parts = clock.splitn(2, ':');
+    let hh: u32 = parts.next()?.parse().ok()?;
+    let mm: u32 = match parts.next() {
+        Some(m) => m.parse().ok()?,
+        None => 0,
+    };
+    if hh > 23 || mm > 59 {
+        return None;
+    }
+    let (meridiem, used) = match suffix {
+        "" => match tokens.get(1).map(|t| t.as_str()) {
+            Some(m @ ("am" | "pm" | "a" | "p")) => (Some(m.starts_with('p')), 2),
+            _ => (None, 1),
+        },
+        "am" | "a" => (Some(false), 1),
+        "pm" | "p" => (Some(true), 1),
+        _ => return None,
+    };
+    let hh = match meridiem {
+        Some(pm) if hh <= 12 => hh % 12 + if pm { 12 } else { 0 },
+        Some(_) => return None,
+        // Bare hours: "at 5" in transit talk usually means the afternoon.
+        None if hh < 12 && (evening || hh < 6) => hh + 12,
+        None => hh,
+    };
+    Some((hh * 60 + mm, used))
+}
+
+fn next_weekday(today: NaiveDate, day: Weekday) -> NaiveDate {
+    let mut date = today;
+    while date.weekday() != day {
+        date += Duration::days(1);
+    }
+    date
+}
+
+pub fn parse(text: &str, today: NaiveDate) -> Query {
+    let tokens = tokenize(text);
+    let phrases = station_phrases();
+    let evening = tokens.iter().any(|t| t == "tonight" || t == "evening");
+    let mut query = Query {
+        intent: Intent::Next,
+        from: None,
+        to: None,
+        direction: None,
+        line: None,
+        after: None,
+        before: None,
+        date: None,
+    };
+    let mut unplaced: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        // Stations first, longest phrase wins.
+        let matched = (1..=MAX_PHRASE.min(tokens.len() - i)).rev().find_map(|n| {
+            let phrase = tokens[i..i + n].join(" ");
+            phrases
+                .iter()
+                .find(|(p, _)| *p == phrase)
+                .map(|(_, s)| (*s, n))
+        });
+        if let Some((station, n)) = matched {
+            let mut before = tokens[..i].iter().rev().skip_while(|t| *t == "the");
+            match before.next().map(|t| t.as_str()) {
+                Some("from" | "at" | "leaving") => query.from = Some(station),
+                Some("to" | "toward" | "towards" | "for" | "into") => query.to = Some(station),
+                _ => unplaced.push(station),
+            }
+            i += n;
+            continue;
+        }
+        let word = tokens[i].as_str();
+        let keyword_time = match word {
+            "after" | "at" | "around" | "from" => Some(false),
+            "before" | "by" | "until" => Some(true),
+            _ => None,
+        };
+        if let Some(is_before) = keyword_time {
+            if let Some((minutes, n)) = parse_clock(&tokens[i + 1..], evening) {
+                let minutes = schedule::service_minutes(minutes);
+                if is_before {
+                    query.before = Some(minutes);
+                } else {
+                    query.after = Some(minutes);
+                }
+                i += 1 + n;
+                continue;
+            }
+        }
+        if let Some((minutes, n)) = parse_clock(&tokens[i..], evening) {
+            // A time on its own, like "trains at cwe 5pm".
+            if tokens[i].contains(':') || n == 2 || !tokens[i].chars().all(|c| c.is_ascii_digit()) {
+                query.after = Some(schedule::service_minutes(minutes));
+                i += n;
+                continue;
+            }
+        }
+        match word {
+            "east" | "eastbound" | "eb" => query.direction = Some(Direction::East),
+            "west" | "westbound" | "wb" => query.direction = Some(Direction::West),
+            "red" => query.line = Some(Line::Red),
+            "blue" => query.line = Some(Line::Blue),
+            "first" | "earliest" => query.intent = Intent::First,
+            "last" | "latest" | "final" => query.intent = Intent::Last,
+            "next" => query.intent = Intent::Next,
+            "today" | "tonight" => query.date = Some(today),
+            "tomorrow" => query.date = Some(today + Duration::days(1)),
+            "monday" => query.date = Some(next_weekday(today, Weekday::Mon)),
+            "tuesday" => query.date = Some(next_weekday(today, Weekday::Tue)),
+            "wednesday" => query.date = Some(next_weekday(today, Weekday::Wed)),
+            "thursday" => query.date = Some(next_weekday(today, Weekday::Thu)),
+            "friday" => query.date = Some(next_weekday(today, Weekday::Fri)),
+            "saturday" => query.date = Some(next_weekday(today, Weekday::Sat)),
+            "sunday" => query.date = Some(next_weekday(today, Weekday::Sun)),
+            _ => {}
+        }
+        i += 1;
+    }
+    // Stations without a preposition fill whichever end is still open.
+    for station in unplaced {
+        if query.from.is_none() {
+            query.from = Some(station);
+        } else if query.to.is_none() {
+            query.to = Some(station);
+        }
+    }
+    query
+}
+
+pub struct Answer {
+    pub text: String,
+    pub date: NaiveDate,
+    pub trip: Option<Plan>,
+    pub departures: Vec<(Direction, Departure)>,
+}
+
+// Which service day and minute to start from. Times past midnight belong to
+// the previous service day, like everywhere else in the schedule.
+fn start(query: &Query, now: NaiveDateTime) -> (NaiveDate, u32) {
+    let (today, minute) = schedule::service_day(now);
+    let date = query.date.unwrap_or(today);
+    let roll = |date: NaiveDate, m: u32| {
+        if m >= MINUTES_PER_DAY && query.date.is_some() {
+            (date - Duration::days(1), m)
+        } else {
+            (date, m)
+        }
+    };
+    match (query.intent, query.after) {
+        (Intent::First, _) => (date, SERVICE_DAY_START),
+        (_, Some(after)) => roll(date, after),
+        _ if query.date.is_some() && query.date != Some(today) => (date, SERVICE_DAY_START),
+        _ => (date, minute),
+    }
+}
+
+pub fn answer(
+    tt: &Timetable,
+    realtime: &Realtime,
+    query: &Query,
+    now: NaiveDateTime,
+) -> Option<Answer> {
+    match (query.from, query.to) {
+        (Some(from), Some(to)) if from != to => Some(answer_trip(tt, query, from, to, now)),
+        (Some(station), _) => Some(answer_station(tt, realtime, query, station, false, now)),
+        (None, Some(station)) => Some(answer_station(tt, realtime, query, station, true, now)),
+        (None, None) => None,
+    }
+}
+
+fn answer_trip(
+    tt: &Timetable,
+    query: &Query,
+    from: usize,
+    to: usize,
+    now: NaiveDateTime,
+) -> Answer {
+    let (date, minute) = start(query, now);
+    if let Some(answer) = closed(tt, &[from, to], date) {
+        return answer;
+    }
+    let plan = match (query.intent, query.before) {
+        (Intent::Last, before) => planner::arrive_by(
+            tt,
+            from,
+            to,
+            date,
+            before.unwrap_or(END_OF_SERVICE),
+            query.line,
+        ),
+        (Intent::Next, Some(before)) => planner::arrive_by(tt, from, to, date, before, query.line),
+        _ => planner::depart_after(tt, from, to, date, minute, query.line),
+    };
+    let text = match &plan {
+        Some(plan) => describe_plan(plan),
+        None => format!(
+            "I couldn't find a train from {} to {} then.",
+            speech::spoken_name(from),
+            speech::spoken_name(to)
+        ),
+    };
+    Answer {
+        text,
+        date: plan.as_ref().map_or(date, |p| p.date),
+        trip: plan,
+        departures: Vec::new(),
+    }
+}
+
+// Says so when an overlay closes one of the stations asked about.
+pub fn closed(tt: &Timetable, stations: &[usize], date: NaiveDate) -> Option<Answer> {
+    let (station, closure) = stations
+        .iter()
+        .find_map(|s| Some((*s, tt.closure(*s, date)?)))?;
+    Some(Answer {
+        text: overlay::closed_message(closure, station),
+        date,
+        trip: None,
+        departures: Vec::new(),
+    })
+}
+
+fn describe_plan(plan: &Plan) -> String {
+    let mut text = String::new();
+    for (i, leg) in plan.legs.iter().enumerate() {
+        let lead = if i == 0 { "Take" } else { " Then change to" };
+        text.push_str(&format!(
+            "{} the {} {} line train from {} toward {}, arriving at {} at {}.",
+            lead,
+            speech::spoken_time(leg.departure),
+            leg.line.as_str(),
+            speech::spoken_name(leg.from),
+            speech::spoken_name(leg.headsign),
+            speech::spoken_name(leg.to),
+            speech::spoken_time(leg.arrival)
+        ));
+    }
+    text
+}
+
+// Trains at one station from the schedule. As a destination ("last train to
+// shiloh") it lists trains arriving there, otherwise trains leaving from it.
+fn scheduled(
+    tt: &Timetable,
+    query: &Query,
+    station: usize,
+    arriving: bool,
+    (date, minute): (NaiveDate, u32),
+    direction: Direction,
+) -> Vec<Departure> {
+    let schedule = tt.schedule(date, direction);
+    let first_stop = |trip: &schedule::Trip| {
+        schedule
+            .order
+            .iter()
+            .copied()
+            .find(|s| trip.times[*s].is_some())
+    };
+    let mut found: Vec<Departure> = schedule
+        .departures(station)
+        .into_iter()
+        .filter(|(_, trip)| query.line.is_none_or(|l| l == trip.line))
+        .filter(|(t, _)| query.before.is_none_or(|b| *t <= b))
+        .filter_map(|(time, trip)| {
+            let terminal = schedule.terminal(trip)?;
+            let useful = if arriving {
+                first_stop(trip) != Some(station)
+            } else {
+                terminal != station
+            };
+            match useful {
+                true => Some(Departure {
+                    date,
+                    time,
+                    line: trip.line,
+                    terminal,
+                    version: tt.version(date).id.clone(),
+                    trip: trip.row,
+                    delay: None,
+                    extra: trip.extra,
+                    event: tt.event_name(trip),
+                    last: schedule.is_last(station, trip),
+                }),
+                false => None,
+            }
+        })
+        .collect();
+    match query.intent {
+        Intent::First => found.into_iter().take(1).collect(),
+        Intent::Last => found.pop().into_iter().collect(),
+        Intent::Next => found
+            .into_iter()
+            .filter(|d| d.time >= minute)
+            .take(2)
+            .collect(),
+    }
+}
+
+fn answer_station(
+    tt: &Timetable,
+    realtime: &Realtime,
+    query: &Query,
+    station: usize,
+    arriving: bool,
+    now: NaiveDateTime,
+) -> Answer {
+    let (date, minute) = start(query, now);
+    if let Some(answer) = closed(tt, &[station], date) {
+        return answer;
+    }
+    // The next trains from here right now are the ones the real-time feed
+    // expects, like everywhere else arrivals are given.
+    let live = query.intent == Intent::Next
+        && !arriving
+        && query.before.is_none()
+        && (date, minute) == schedule::service_day(now);
+    let directions: Vec<Direction> = match query.direction {
+        Some(d) => vec![d],
+        None => Direction::ALL.to_vec(),
+    };
+    let mut departures = Vec::new();
+    let mut sentences = Vec::new();
+    for direction in directions {
+        let found: Vec<Departure> = match live {
+            true => realtime
+                .upcoming(tt, station, direction, now, usize::MAX)
+                .into_iter()
+                .filter(|d| query.line.is_none_or(|l| l == d.line) && d.terminal != station)
+                .take(2)
+                .collect(),
+            false => scheduled(tt, query, station, arriving, (date, minute), direction),
+        };
+        if found.is_empty() {
+            continue;
+        }
+        sentences.push(match (query.intent, arriving) {
+            (Intent::Next, false) => speech::sentence(station, direction, &found, now),
+            (intent, arriving) => {
+                let d = &found[0];
+                format!(
+                    "The {} {}bound {} line train {} {} {} at {}.",
+                    intent.as_str(),
+                    direction.as_str(),
+                    d.line.as_str(),
+                    if arriving { "to" } else { "at" },
+                    speech::spoken_name(station),
+                    if arriving { "arrives" } else { "leaves" },
+                    speech::spoken_time(d.time)
+                )
+            }
+        });
+        departures.extend(found.into_iter().map(|d| (direction, d)));
+    }
+    let text = match sentences.is_empty() {
+        true => format!(
+            "I couldn't find any trains at {} then.",
+            speech::spoken_name(station)
+        ),
+        false => sentences.join(" "),
+    };
+    Answer {
+        text,
+        date,
+        trip: None,
+        departures,
+    }
+}
+
+// Alerts along a planned trip, or else at the stations asked about when the
+// first train leaves.
+pub fn alerts(store: &Alerts, query: &Query, answer: &Answer, now: NaiveDateTime) -> Vec<Alert> {
+    if let Some(plan) = &answer.trip {
+        return store.on_trip(plan);
+    }
+    let stations: Vec<usize> = query.from.into_iter().chain(query.to).collect();
+    let lines: Vec<Line> = query.line.into_iter().collect();
+    let at = answer
+        .departures
+        .first()
+        .map_or(now, |(_, d)| d.datetime().max(now));
+    store.at_stations(&stations, &lines, query.direction, v2::local(at))
+}
+
+#[post("/ask")]
+pub async fn ask(
+    http: HttpRequest,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    store: web::Data<Alerts>,
+    req: web::Json<AskRequest>,
+) -> HttpResponse {
+    let now = Local::now().naive_local();
+    let query = parse(&req.text, now.date());
+    let answer = match answer(&tt, &realtime, &query, now) {
+        Some(a) => a,
+        None => {
+            return v2::error(
+                &http,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "no_station",
+                "I couldn't find a station in that question",
+            )
+        }
+    };
+    let in_effect = alerts(&store, &query, &answer, now);
+    negotiate::reply(
+        &http,
+        StatusCode::OK,
+        &AskResponse {
+            query: req.text.clone(),
+            interpretation: Interpretation {
+                intent: query.intent.as_str().to_string(),
+                from: query.from.map(v2::station_ref),
+                to: query.to.map(v2::station_ref),
+                direction: query.direction.map(|d| d.as_str().to_string()),
+                line: query.line.map(|l| l.as_str().to_string()),
+                after: query.after.map(schedule::format_time),
+                before: query.before.map(schedule::format_time),
+                service_date: answer.date,
+            },
+            answer: answer.text,
+            trip: answer
+                .trip
+                .as_ref()
+                .map(|plan| v2::trip_plan(plan, store.on_trip(plan))),
+            departures: answer
+                .departures
+                .iter()
+                .map(|(direction, d)| AskDeparture {
+                    direction: direction.as_str().to_string(),
+                    arrival: v2::arrival(d, now),
+                })
+                .collect(),
+            alerts: in_effect,
+        },
+    )
+}
@@ -0,0 +1,213 @@
+use crate::alerts::{Alert, Alerts};
+use crate::ask::{self, Answer};
+use crate::realtime::Realtime;
+use crate::schedule::{self, Departure, Direction, Line, Timetable};
+use crate::{speech, v2};
+use chrono::NaiveDateTime;
+
+// Commands shared by the chat integrations. Each platform turns a Response
+// into its own message format.
+
+pub const HELP: &str = "Ask me about MetroLink trains:\n\
+/next cwe west - the next trains at a station\n\
+/board clayton - departures both ways\n\
+/last shiloh - the last trains tonight\n\
+Or just ask, like \"last red line to the airport from cwe\".";
+
+const COMMANDS: [&str; 5] = ["next", "board", "last", "help", "start"];
+
+pub enum Response {
+    Help,
+    // A question back to the user, like which station they meant.
+    Prompt(String),
+    ChooseDirection(usize),
+    // Both come with the service alerts that concern them.
+    Answer(Answer, Vec<Alert>),
+    Board(usize, Vec<(Direction, Vec<Departure>)>, Vec<Alert>),
+}
+
+// MetroLink's published line colors.
+pub fn line_color(line: Line) -> u32 {
+    match line {
+        Line::Red => 0xDA291C,
+        Line::Blue => 0x0072CE,
+    }
+}
+
+// "next cwe west" -> ("next", "cwe west"); anything else is a question.
+pub fn split(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let word = word.trim_start_matches('/');
+    let word = word.split('@').next().unwrap_or(word);
+    match COMMANDS.iter().find(|c| c.eq_ignore_ascii_case(word)) {
+        Some(command) => (command, rest.trim()),
+        None => ("", text),
+    }
+}
+
+pub fn respond(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    command: &str,
+    args: &str,
+    now: NaiveDateTime,
+) -> Response {
+    match command {
+        "start" | "help" => Response::Help,
+        "next" => next(tt, realtime, alerts, args, now),
+        "board" => match station(args, now) {
+            Some(station) => board(tt, realtime, alerts, station, now),
+            None => Response::Prompt("Which station? For example: /board clayton".to_string()),
+        },
+        "last" => ask(tt, realtime, alerts, &format!("last {}", args), now),
+        _ => ask(tt, realtime, alerts, args, now),
+    }
+}
+
+fn station(args: &str, now: NaiveDateTime) -> Option<usize> {
+    let query = ask::parse(args, now.date());
+    query.from.or(query.to)
+}
+
+fn ask(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    text: &str,
+    now: NaiveDateTime,
+) -> Response {
+    let query = ask::parse(text, now.date());
+    match ask::answer(tt, realtime, &query, now) {
+        Some(answer) => {
+            let alerts = ask::alerts(alerts, &query, &answer, now);
+            Response::Answer(answer, alerts)
+        }
+        None => Response::Help,
+    }
+}
+
+// Without a direction, ask rather than guess.
+fn next(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    args: &str,
+    now: NaiveDateTime,
+) -> Response {
+    let query = ask::parse(args, now.date());
+    match (query.from.or(query.to), query.direction) {
+        (None, _) => Response::Prompt("Which station? For example: /next cwe west".to_string()),
+        (Some(station), None) => Response::ChooseDirection(station),
+        (Some(_), Some(_)) => ask(tt, realtime, alerts, &format!("next {}", args), now),
+    }
+}
+
+pub fn board(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    station: usize,
+    now: NaiveDateTime,
+) -> Response {
+    let in_effect = alerts.at_stations(&[station], &[], None, v2::local(now));
+    if let Some(answer) = ask::closed(tt, &[station], schedule::service_day(now).0) {
+        return Response::Answer(answer, in_effect);
+    }
+    let directions = Direction::ALL
+        .iter()
+        .map(|direction| {
+            let departures = realtime
+                .upcoming(tt, station, *direction, now, 4)
+                .into_iter()
+                .filter(|d| d.terminal != station)
+                .take(3)
+                .collect();
+            (*direction, departures)
+        })
+        .collect();
+    Response::Board(station, directions, in_effect)
+}
+
+pub fn bound(direction: Direction) -> &'static str {
+    match direction {
+        Direction::East => "Eastbound",
+        Direction::West => "Westbound",
+    }
+}
+
+// "5:23A blue to Fairview Heights", at the time the train is expected.
+pub fn departure_line(d: &Departure) -> String {
+    format!(
+        "{} {} to {}",
+        schedule::format_time(d.expected_time()),
+        d.line.as_str(),
+        speech::spoken_name(d.terminal)
+    )
+}
+
+// "⚠ Elevator out of service at Central West End"
+pub fn alert_line(alert: &Alert) -> String {
+    format!("\u{26A0} {}", alert.header)
+}
+
+// Plain-text rendering, also used as the fallback text of richer messages.
+impl Response {
+    pub fn text(&self) -> String {
+        match self {
+            Response::Help => HELP.to_string(),
+            Response::Prompt(text) => text.clone(),
+            Response::ChooseDirection(station) => {
+                format!("Which way from {}?", speech::spoken_name(*station))
+            }
+            Response::Answer(answer, alerts) => {
+                let mut text = answer.text.clone();
+                for alert in alerts {
+                    text.push('\n');
+                    text.push_str(&alert_line(alert));
+                }
+                text
+            }
+            Response::Board(station, directions, alerts) => {
+                let mut text = speech::spoken_name(*station);
+                for (direction, departures) in directions {
+                    text.push_str("\n\n");
+                    text.push_str(bound(*direction));
+                    if departures.is_empty() {
+                        text.push_str("\nno more trains today");
+                    }
+                    for d in departures {
+                        text.push('\n');
+                        text.push_str(&departure_line(d));
+                    }
+                }
+                if !alerts.is_empty() {
+                    text.push('\n');
+                }
+                for alert in alerts {
+                    text.push('\n');
+                    text.push_str(&alert_line(alert));
+                }
+                text
+            }
+        }
+    }
+
+    // The line the answer is mostly about, for coloring.
+    pub fn line(&self) -> Option<Line> {
+        match self {
+            Response::Answer(answer, _) => answer
+                .departures
+                .first()
+                .map(|(_, d)| d.line)
+                .or_else(|| answer.trip.as_ref().map(|p| p.legs[0].line)),
+            Response::Board(_, directions, _) => directions
+                .iter()
+                .flat_map(|(_, d)| d.first())
+                .map(|d| d.line)
+                .next(),
+            _ => None,
+        }
+    }
+}
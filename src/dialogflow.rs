@@ -0,0 +1,224 @@
+use crate::alerts::Alerts;
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::Timetable;
+use crate::speech::{self, SpeechResponse};
+use actix_web::http::header;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDateTime};
+use serde_json::{json, Map, Value};
+
+// Dialogflow fulfillment webhooks. Both editions read the "station",
+// "direction" and "line" parameters; when there is no station parameter the
+// whole utterance is parsed instead.
+//
+// Mounted at /dialogflow when --dialogflow-auth is set. Requests must carry
+// those credentials as HTTP basic auth, which both editions can send.
+
+const SLOTS: [&str; 3] = ["station", "direction", "line"];
+
+const REPROMPT: &str =
+    "Which station? You can say something like \"next westbound train at Central West End\".";
+
+pub struct Dialogflow {
+    // "username:password".
+    pub credentials: String,
+}
+
+impl Dialogflow {
+    fn allows(&self, http: &HttpRequest) -> bool {
+        let given = http
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|h| {
+                base64::Engine::decode(&base64::engine::general_purpose::STANDARD, h).ok()
+            })
+            .unwrap_or_default();
+        // Compared without stopping at the first difference.
+        given.len() == self.credentials.len()
+            && given
+                .iter()
+                .zip(self.credentials.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(es).service(cx);
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .reason("invalid Dialogflow credentials")
+        .header(header::WWW_AUTHENTICATE, "Basic realm=\"dialogflow\"")
+        .finish()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EsRequest {
+    query_result: EsQueryResult,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EsQueryResult {
+    #[serde(default)]
+    query_text: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    intent: Option<EsIntent>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EsIntent {
+    #[serde(default)]
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CxRequest {
+    intent_info: Option<CxIntentInfo>,
+    fulfillment_info: Option<CxFulfillmentInfo>,
+    session_info: Option<CxSessionInfo>,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CxIntentInfo {
+    #[serde(default)]
+    display_name: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct CxFulfillmentInfo {
+    #[serde(default)]
+    tag: String,
+}
+
+#[derive(Deserialize)]
+struct CxSessionInfo {
+    #[serde(default)]
+    parameters: Map<String, Value>,
+}
+
+// Parameters arrive as plain strings, lists, or in CX intent info as
+// {"originalValue": ..., "resolvedValue": ...}.
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.trim().is_empty() => Some(s.clone()),
+        Value::Array(items) => items.iter().find_map(value_text),
+        Value::Object(map) => ["resolvedValue", "originalValue", "name"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(value_text)),
+        _ => None,
+    }
+}
+
+fn fulfill(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    intent: &str,
+    parameters: &[&Map<String, Value>],
+    utterance: &str,
+    now: NaiveDateTime,
+) -> Option<SpeechResponse> {
+    let slots: Vec<String> = SLOTS
+        .iter()
+        .filter_map(|slot| {
+            parameters
+                .iter()
+                .find_map(|p| p.get(*slot).and_then(value_text))
+        })
+        .collect();
+    let station_given = parameters
+        .iter()
+        .any(|p| p.get("station").and_then(value_text).is_some());
+    let words: Vec<&str> = match station_given {
+        true => slots.iter().map(String::as_str).collect(),
+        false => vec![utterance],
+    };
+    speech::from_slots(tt, realtime, alerts, intent, &words, now)
+}
+
+#[post("/es")]
+async fn es(
+    http: HttpRequest,
+    dialogflow: web::Data<Dialogflow>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    req: web::Json<EsRequest>,
+) -> HttpResponse {
+    if !dialogflow.allows(&http) {
+        return unauthorized();
+    }
+    let result = &req.query_result;
+    let intent = result
+        .intent
+        .as_ref()
+        .map_or("", |i| i.display_name.as_str());
+    let now = Local::now().naive_local();
+    let text = match fulfill(
+        &tt,
+        &realtime,
+        &alerts,
+        intent,
+        &[&result.parameters],
+        &result.query_text,
+        now,
+    ) {
+        Some(speech) => speech.text,
+        None => REPROMPT.to_string(),
+    };
+    HttpResponse::Ok().json(json!({
+        "fulfillmentText": text,
+        "fulfillmentMessages": [{ "text": { "text": [text] } }],
+    }))
+}
+
+#[post("/cx")]
+async fn cx(
+    http: HttpRequest,
+    dialogflow: web::Data<Dialogflow>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    req: web::Json<CxRequest>,
+) -> HttpResponse {
+    if !dialogflow.allows(&http) {
+        return unauthorized();
+    }
+    // The fulfillment tag names the webhook call; fall back to the intent.
+    let intent = match (&req.fulfillment_info, &req.intent_info) {
+        (Some(f), _) if !f.tag.is_empty() => f.tag.as_str(),
+        (_, Some(i)) => i.display_name.as_str(),
+        _ => "",
+    };
+    let mut parameters = Vec::new();
+    if let Some(info) = &req.intent_info {
+        parameters.push(&info.parameters);
+    }
+    if let Some(session) = &req.session_info {
+        parameters.push(&session.parameters);
+    }
+    let now = Local::now().naive_local();
+    let messages = match fulfill(&tt, &realtime, &alerts, intent, &parameters, &req.text, now) {
+        Some(speech) => json!([
+            { "text": { "text": [speech.text] } },
+            { "outputAudioText": { "ssml": speech.ssml } },
+        ]),
+        None => json!([{ "text": { "text": [REPROMPT] } }]),
+    };
+    HttpResponse::Ok().json(json!({ "fulfillmentResponse": { "messages": messages } }))
+}
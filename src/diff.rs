@@ -0,0 +1,558 @@
+use crate::schedule::{self, Schedule, Timetable, Trip, STATIONS};
+use crate::{gtfs, speech};
+use std::collections::{BTreeMap, HashMap};
+
+// `metro-schedule-api diff OLD NEW` compares two sets of schedules, each a
+// directory of CSVs or an unzipped GTFS feed. Trips are paired up by line,
+// the stations they stop at and their times, never by row, so reordering or
+// inserting rows in a CSV doesn't show up as a change.
+
+// Trips with the same stops that start within this many minutes of each
+// other are taken to be the same train, retimed.
+const MATCH_WINDOW: u32 = 30;
+
+// Retimed trips list this many moved stops before summarizing the rest.
+const SHOWN_STOPS: usize = 3;
+
+#[derive(Serialize)]
+pub struct Diff {
+    old: String,
+    new: String,
+    schedules: Vec<ScheduleDiff>,
+}
+
+#[derive(Serialize)]
+struct ScheduleDiff {
+    day: &'static str,
+    direction: &'static str,
+    added: Vec<TripSummary>,
+    removed: Vec<TripSummary>,
+    retimed: Vec<Retimed>,
+    stations: Vec<StationChange>,
+    headways: Vec<HeadwayChange>,
+}
+
+#[derive(Serialize)]
+struct TripSummary {
+    line: &'static str,
+    from: &'static str,
+    to: &'static str,
+    departs: String,
+    arrives: String,
+}
+
+#[derive(Serialize)]
+struct Retimed {
+    old: TripSummary,
+    new: TripSummary,
+    stops: Vec<StopShift>,
+}
+
+#[derive(Serialize)]
+struct StopShift {
+    station: &'static str,
+    old: String,
+    new: String,
+    minutes: i64,
+}
+
+#[derive(Serialize)]
+struct StationChange {
+    station: &'static str,
+    // Stop times moved on retimed trips, and by how much at most each way.
+    shifted: usize,
+    earliest_shift: Option<i64>,
+    latest_shift: Option<i64>,
+    first_train: Option<Change>,
+    last_train: Option<Change>,
+}
+
+#[derive(Serialize)]
+struct Change {
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HeadwayChange {
+    station: &'static str,
+    hour: String,
+    old_trains: usize,
+    new_trains: usize,
+    old_headway: Option<f64>,
+    new_headway: Option<f64>,
+}
+
+impl ScheduleDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.retimed.is_empty()
+            && self.stations.is_empty()
+            && self.headways.is_empty()
+    }
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.schedules.iter().all(ScheduleDiff::is_empty)
+    }
+}
+
+pub fn load(dir: &str) -> Result<Timetable, String> {
+    if gtfs::is_feed(dir) {
+        gtfs::load(dir)
+    } else {
+        Timetable::load(Some(dir))
+    }
+}
+
+// The stations a trip stops at, in travel order, with their times.
+fn stops(schedule: &Schedule, trip: &Trip) -> Vec<(usize, u32)> {
+    schedule
+        .order
+        .iter()
+        .filter_map(|s| trip.times[*s].map(|t| (*s, t)))
+        .collect()
+}
+
+fn summary(schedule: &Schedule, trip: &Trip) -> TripSummary {
+    let stops = stops(schedule, trip);
+    let (first, last) = (stops[0], stops[stops.len() - 1]);
+    TripSummary {
+        line: trip.line.as_str(),
+        from: STATIONS[first.0].id,
+        to: STATIONS[last.0].id,
+        departs: schedule::format_time(first.1),
+        arrives: schedule::format_time(last.1),
+    }
+}
+
+struct Pairing<'a> {
+    pairs: Vec<(&'a Trip, &'a Trip)>,
+    removed: Vec<&'a Trip>,
+    added: Vec<&'a Trip>,
+}
+
+// Pairs old trips with new ones. Identical trips pair first, then the closest
+// starts with the same line and stops, within MATCH_WINDOW.
+fn pair<'a>(old: &'a Schedule, new: &'a Schedule) -> Pairing<'a> {
+    let mut groups: HashMap<_, (Vec<&Trip>, Vec<&Trip>)> = HashMap::new();
+    for trip in old.trips.iter() {
+        let pattern: Vec<usize> = stops(old, trip).iter().map(|(s, _)| *s).collect();
+        groups.entry((trip.line, pattern)).or_default().0.push(trip);
+    }
+    for trip in new.trips.iter() {
+        let pattern: Vec<usize> = stops(new, trip).iter().map(|(s, _)| *s).collect();
+        groups.entry((trip.line, pattern)).or_default().1.push(trip);
+    }
+    let (mut pairs, mut removed, mut added) = (Vec::new(), Vec::new(), Vec::new());
+    for ((_, pattern), (olds, news)) in groups {
+        let mut old_used = vec![false; olds.len()];
+        let mut new_used = vec![false; news.len()];
+        for (i, o) in olds.iter().enumerate() {
+            if let Some(j) = (0..news.len()).find(|j| !new_used[*j] && news[*j].times == o.times) {
+                old_used[i] = true;
+                new_used[j] = true;
+            }
+        }
+        let start = |trip: &Trip| pattern.first().and_then(|s| trip.times[*s]).unwrap_or(0);
+        let mut candidates = Vec::new();
+        for (i, o) in olds.iter().enumerate().filter(|(i, _)| !old_used[*i]) {
+            for (j, n) in news.iter().enumerate().filter(|(j, _)| !new_used[*j]) {
+                let apart = (start(o) as i64 - start(n) as i64).unsigned_abs() as u32;
+                if apart <= MATCH_WINDOW {
+                    candidates.push((apart, i, j));
+                }
+            }
+        }
+        candidates.sort_unstable();
+        for (_, i, j) in candidates {
+            if !old_used[i] && !new_used[j] {
+                old_used[i] = true;
+                new_used[j] = true;
+                pairs.push((olds[i], news[j]));
+            }
+        }
+        removed.extend(
+            olds.iter()
+                .zip(old_used)
+                .filter(|(_, used)| !used)
+                .map(|(t, _)| *t),
+        );
+        added.extend(
+            news.iter()
+                .zip(new_used)
+                .filter(|(_, used)| !used)
+                .map(|(t, _)| *t),
+        );
+    }
+    Pairing {
+        pairs,
+        removed,
+        added,
+    }
+}
+
+fn headway(trains: usize) -> Option<f64> {
+    if trains == 0 {
+        None
+    } else {
+        Some((600.0 / trains as f64).round() / 10.0)
+    }
+}
+
+fn compare(old: &Schedule, new: &Schedule) -> ScheduleDiff {
+    let Pairing {
+        pairs,
+        mut removed,
+        mut added,
+    } = pair(old, new);
+    let departs = |schedule: &Schedule, trip: &Trip| stops(schedule, trip)[0].1;
+
+    let mut shifts: HashMap<usize, Vec<i64>> = HashMap::new();
+    let mut retimed = Vec::new();
+    let mut pairs: Vec<_> = pairs
+        .into_iter()
+        .filter(|(o, n)| o.times != n.times)
+        .collect();
+    pairs.sort_by_key(|(o, _)| departs(old, o));
+    for (o, n) in pairs {
+        let moved: Vec<StopShift> = stops(old, o)
+            .into_iter()
+            .filter_map(|(station, before)| {
+                let after = n.times[station]?;
+                let minutes = after as i64 - before as i64;
+                if minutes == 0 {
+                    return None;
+                }
+                shifts.entry(station).or_default().push(minutes);
+                Some(StopShift {
+                    station: STATIONS[station].id,
+                    old: schedule::format_time(before),
+                    new: schedule::format_time(after),
+                    minutes,
+                })
+            })
+            .collect();
+        retimed.push(Retimed {
+            old: summary(old, o),
+            new: summary(new, n),
+            stops: moved,
+        });
+    }
+    removed.sort_by_key(|t| departs(old, t));
+    added.sort_by_key(|t| departs(new, t));
+
+    let mut stations = Vec::new();
+    let mut headways = Vec::new();
+    for station in new.order.iter().copied() {
+        let times = |schedule: &Schedule| {
+            let mut times: Vec<u32> = schedule
+                .trips
+                .iter()
+                .filter_map(|t| t.times[station])
+                .collect();
+            times.sort_unstable();
+            times
+        };
+        let (before, after) = (times(old), times(new));
+        let change = |a: Option<&u32>, b: Option<&u32>| {
+            if a == b {
+                None
+            } else {
+                Some(Change {
+                    old: a.map(|t| schedule::format_time(*t)),
+                    new: b.map(|t| schedule::format_time(*t)),
+                })
+            }
+        };
+        let first_train = change(before.first(), after.first());
+        let last_train = change(before.last(), after.last());
+        let moved = shifts.remove(&station).unwrap_or_default();
+        if first_train.is_some() || last_train.is_some() || !moved.is_empty() {
+            stations.push(StationChange {
+                station: STATIONS[station].id,
+                shifted: moved.len(),
+                earliest_shift: moved.iter().min().copied(),
+                latest_shift: moved.iter().max().copied(),
+                first_train,
+                last_train,
+            });
+        }
+
+        let mut hours: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+        for t in before.iter() {
+            hours.entry(t / 60).or_default().0 += 1;
+        }
+        for t in after.iter() {
+            hours.entry(t / 60).or_default().1 += 1;
+        }
+        for (hour, (old_trains, new_trains)) in hours {
+            if old_trains != new_trains {
+                headways.push(HeadwayChange {
+                    station: STATIONS[station].id,
+                    hour: schedule::format_time(hour * 60),
+                    old_trains,
+                    new_trains,
+                    old_headway: headway(old_trains),
+                    new_headway: headway(new_trains),
+                });
+            }
+        }
+    }
+
+    ScheduleDiff {
+        day: new.day.as_str(),
+        direction: new.direction.as_str(),
+        added: added.into_iter().map(|t| summary(new, t)).collect(),
+        removed: removed.into_iter().map(|t| summary(old, t)).collect(),
+        retimed,
+        stations,
+        headways,
+    }
+}
+
+pub fn diff(old: &str, new: &str) -> Result<Diff, String> {
+    let (before, after) = (load(old)?, load(new)?);
+    let schedules = after
+        .base()
+        .schedules
+        .iter()
+        .map(|n| compare(before.base().schedule(n.day, n.direction), n))
+        .collect();
+    Ok(Diff {
+        old: old.to_string(),
+        new: new.to_string(),
+        schedules,
+    })
+}
+
+fn name(id: &str) -> String {
+    schedule::find_station(id)
+        .map(speech::spoken_name)
+        .unwrap_or_else(|| id.to_string())
+}
+
+fn trip_line(trip: &TripSummary) -> String {
+    format!(
+        "{} {} {} -> {} {}",
+        trip.line,
+        name(trip.from),
+        trip.departs,
+        name(trip.to),
+        trip.arrives
+    )
+}
+
+fn every(headway: Option<f64>) -> String {
+    headway.map_or("none".to_string(), |h| format!("{} min", h))
+}
+
+pub fn print(diff: &Diff) {
+    println!("--- {}\n+++ {}", diff.old, diff.new);
+    for s in diff.schedules.iter().filter(|s| !s.is_empty()) {
+        println!(
+            "\n{} {}bound: {} added, {} removed, {} retimed",
+            s.day,
+            s.direction,
+            s.added.len(),
+            s.removed.len(),
+            s.retimed.len()
+        );
+        for trip in s.added.iter() {
+            println!("  + {}", trip_line(trip));
+        }
+        for trip in s.removed.iter() {
+            println!("  - {}", trip_line(trip));
+        }
+        for trip in s.retimed.iter() {
+            let mut moved: Vec<String> = trip
+                .stops
+                .iter()
+                .take(SHOWN_STOPS)
+                .map(|stop| {
+                    format!(
+                        "{} {} -> {} ({:+})",
+                        name(stop.station),
+                        stop.old,
+                        stop.new,
+                        stop.minutes
+                    )
+                })
+                .collect();
+            if trip.stops.len() > SHOWN_STOPS {
+                moved.push(format!("{} more", trip.stops.len() - SHOWN_STOPS));
+            }
+            println!("  ~ {}: {}", trip_line(&trip.old), moved.join(", "));
+        }
+        if !s.stations.is_empty() {
+            println!("  stations:");
+        }
+        for station in s.stations.iter() {
+            let mut notes = Vec::new();
+            match (station.earliest_shift, station.latest_shift) {
+                (Some(earliest), Some(latest)) if earliest == latest => {
+                    notes.push(format!("{} moved {:+} min", station.shifted, earliest))
+                }
+                (Some(earliest), Some(latest)) => notes.push(format!(
+                    "{} moved {:+} to {:+} min",
+                    station.shifted, earliest, latest
+                )),
+                _ => {}
+            }
+            for (label, change) in [
+                ("first train", &station.first_train),
+                ("last train", &station.last_train),
+            ] {
+                if let Some(change) = change {
+                    let show = |t: &Option<String>| t.clone().unwrap_or_else(|| "none".to_string());
+                    notes.push(format!(
+                        "{} {} -> {}",
+                        label,
+                        show(&change.old),
+                        show(&change.new)
+                    ));
+                }
+            }
+            println!("    {}: {}", name(station.station), notes.join("; "));
+        }
+        // The same change usually shows at every station along the line, so
+        // group stations that changed alike.
+        let mut hours: BTreeMap<(u32, usize, usize), Vec<&str>> = BTreeMap::new();
+        for h in s.headways.iter() {
+            let hour = schedule::parse_time(&h.hour).map_or(0, schedule::service_minutes);
+            hours
+                .entry((hour, h.old_trains, h.new_trains))
+                .or_default()
+                .push(h.station);
+        }
+        if !hours.is_empty() {
+            println!("  headways:");
+        }
+        for ((hour, old_trains, new_trains), stations) in hours {
+            let at = if stations.len() <= SHOWN_STOPS {
+                stations
+                    .iter()
+                    .map(|s| name(s))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            } else {
+                format!("{} stations", stations.len())
+            };
+            println!(
+                "    {} hour: {} -> {} trains, every {} -> {}, at {}",
+                schedule::format_time(hour),
+                old_trains,
+                new_trains,
+                every(headway(old_trains)),
+                every(headway(new_trains)),
+                at
+            );
+        }
+    }
+    if diff.is_empty() {
+        println!("no differences");
+    }
+}
+
+// Exits like diff(1): 0 when the same, 1 when different, 2 on trouble.
+pub fn run(old: &str, new: &str, json: bool) -> i32 {
+    match diff(old, new) {
+        Ok(d) => {
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&d).expect("diff serializes")
+                );
+            } else {
+                print(&d);
+            }
+            if d.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three stations and a train or two each way. The new weekday eastbound
+    // file retimes the 7:00A by four minutes, drops the 8:00A blue train,
+    // adds a 9:30A red one and lists its rows in a different order.
+    const OLD: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/diff/old");
+    const NEW: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/diff/new");
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run(OLD, OLD, false), 0);
+        assert_eq!(run(OLD, NEW, false), 1);
+        assert_eq!(run(OLD, NEW, true), 1);
+        let missing = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/diff/missing");
+        assert_eq!(run(OLD, missing, false), 2);
+    }
+
+    #[test]
+    fn reports_added_removed_and_retimed_trips() {
+        let d = diff(OLD, NEW).unwrap();
+        let changed: Vec<&ScheduleDiff> = d.schedules.iter().filter(|s| !s.is_empty()).collect();
+        assert_eq!(changed.len(), 1);
+        let s = changed[0];
+        assert_eq!((s.day, s.direction), ("weekday", "east"));
+
+        let trip = |t: &TripSummary| (t.line, t.from, t.departs.clone(), t.to, t.arrives.clone());
+        let added: Vec<_> = s.added.iter().map(trip).collect();
+        assert_eq!(
+            added,
+            [(
+                "red",
+                "cwe",
+                "9:30A".to_string(),
+                "grand",
+                "9:35A".to_string()
+            )]
+        );
+        let removed: Vec<_> = s.removed.iter().map(trip).collect();
+        assert_eq!(
+            removed,
+            [(
+                "blue",
+                "cwe",
+                "8:00A".to_string(),
+                "grand",
+                "8:05A".to_string()
+            )]
+        );
+
+        assert_eq!(s.retimed.len(), 1);
+        let retimed = &s.retimed[0];
+        assert_eq!(retimed.old.departs, "7:00A");
+        assert_eq!(retimed.new.departs, "7:04A");
+        let moved: Vec<(&str, i64)> = retimed
+            .stops
+            .iter()
+            .map(|m| (m.station, m.minutes))
+            .collect();
+        assert_eq!(moved, [("cwe", 4), ("cortex", 4), ("grand", 4)]);
+
+        let grand = s.stations.iter().find(|c| c.station == "grand").unwrap();
+        assert_eq!(
+            (grand.shifted, grand.earliest_shift, grand.latest_shift),
+            (1, Some(4), Some(4))
+        );
+        let last = grand.last_train.as_ref().unwrap();
+        assert_eq!(
+            (last.old.as_deref(), last.new.as_deref()),
+            (Some("8:05A"), Some("9:35A"))
+        );
+        assert!(grand.first_train.is_none());
+    }
+}
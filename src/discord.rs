@@ -0,0 +1,206 @@
+use crate::alerts::Alerts;
+use crate::chat::{self, Response};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{Line, STATIONS};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Local;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde_json::{json, Value};
+use std::convert::TryInto;
+
+// Discord interactions endpoint. Register a /metro command with a "text"
+// option, or /next, /board and /last with whatever options you like; their
+// values are read in order as "station direction".
+
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE: u8 = 4;
+const EPHEMERAL: u32 = 1 << 6;
+
+pub struct Discord {
+    pub public_key: VerifyingKey,
+}
+
+impl Discord {
+    pub fn from_hex(public_key: &str) -> Result<Discord, String> {
+        let bytes: [u8; 32] = hex::decode(public_key.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or("Discord public key must be 64 hex digits")?;
+        let public_key = VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        Ok(Discord { public_key })
+    }
+
+    // https://discord.com/developers/docs/interactions/overview#setting-up-an-endpoint-validating-security-request-headers
+    fn verify(&self, timestamp: &str, signature: &str, body: &[u8]) -> bool {
+        let signature = match hex::decode(signature)
+            .ok()
+            .and_then(|b| Signature::from_slice(&b).ok())
+        {
+            Some(s) => s,
+            None => return false,
+        };
+        let mut message = timestamp.as_bytes().to_vec();
+        message.extend_from_slice(body);
+        self.public_key.verify_strict(&message, &signature).is_ok()
+    }
+}
+
+#[derive(Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    data: Option<CommandData>,
+}
+
+#[derive(Deserialize)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Deserialize)]
+struct CommandOption {
+    value: Option<Value>,
+}
+
+fn header<'a>(http: &'a HttpRequest, name: &str) -> &'a str {
+    http.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+}
+
+fn color(line: Option<Line>) -> u32 {
+    line.map_or(0x808080, chat::line_color)
+}
+
+fn message(response: &Response) -> Value {
+    let data = match response {
+        Response::Board(station, directions, alerts) => {
+            let mut fields: Vec<Value> = directions
+                .iter()
+                .map(|(direction, departures)| {
+                    let lines: Vec<String> = departures.iter().map(chat::departure_line).collect();
+                    let value = match lines.is_empty() {
+                        true => "no more trains today".to_string(),
+                        false => lines.join("\n"),
+                    };
+                    json!({ "name": chat::bound(*direction), "value": value, "inline": true })
+                })
+                .collect();
+            if !alerts.is_empty() {
+                let lines: Vec<String> = alerts.iter().map(chat::alert_line).collect();
+                fields
+                    .push(json!({ "name": "Alerts", "value": lines.join("\n"), "inline": false }));
+            }
+            json!({
+                "embeds": [{
+                    "title": STATIONS[*station].name,
+                    "color": color(response.line()),
+                    "fields": fields,
+                }],
+            })
+        }
+        Response::Answer(..) => json!({
+            "embeds": [{ "description": response.text(), "color": color(response.line()) }],
+        }),
+        _ => json!({ "content": response.text(), "flags": EPHEMERAL }),
+    };
+    json!({ "type": CHANNEL_MESSAGE, "data": data })
+}
+
+pub async fn interactions(
+    http: HttpRequest,
+    discord: web::Data<Discord>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let signed = discord.verify(
+        header(&http, "X-Signature-Timestamp"),
+        header(&http, "X-Signature-Ed25519"),
+        &body,
+    );
+    if !signed {
+        return HttpResponse::Unauthorized()
+            .reason("invalid request signature")
+            .finish();
+    }
+    let interaction: Interaction = match serde_json::from_slice(&body) {
+        Ok(i) => i,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .reason("not an interaction")
+                .finish()
+        }
+    };
+    let data = match (interaction.kind, interaction.data) {
+        (PING, _) => return HttpResponse::Ok().json(json!({ "type": PONG })),
+        (APPLICATION_COMMAND, Some(data)) => data,
+        _ => {
+            return HttpResponse::BadRequest()
+                .reason("unsupported interaction type")
+                .finish()
+        }
+    };
+    let text = data
+        .options
+        .iter()
+        .filter_map(|o| match &o.value {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(v) => Some(v.to_string()),
+            None => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let now = Local::now().naive_local();
+    let (command, args) = match chat::split(&data.name) {
+        ("", _) => chat::split(&text),
+        (command, _) => (command, text.as_str()),
+    };
+    let response = match chat::respond(&tt, &realtime, &alerts, command, args, now) {
+        Response::ChooseDirection(station) => chat::board(&tt, &realtime, &alerts, station, now),
+        response => response,
+    };
+    HttpResponse::Ok().json(message(&response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Discord doesn't publish an example request, so these use TEST 2 from
+    // RFC 8032, whose message is the single byte "r".
+    const PUBLIC_KEY: &str = "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c";
+    const SIGNATURE: &str = "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+        085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00";
+
+    #[test]
+    fn accepts_the_rfc_8032_vector() {
+        let discord = Discord::from_hex(PUBLIC_KEY).unwrap();
+        assert!(discord.verify("", SIGNATURE, b"r"));
+        // The timestamp is signed along with the body.
+        assert!(discord.verify("r", SIGNATURE, b""));
+    }
+
+    #[test]
+    fn rejects_a_changed_message_or_signature() {
+        let discord = Discord::from_hex(PUBLIC_KEY).unwrap();
+        assert!(!discord.verify("", SIGNATURE, b"s"));
+        assert!(!discord.verify("1", SIGNATURE, b"r"));
+        let flipped = format!("93{}", &SIGNATURE[2..]);
+        assert!(!discord.verify("", &flipped, b"r"));
+        assert!(!discord.verify("", "not hex", b"r"));
+    }
+
+    #[test]
+    fn public_key_must_be_32_bytes() {
+        assert!(Discord::from_hex(&PUBLIC_KEY[2..]).is_err());
+        assert!(Discord::from_hex("zz").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::negotiate::{self, Reply};
+use crate::overlay::ExtraTrip;
+use crate::reload::Current;
+use crate::schedule::{self, DayType, Direction, Line, Timetable, Trip, Version, STATIONS};
+use crate::v2::{self, StationRef};
+use actix_web::http::StatusCode;
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDate};
+use schemars::JsonSchema;
+
+// Extra trains for ballgames and concerts, from the JSON file given with
+// --events-file. Each event adds its trains to the timetable for its service
+// date, either as whole trips or as a train every few minutes past the venue
+// for a while. Those trains show up in v2 arrivals as extra service.
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Event {
+    pub id: String,
+    pub name: String,
+    // The service date; trains after midnight still count towards it.
+    pub date: NaiveDate,
+    pub venue: String,
+    // Written like an overlay's, but without days.
+    #[serde(default)]
+    pub extra_trips: Vec<ExtraTrip>,
+    #[serde(default)]
+    pub headways: Vec<Headway>,
+}
+
+// A train every `every` minutes leaving the venue from `from` to `until`,
+// both inclusive.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Headway {
+    pub direction: String,
+    pub line: String,
+    #[schemars(schema_with = "crate::openapi::time_schema")]
+    pub from: String,
+    #[schemars(schema_with = "crate::openapi::time_schema")]
+    pub until: String,
+    pub every: u32,
+}
+
+impl Headway {
+    // The window in service-day minutes.
+    fn window(&self) -> Option<(u32, u32)> {
+        let from = schedule::service_minutes(schedule::parse_time(&self.from)?);
+        let until = schedule::service_minutes(schedule::parse_time(&self.until)?);
+        Some((from, until))
+    }
+}
+
+impl Event {
+    // The venue becomes a station id, and anything unknown is refused.
+    fn check(&mut self) -> Result<(), String> {
+        if self.id.is_empty() || self.name.is_empty() {
+            return Err("id and name are required".to_string());
+        }
+        let venue = schedule::find_station(&self.venue)
+            .ok_or_else(|| format!("unknown station '{}'", self.venue))?;
+        self.venue = STATIONS[venue].id.to_string();
+        if self.extra_trips.is_empty() && self.headways.is_empty() {
+            return Err("an event needs extra_trips or headways".to_string());
+        }
+        for extra in self.extra_trips.iter_mut() {
+            if !extra.days.is_empty() {
+                return Err(
+                    "an event's extra trips run on its date, so they take no days".to_string(),
+                );
+            }
+            extra.check()?;
+        }
+        for headway in self.headways.iter() {
+            if Direction::parse(&headway.direction).is_none() {
+                return Err(format!(
+                    "direction must be 'east' or 'west', not '{}'",
+                    headway.direction
+                ));
+            }
+            if Line::parse(&headway.line).is_none() {
+                return Err(format!(
+                    "line must be 'red' or 'blue', not '{}'",
+                    headway.line
+                ));
+            }
+            match headway.window() {
+                Some((from, until)) if from <= until => {}
+                Some(_) => return Err(format!("{} is before {}", headway.until, headway.from)),
+                None => return Err(format!("bad time in {} to {}", headway.from, headway.until)),
+            }
+            if headway.every == 0 {
+                return Err("every must be at least a minute".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn venue(&self) -> usize {
+        schedule::find_station(&self.venue).expect("checked on load")
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<Event>, String> {
+    let contents = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut events: Vec<Event> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    for i in 0..events.len() {
+        if events[..i].iter().any(|e| e.id == events[i].id) {
+            return Err(format!(
+                "{}: event '{}' is listed twice",
+                path, events[i].id
+            ));
+        }
+        let event = &mut events[i];
+        event
+            .check()
+            .map_err(|e| format!("{}: event '{}': {}", path, event.id, e))?;
+    }
+    Ok(events)
+}
+
+// Adds an event's trains to the schedules for its day type. Headway trains
+// copy the running times of the regular train of that line closest in time
+// at the venue, so they stop where it stops.
+pub fn apply_one(version: &mut Version, event: &Event) -> Result<(), String> {
+    let day = DayType::for_date(event.date);
+    let venue = event.venue();
+    for schedule in version.schedules.iter_mut().filter(|s| s.day == day) {
+        let direction = schedule.direction;
+        let mut added = Vec::new();
+        for extra in event.extra_trips.iter().filter(|e| e.runs(day, direction)) {
+            added.push(extra.trip(schedule, 0, Some(&event.id))?);
+        }
+        for headway in event
+            .headways
+            .iter()
+            .filter(|h| Direction::parse(&h.direction) == Some(direction))
+        {
+            let line = Line::parse(&headway.line).expect("checked on load");
+            let (from, until) = headway.window().expect("checked on load");
+            let templates: Vec<&Trip> = schedule
+                .trips
+                .iter()
+                .filter(|t| t.line == line && !t.extra && t.times[venue].is_some())
+                .collect();
+            for at in (from..=until).step_by(headway.every as usize) {
+                let template = templates
+                    .iter()
+                    .min_by_key(|t| (t.times[venue].unwrap_or_default() as i64 - at as i64).abs())
+                    .ok_or_else(|| {
+                        let (line, direction) = (line.as_str(), direction.as_str());
+                        format!(
+                            "no {} line train stops at {} {}bound to copy",
+                            line, event.venue, direction
+                        )
+                    })?;
+                let shift = at as i64 - template.times[venue].unwrap_or_default() as i64;
+                added.push(Trip {
+                    line,
+                    row: 0,
+                    times: template
+                        .times
+                        .iter()
+                        .map(|t| t.map(|t| (t as i64 + shift).max(0) as u32))
+                        .collect(),
+                    extra: true,
+                    event: Some(event.id.clone()),
+                });
+            }
+        }
+        let last = schedule.trips.iter().map(|t| t.row).max().unwrap_or(1);
+        for (row, mut trip) in (last + 1..).zip(added) {
+            trip.row = row;
+            schedule.trips.push(trip);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UpcomingEvent {
+    id: String,
+    name: String,
+    date: NaiveDate,
+    venue: StationRef,
+    // Trains the event adds, in both directions.
+    extra_trains: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct EventsResponse {
+    events: Vec<UpcomingEvent>,
+}
+
+impl Reply for EventsResponse {
+    const NAME: &'static str = "events";
+
+    fn text(&self) -> String {
+        match self.events.is_empty() {
+            true => "no upcoming events".to_string(),
+            false => self
+                .events
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{} {} at {} ({} extra trains)",
+                        e.date, e.name, e.venue.name, e.extra_trains
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("; "),
+        }
+    }
+}
+
+// Counted by id, as names like "Cardinals game" repeat.
+fn extra_trains(tt: &Timetable, event: &Event) -> usize {
+    tt.version(event.date)
+        .schedules
+        .iter()
+        .flat_map(|s| s.trips.iter())
+        .filter(|t| t.event.as_deref() == Some(event.id.as_str()))
+        .count()
+}
+
+// Events from today's service date on, soonest first.
+#[get("/events")]
+pub async fn list(http: HttpRequest, tt: Current) -> HttpResponse {
+    let (today, _) = schedule::service_day(Local::now().naive_local());
+    let mut upcoming: Vec<&Event> = tt.events.iter().filter(|e| e.date >= today).collect();
+    upcoming.sort_by_key(|e| e.date);
+    let events = upcoming
+        .into_iter()
+        .map(|e| UpcomingEvent {
+            id: e.id.clone(),
+            name: e.name.clone(),
+            date: e.date,
+            venue: v2::station_ref(e.venue()),
+            extra_trains: extra_trains(&tt, e),
+        })
+        .collect();
+    negotiate::reply(&http, StatusCode::OK, &EventsResponse { events })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay;
+
+    fn event(value: serde_json::Value) -> Event {
+        let mut event: Event = serde_json::from_value(value).unwrap();
+        event.check().unwrap();
+        event
+    }
+
+    // A day game and a night game on the same date under the same name.
+    #[test]
+    fn counts_trains_by_event_id() {
+        let day = event(serde_json::json!({
+            "id": "cardinals-day",
+            "name": "Cardinals game",
+            "date": "2026-10-20",
+            "venue": "stadium",
+            "extra_trips": [{ "direction": "east", "line": "red", "times": { "stadium": "4:40P", "8th-pine": "4:42P" } }],
+        }));
+        let night = event(serde_json::json!({
+            "id": "cardinals-night",
+            "name": "Cardinals game",
+            "date": "2026-10-20",
+            "venue": "stadium",
+            "headways": [{ "direction": "west", "line": "blue", "from": "10:00P", "until": "10:20P", "every": 10 }],
+        }));
+        let tt =
+            overlay::apply(Timetable::embedded().unwrap(), Vec::new(), vec![day, night]).unwrap();
+        assert_eq!(extra_trains(&tt, &tt.events[0]), 1);
+        assert_eq!(extra_trains(&tt, &tt.events[1]), 3);
+
+        let stadium = schedule::find_station("stadium").unwrap();
+        let at = schedule::service_datetime(tt.events[0].date, 16 * 60 + 39);
+        let extra = tt
+            .upcoming(stadium, Direction::East, at, 5)
+            .into_iter()
+            .find(|d| d.extra)
+            .unwrap();
+        assert_eq!(extra.event.as_deref(), Some("Cardinals game"));
+    }
+}
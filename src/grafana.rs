@@ -0,0 +1,262 @@
+use crate::alerts::{Alert, Alerts};
+use crate::reload::Current;
+use crate::schedule::{self, Departure, Direction, Timetable, SERVICE_DAY_START, STATIONS};
+use crate::{negotiate, speech, v2};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use serde_json::{json, Value};
+
+// Grafana "JSON API" / SimpleJSON datasource, mounted at /grafana. Targets
+// name a series and a platform, e.g. "departures cwe west" or
+// "headway cwe west".
+
+const KINDS: [&str; 2] = ["departures", "headway"];
+
+#[derive(Deserialize)]
+pub struct Range {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+impl Range {
+    fn local(&self) -> (NaiveDateTime, NaiveDateTime) {
+        let local = |t: &DateTime<Utc>| t.with_timezone(&chrono::Local).naive_local();
+        (local(&self.from), local(&self.to))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    range: Range,
+    #[serde(default)]
+    targets: Vec<Target>,
+    max_data_points: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    #[serde(default)]
+    target: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AnnotationRequest {
+    range: Range,
+    annotation: Value,
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(health)
+        .service(search)
+        .service(query)
+        .service(annotations);
+}
+
+// "headway cwe west" -> ("headway", station, direction)
+fn parse_target(target: &str) -> Option<(&str, usize, Direction)> {
+    let words: Vec<&str> = target.split_whitespace().collect();
+    let (kind, rest) = words.split_first()?;
+    let (direction, station) = rest.split_last()?;
+    let kind = KINDS.iter().find(|k| *k == kind)?;
+    Some((
+        kind,
+        schedule::find_station(&station.join(" "))?,
+        Direction::parse(direction)?,
+    ))
+}
+
+fn millis(d: &Departure) -> i64 {
+    v2::local(d.datetime()).timestamp_millis()
+}
+
+fn series(tt: &Timetable, target: &Target, range: &Range, limit: usize) -> Option<Value> {
+    let (kind, station, direction) = parse_target(&target.target)?;
+    let (from, to) = range.local();
+    // Headways need the train before the range starts too.
+    let departures: Vec<Departure> = tt
+        .between(station, direction, from - Duration::hours(3), to)
+        .into_iter()
+        .filter(|d| d.terminal != station)
+        .collect();
+    let in_range = |d: &&Departure| d.datetime() >= from;
+    let table = target.kind.as_deref() == Some("table");
+    let value = match (kind, table) {
+        ("departures", true) => json!({
+            "type": "table",
+            "columns": [
+                { "text": "Time", "type": "time" },
+                { "text": "Scheduled", "type": "string" },
+                { "text": "Line", "type": "string" },
+                { "text": "Headsign", "type": "string" },
+            ],
+            "rows": departures
+                .iter()
+                .filter(in_range)
+                .take(limit)
+                .map(|d| json!([millis(d), schedule::format_time(d.time), d.line.as_str(), STATIONS[d.terminal].name]))
+                .collect::<Vec<_>>(),
+        }),
+        // One point per train, for bars or points on a time axis.
+        ("departures", false) => json!({
+            "target": target.target,
+            "datapoints": departures.iter().filter(in_range).take(limit).map(|d| json!([1, millis(d)])).collect::<Vec<_>>(),
+        }),
+        // Minutes since the train before, plotted at each departure.
+        _ => json!({
+            "target": target.target,
+            "datapoints": departures
+                .windows(2)
+                .filter(|w| in_range(&&w[1]))
+                .take(limit)
+                .map(|w| json!([(w[1].datetime() - w[0].datetime()).num_minutes(), millis(&w[1])]))
+                .collect::<Vec<_>>(),
+        }),
+    };
+    Some(value)
+}
+
+// Grafana's "Save & test" only needs a 200 here.
+#[get("/")]
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[post("/search")]
+async fn search(http: HttpRequest, req: web::Json<SearchRequest>) -> HttpResponse {
+    let filter = req.target.to_lowercase();
+    let mut targets = Vec::new();
+    for kind in KINDS.iter() {
+        for station in STATIONS.iter() {
+            for direction in Direction::ALL.iter() {
+                let target = format!("{} {} {}", kind, station.id, direction.as_str());
+                if target.contains(&filter) {
+                    targets.push(target);
+                }
+            }
+        }
+    }
+    negotiate::json(&http, StatusCode::OK, &targets)
+}
+
+#[post("/query")]
+async fn query(http: HttpRequest, tt: Current, req: web::Json<QueryRequest>) -> HttpResponse {
+    let limit = req.max_data_points.unwrap_or(usize::MAX);
+    let results: Vec<Value> = req
+        .targets
+        .iter()
+        .filter_map(|target| series(&tt, target, &req.range, limit))
+        .collect();
+    negotiate::json(&http, StatusCode::OK, &results)
+}
+
+// Alerts in effect during the range, as regions clipped to it. An empty
+// annotation query shows every alert.
+fn alert_annotations(req: &AnnotationRequest, alerts: &[Alert]) -> Vec<Value> {
+    let (from, to) = (
+        req.range.from.with_timezone(&Local),
+        req.range.to.with_timezone(&Local),
+    );
+    alerts
+        .iter()
+        .filter(|a| a.overlaps(from, to))
+        .map(|a| {
+            let start = a.active_from.map_or(from, |start| start.max(from));
+            let end = a.active_until.map_or(to, |until| until.min(to));
+            let mut tags = vec![json!("alert"), json!(a.severity)];
+            tags.extend(
+                a.stations
+                    .iter()
+                    .chain(&a.lines)
+                    .chain(&a.directions)
+                    .map(|t| json!(t)),
+            );
+            json!({
+                "annotation": req.annotation,
+                "time": start.timestamp_millis(),
+                "timeEnd": end.timestamp_millis(),
+                "isRegion": true,
+                "title": a.header,
+                "text": a.description,
+                "tags": tags,
+            })
+        })
+        .collect()
+}
+
+// The annotation query names a platform, like "cwe west", and marks the first
+// and last train of each service day in the range along with the alerts
+// about that platform.
+#[post("/annotations")]
+async fn annotations(
+    http: HttpRequest,
+    tt: Current,
+    alerts: web::Data<Alerts>,
+    req: web::Json<AnnotationRequest>,
+) -> HttpResponse {
+    let (from, to) = req.range.local();
+    let platform = req.annotation["query"].as_str().unwrap_or("").trim();
+    if platform.is_empty() {
+        return negotiate::json(
+            &http,
+            StatusCode::OK,
+            &alert_annotations(&req, &alerts.all()),
+        );
+    }
+    let (station, direction) = match parse_target(&format!("departures {}", platform)) {
+        Some((_, station, direction)) => (station, direction),
+        None => return negotiate::json(&http, StatusCode::OK, &Vec::<Value>::new()),
+    };
+    // Whole service days, so the first and last trains are the day's own.
+    let (first_day, _) = schedule::service_day(from);
+    let (last_day, _) = schedule::service_day(to);
+    let departures: Vec<Departure> = tt
+        .between(
+            station,
+            direction,
+            schedule::service_datetime(first_day, SERVICE_DAY_START),
+            schedule::service_datetime(last_day + Duration::days(1), SERVICE_DAY_START),
+        )
+        .into_iter()
+        .filter(|d| d.terminal != station)
+        .collect();
+    let mut found = Vec::new();
+    for (i, d) in departures.iter().enumerate() {
+        let first = i == 0 || departures[i - 1].date != d.date;
+        let last = departures.get(i + 1).is_none_or(|next| next.date != d.date);
+        let shown = d.datetime() >= from && d.datetime() < to;
+        for (is, title) in [(first, "First train"), (last, "Last train")] {
+            if is && shown {
+                found.push(json!({
+                    "annotation": req.annotation,
+                    "time": millis(d),
+                    "title": title,
+                    "text": format!(
+                        "{} {}bound {} line train at {}",
+                        title.trim_end_matches(" train"),
+                        direction.as_str(),
+                        d.line.as_str(),
+                        speech::spoken_name(station)
+                    ),
+                    "tags": [STATIONS[station].id, direction.as_str(), d.line.as_str()],
+                }));
+            }
+        }
+    }
+    let about: Vec<Alert> = alerts
+        .all()
+        .into_iter()
+        .filter(|a| a.touches(&[station], &[], Some(direction)))
+        .collect();
+    found.extend(alert_annotations(&req, &about));
+    negotiate::json(&http, StatusCode::OK, &found)
+}
@@ -0,0 +1,299 @@
+use crate::schedule::{
+    self, DayType, Direction, Line, Schedule, Timetable, Trip, Version, STATIONS,
+};
+use chrono::{Local, NaiveDate};
+use csv::Reader;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Reads an unzipped GTFS feed into the same timetable the CSVs give, so feeds
+// can be compared with the hand-kept data. Only MetroLink red and blue line
+// trips at known stations are kept, and calendar_dates.txt exceptions are
+// ignored: a timetable only knows weekday, Saturday and Sunday service.
+
+#[derive(Deserialize)]
+struct Stop {
+    stop_id: String,
+    stop_name: String,
+    #[serde(default)]
+    parent_station: String,
+    #[serde(default)]
+    stop_lat: Option<f64>,
+    #[serde(default)]
+    stop_lon: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct Route {
+    route_id: String,
+    #[serde(default)]
+    route_short_name: String,
+    #[serde(default)]
+    route_long_name: String,
+}
+
+#[derive(Deserialize)]
+struct GtfsTrip {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+}
+
+#[derive(Deserialize)]
+struct StopTime {
+    trip_id: String,
+    #[serde(default)]
+    arrival_time: String,
+    #[serde(default)]
+    departure_time: String,
+    stop_id: String,
+    stop_sequence: u32,
+}
+
+#[derive(Deserialize)]
+struct Calendar {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+pub fn is_feed(dir: &str) -> bool {
+    Path::new(dir).join("stop_times.txt").is_file()
+}
+
+fn read<T: serde::de::DeserializeOwned>(dir: &str, name: &str) -> Result<Vec<T>, String> {
+    let path = Path::new(dir).join(name);
+    let mut reader = Reader::from_path(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    reader
+        .deserialize()
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Feeds name stops like "CENTRAL WEST END METROLINK STATION", so compare
+// letters and digits only, without the words every station shares.
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    let words: Vec<&str> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && !["metrolink", "station", "platform", "eb", "wb"].contains(w))
+        .collect();
+    words.concat()
+}
+
+pub fn station_for(name: &str) -> Option<usize> {
+    let name = normalize(name);
+    STATIONS.iter().position(|s| {
+        normalize(s.name) == name
+            || normalize(s.id) == name
+            || s.aliases.iter().any(|a| normalize(a) == name)
+    })
+}
+
+fn line_for(route: &Route) -> Option<Line> {
+    let name = format!("{} {}", route.route_short_name, route.route_long_name).to_lowercase();
+    match (name.contains("red"), name.contains("blue")) {
+        (true, false) => Some(Line::Red),
+        (false, true) => Some(Line::Blue),
+        _ => None,
+    }
+}
+
+// "25:10:00" -> service-day minutes; GTFS already counts past midnight.
+fn parse_minutes(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':');
+    let hh: u32 = parts.next()?.parse().ok()?;
+    let mm: u32 = parts.next()?.parse().ok()?;
+    if mm > 59 {
+        return None;
+    }
+    Some(schedule::service_minutes(hh * 60 + mm))
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+// Feeds often carry the current and the next service period side by side.
+// For each day type use the period in effect today, or else the latest one.
+fn services(calendar: &[Calendar]) -> HashMap<&str, Vec<DayType>> {
+    let today = Local::now().date_naive();
+    let runs = |c: &Calendar, day: DayType| match day {
+        DayType::Weekday => c.monday + c.tuesday + c.wednesday + c.thursday + c.friday > 0,
+        DayType::Saturday => c.saturday > 0,
+        DayType::Sunday => c.sunday > 0,
+    };
+    let mut services: HashMap<&str, Vec<DayType>> = HashMap::new();
+    for day in DayType::ALL.iter().copied() {
+        let candidates: Vec<&Calendar> = calendar.iter().filter(|c| runs(c, day)).collect();
+        let current = candidates.iter().find(|c| {
+            parse_date(&c.start_date).is_some_and(|d| d <= today)
+                && parse_date(&c.end_date).is_some_and(|d| today <= d)
+        });
+        let period = match current {
+            Some(c) => Some((&c.start_date, &c.end_date)),
+            None => candidates
+                .iter()
+                .max_by_key(|c| parse_date(&c.start_date))
+                .map(|c| (&c.start_date, &c.end_date)),
+        };
+        for c in candidates
+            .iter()
+            .filter(|c| Some((&c.start_date, &c.end_date)) == period)
+        {
+            services.entry(c.service_id.as_str()).or_default().push(day);
+        }
+    }
+    services
+}
+
+// Which of our stations each of the feed's stops is. Platforms usually hang
+// off a parent station with the proper name.
+fn stations_of(stops: &[Stop]) -> Vec<(&Stop, usize)> {
+    let names: HashMap<&str, &str> = stops
+        .iter()
+        .map(|s| (s.stop_id.as_str(), s.stop_name.as_str()))
+        .collect();
+    stops
+        .iter()
+        .filter_map(|s| {
+            let parent = names.get(s.parent_station.as_str()).copied();
+            let station = parent
+                .and_then(station_for)
+                .or_else(|| station_for(&s.stop_name))?;
+            Some((s, station))
+        })
+        .collect()
+}
+
+pub fn stations(dir: &str) -> Result<HashMap<String, usize>, String> {
+    let stops: Vec<Stop> = read(dir, "stops.txt")?;
+    Ok(stations_of(&stops)
+        .into_iter()
+        .map(|(s, station)| (s.stop_id.clone(), station))
+        .collect())
+}
+
+// Where each station is, indexed like STATIONS: the middle of the feed's
+// stops for it.
+pub fn coordinates(dir: &str) -> Result<Vec<Option<(f64, f64)>>, String> {
+    let stops: Vec<Stop> = read(dir, "stops.txt")?;
+    let mut sums = vec![(0.0, 0.0, 0); STATIONS.len()];
+    for (stop, station) in stations_of(&stops) {
+        if let (Some(lat), Some(lon)) = (stop.stop_lat, stop.stop_lon) {
+            let sum = &mut sums[station];
+            *sum = (sum.0 + lat, sum.1 + lon, sum.2 + 1);
+        }
+    }
+    Ok(sums
+        .into_iter()
+        .map(|(lat, lon, n)| match n {
+            0 => None,
+            n => Some((lat / n as f64, lon / n as f64)),
+        })
+        .collect())
+}
+
+pub fn load(dir: &str) -> Result<Timetable, String> {
+    let stations = stations(dir)?;
+    let routes: Vec<Route> = read(dir, "routes.txt")?;
+    let trips: Vec<GtfsTrip> = read(dir, "trips.txt")?;
+    let stop_times: Vec<StopTime> = read(dir, "stop_times.txt")?;
+    let calendar: Vec<Calendar> = read(dir, "calendar.txt")?;
+
+    let lines: HashMap<&str, Line> = routes
+        .iter()
+        .filter_map(|r| Some((r.route_id.as_str(), line_for(r)?)))
+        .collect();
+    let services = services(&calendar);
+
+    let mut times: HashMap<&str, Vec<&StopTime>> = HashMap::new();
+    for stop_time in stop_times.iter() {
+        times
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time);
+    }
+    let mut schedules: HashMap<(DayType, Direction), Vec<Trip>> = HashMap::new();
+    for trip in trips.iter() {
+        let (line, days) = match (
+            lines.get(trip.route_id.as_str()),
+            services.get(trip.service_id.as_str()),
+        ) {
+            (Some(line), Some(days)) => (*line, days),
+            _ => continue,
+        };
+        let mut stops = match times.get(trip.trip_id.as_str()) {
+            Some(stops) => stops.clone(),
+            None => continue,
+        };
+        stops.sort_by_key(|s| s.stop_sequence);
+        let visited: Vec<(usize, u32)> = stops
+            .iter()
+            .filter_map(|s| {
+                let station = *stations.get(s.stop_id.as_str())?;
+                let time =
+                    parse_minutes(&s.departure_time).or_else(|| parse_minutes(&s.arrival_time))?;
+                Some((station, time))
+            })
+            .collect();
+        let (first, last) = match (visited.first(), visited.last()) {
+            (Some(first), Some(last)) if visited.len() > 1 => (first.0, last.0),
+            _ => continue,
+        };
+        // STATIONS is in eastbound order.
+        let direction = if first < last {
+            Direction::East
+        } else {
+            Direction::West
+        };
+        let mut times = vec![None; STATIONS.len()];
+        for (station, time) in visited {
+            times[station] = Some(time);
+        }
+        for day in days.iter().copied() {
+            schedules.entry((day, direction)).or_default().push(Trip {
+                line,
+                row: 0,
+                times: times.clone(),
+                extra: false,
+                event: None,
+            });
+        }
+    }
+
+    let mut timetable = Vec::new();
+    for day in DayType::ALL.iter().copied() {
+        for direction in Direction::ALL.iter().copied() {
+            let mut order: Vec<usize> = (0..STATIONS.len()).collect();
+            if direction == Direction::West {
+                order.reverse();
+            }
+            let mut trips = schedules.remove(&(day, direction)).unwrap_or_default();
+            trips.sort_by_key(|t| order.iter().find_map(|s| t.times[*s]));
+            // Numbered as if written out as a CSV, header first.
+            for (i, trip) in trips.iter_mut().enumerate() {
+                trip.row = i + 2;
+            }
+            timetable.push(Schedule {
+                day,
+                direction,
+                order,
+                trips,
+            });
+        }
+    }
+    Ok(Timetable::new(vec![Version {
+        id: "gtfs".to_string(),
+        effective_from: None,
+        effective_until: None,
+        schedules: timetable,
+    }]))
+}
@@ -0,0 +1,592 @@
+use crate::schedule::{DayType, Direction};
+use protobuf::{CodedInputStream, CodedOutputStream, ProtobufResult};
+
+// The parts of the GTFS-Realtime messages we use, read and written field by
+// field with the protobuf crate's wire-format streams rather than generated
+// code. Field numbers are from gtfs-realtime.proto; anything else is skipped
+// when reading. The same structs serialize to JSON for the debug feeds.
+
+// TripDescriptor.schedule_relationship
+pub const TRIP_CANCELED: i32 = 3;
+// StopTimeUpdate.schedule_relationship
+pub const STOP_SKIPPED: i32 = 1;
+pub const STOP_NO_DATA: i32 = 2;
+// VehiclePosition.current_status
+pub const STOPPED_AT: i32 = 1;
+pub const IN_TRANSIT_TO: i32 = 2;
+// Alert.severity_level
+pub const INFO: i32 = 2;
+pub const WARNING: i32 = 3;
+pub const SEVERE: i32 = 4;
+
+#[derive(Default, Serialize)]
+pub struct FeedMessage {
+    pub header: FeedHeader,
+    pub entity: Vec<FeedEntity>,
+}
+
+#[derive(Default, Serialize)]
+pub struct FeedHeader {
+    pub gtfs_realtime_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Default, Serialize)]
+pub struct FeedEntity {
+    pub id: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub is_deleted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_update: Option<TripUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<VehiclePosition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<Alert>,
+}
+
+#[derive(Default, Serialize)]
+pub struct TripUpdate {
+    pub trip: TripDescriptor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<VehicleDescriptor>,
+    pub stop_time_update: Vec<StopTimeUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<i32>,
+}
+
+#[derive(Default, Serialize)]
+pub struct TripDescriptor {
+    pub trip_id: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub route_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction_id: Option<u32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub start_time: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub start_date: String,
+    pub schedule_relationship: i32,
+}
+
+#[derive(Default, Serialize)]
+pub struct VehicleDescriptor {
+    pub id: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub label: String,
+}
+
+#[derive(Default, Serialize)]
+pub struct StopTimeUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequence: Option<u32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stop_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival: Option<StopTimeEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departure: Option<StopTimeEvent>,
+    pub schedule_relationship: i32,
+}
+
+#[derive(Default, Serialize)]
+pub struct StopTimeEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<i64>,
+}
+
+#[derive(Default, Serialize)]
+pub struct VehiclePosition {
+    pub trip: TripDescriptor,
+    pub vehicle: VehicleDescriptor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    pub current_stop_sequence: u32,
+    pub stop_id: String,
+    pub current_status: i32,
+    pub timestamp: u64,
+}
+
+#[derive(Default, Serialize)]
+pub struct Position {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+#[derive(Default, Serialize)]
+pub struct Alert {
+    pub active_period: Vec<TimeRange>,
+    pub informed_entity: Vec<EntitySelector>,
+    pub header_text: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description_text: String,
+    pub severity_level: i32,
+}
+
+#[derive(Default, Serialize)]
+pub struct TimeRange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+#[derive(Default, Serialize)]
+pub struct EntitySelector {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub route_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction_id: Option<u32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stop_id: String,
+}
+
+trait Decode: Default {
+    // Reads one field, or returns false if it isn't one we keep.
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool>;
+
+    fn decode(bytes: &[u8]) -> ProtobufResult<Self> {
+        let mut message = Self::default();
+        let mut is = CodedInputStream::from_bytes(bytes);
+        while !is.eof()? {
+            let (number, wire_type) = is.read_tag_unpack()?;
+            if !message.field(number, &mut is)? {
+                is.skip_field(wire_type)?;
+            }
+        }
+        Ok(message)
+    }
+}
+
+fn message<T: Decode>(is: &mut CodedInputStream) -> ProtobufResult<T> {
+    T::decode(&is.read_bytes()?)
+}
+
+impl Decode for FeedMessage {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.header = message(is)?,
+            2 => self.entity.push(message(is)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for FeedHeader {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.gtfs_realtime_version = is.read_string()?,
+            3 => self.timestamp = Some(is.read_uint64()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for FeedEntity {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.id = is.read_string()?,
+            2 => self.is_deleted = is.read_bool()?,
+            3 => self.trip_update = Some(message(is)?),
+            5 => self.alert = Some(message(is)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for TripUpdate {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.trip = message(is)?,
+            2 => self.stop_time_update.push(message(is)?),
+            4 => self.timestamp = Some(is.read_uint64()?),
+            5 => self.delay = Some(is.read_int32()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for TripDescriptor {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.trip_id = is.read_string()?,
+            2 => self.start_time = is.read_string()?,
+            3 => self.start_date = is.read_string()?,
+            4 => self.schedule_relationship = is.read_int32()?,
+            5 => self.route_id = is.read_string()?,
+            6 => self.direction_id = Some(is.read_uint32()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for StopTimeUpdate {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.stop_sequence = Some(is.read_uint32()?),
+            2 => self.arrival = Some(message(is)?),
+            3 => self.departure = Some(message(is)?),
+            4 => self.stop_id = is.read_string()?,
+            5 => self.schedule_relationship = is.read_int32()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for StopTimeEvent {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.delay = Some(is.read_int32()?),
+            2 => self.time = Some(is.read_int64()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for Alert {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.active_period.push(message(is)?),
+            5 => self.informed_entity.push(message(is)?),
+            10 => self.header_text = message::<TranslatedString>(is)?.text(),
+            11 => self.description_text = message::<TranslatedString>(is)?.text(),
+            14 => self.severity_level = is.read_int32()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for TimeRange {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.start = Some(is.read_uint64()?),
+            2 => self.end = Some(is.read_uint64()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for EntitySelector {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            2 => self.route_id = is.read_string()?,
+            5 => self.stop_id = is.read_string()?,
+            6 => self.direction_id = Some(is.read_uint32()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for TranslatedString {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.0.push(message(is)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Decode for Translation {
+    fn field(&mut self, number: u32, is: &mut CodedInputStream) -> ProtobufResult<bool> {
+        match number {
+            1 => self.text = is.read_string()?,
+            2 => self.language = is.read_string()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<FeedMessage, String> {
+    FeedMessage::decode(bytes).map_err(|e| e.to_string())
+}
+
+trait Encode {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()>;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut bytes);
+            self.fields(&mut os)
+                .and_then(|_| os.flush())
+                .expect("writing to a Vec can't fail");
+        }
+        bytes
+    }
+}
+
+fn write_message<T: Encode>(
+    os: &mut CodedOutputStream,
+    number: u32,
+    message: &T,
+) -> ProtobufResult<()> {
+    os.write_bytes(number, &message.encode())
+}
+
+// proto2 optional strings: an empty one is left out.
+fn write_string(os: &mut CodedOutputStream, number: u32, s: &str) -> ProtobufResult<()> {
+    match s.is_empty() {
+        true => Ok(()),
+        false => os.write_string(number, s),
+    }
+}
+
+// A TranslatedString. Ours have a single translation and no language; of
+// other feeds' the English one is read, or else the first.
+#[derive(Default)]
+struct TranslatedString(Vec<Translation>);
+
+#[derive(Default)]
+struct Translation {
+    text: String,
+    language: String,
+}
+
+impl TranslatedString {
+    fn text(self) -> String {
+        let mut translations = self.0;
+        let english = translations
+            .iter()
+            .position(|t| t.language.starts_with("en"));
+        match (english, translations.is_empty()) {
+            (Some(i), _) => translations.swap_remove(i).text,
+            (None, false) => translations.swap_remove(0).text,
+            (None, true) => String::new(),
+        }
+    }
+}
+
+impl Encode for TranslatedString {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        for translation in self.0.iter() {
+            write_message(os, 1, translation)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for Translation {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        os.write_string(1, &self.text)?;
+        write_string(os, 2, &self.language)
+    }
+}
+
+fn write_text(os: &mut CodedOutputStream, number: u32, text: &str) -> ProtobufResult<()> {
+    let translation = Translation {
+        text: text.to_string(),
+        language: String::new(),
+    };
+    match text.is_empty() {
+        true => Ok(()),
+        false => write_message(os, number, &TranslatedString(vec![translation])),
+    }
+}
+
+impl Encode for FeedMessage {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_message(os, 1, &self.header)?;
+        for entity in self.entity.iter() {
+            write_message(os, 2, entity)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for FeedHeader {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        os.write_string(1, &self.gtfs_realtime_version)?;
+        // FULL_DATASET
+        os.write_enum(2, 0)?;
+        if let Some(t) = self.timestamp {
+            os.write_uint64(3, t)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for FeedEntity {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        os.write_string(1, &self.id)?;
+        if self.is_deleted {
+            os.write_bool(2, true)?;
+        }
+        if let Some(t) = &self.trip_update {
+            write_message(os, 3, t)?;
+        }
+        if let Some(v) = &self.vehicle {
+            write_message(os, 4, v)?;
+        }
+        if let Some(a) = &self.alert {
+            write_message(os, 5, a)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for TripUpdate {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_message(os, 1, &self.trip)?;
+        for update in self.stop_time_update.iter() {
+            write_message(os, 2, update)?;
+        }
+        if let Some(v) = &self.vehicle {
+            write_message(os, 3, v)?;
+        }
+        if let Some(t) = self.timestamp {
+            os.write_uint64(4, t)?;
+        }
+        if let Some(d) = self.delay {
+            os.write_int32(5, d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for TripDescriptor {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_string(os, 1, &self.trip_id)?;
+        write_string(os, 2, &self.start_time)?;
+        write_string(os, 3, &self.start_date)?;
+        os.write_enum(4, self.schedule_relationship)?;
+        write_string(os, 5, &self.route_id)?;
+        if let Some(d) = self.direction_id {
+            os.write_uint32(6, d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for VehicleDescriptor {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_string(os, 1, &self.id)?;
+        write_string(os, 2, &self.label)
+    }
+}
+
+impl Encode for StopTimeUpdate {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        if let Some(n) = self.stop_sequence {
+            os.write_uint32(1, n)?;
+        }
+        if let Some(e) = &self.arrival {
+            write_message(os, 2, e)?;
+        }
+        if let Some(e) = &self.departure {
+            write_message(os, 3, e)?;
+        }
+        write_string(os, 4, &self.stop_id)?;
+        os.write_enum(5, self.schedule_relationship)
+    }
+}
+
+impl Encode for StopTimeEvent {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        if let Some(d) = self.delay {
+            os.write_int32(1, d)?;
+        }
+        if let Some(t) = self.time {
+            os.write_int64(2, t)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for VehiclePosition {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_message(os, 1, &self.trip)?;
+        if let Some(p) = &self.position {
+            write_message(os, 2, p)?;
+        }
+        os.write_uint32(3, self.current_stop_sequence)?;
+        os.write_enum(4, self.current_status)?;
+        os.write_uint64(5, self.timestamp)?;
+        write_string(os, 7, &self.stop_id)?;
+        write_message(os, 8, &self.vehicle)
+    }
+}
+
+impl Encode for Position {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        os.write_float(1, self.latitude)?;
+        os.write_float(2, self.longitude)
+    }
+}
+
+impl Encode for Alert {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        for period in self.active_period.iter() {
+            write_message(os, 1, period)?;
+        }
+        for entity in self.informed_entity.iter() {
+            write_message(os, 5, entity)?;
+        }
+        write_text(os, 10, &self.header_text)?;
+        write_text(os, 11, &self.description_text)?;
+        os.write_enum(14, self.severity_level)
+    }
+}
+
+impl Encode for TimeRange {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        if let Some(t) = self.start {
+            os.write_uint64(1, t)?;
+        }
+        if let Some(t) = self.end {
+            os.write_uint64(2, t)?;
+        }
+        Ok(())
+    }
+}
+
+impl Encode for EntitySelector {
+    fn fields(&self, os: &mut CodedOutputStream) -> ProtobufResult<()> {
+        write_string(os, 2, &self.route_id)?;
+        write_string(os, 5, &self.stop_id)?;
+        if let Some(d) = self.direction_id {
+            os.write_uint32(6, d)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn encode(feed: &FeedMessage) -> Vec<u8> {
+    feed.encode()
+}
+
+// Trip ids name the schedule version, file and row, like
+// "base-weekday-east-12", so they stay put as long as the file does.
+pub fn trip_id(version: &str, day: DayType, direction: Direction, row: usize) -> String {
+    format!(
+        "{}-{}-{}-{}",
+        version,
+        day.as_str(),
+        direction.as_str(),
+        row
+    )
+}
+
+// Versions may have dashes of their own, so this splits from the right.
+pub fn parse_trip_id(id: &str) -> Option<(&str, DayType, Direction, usize)> {
+    let mut parts = id.rsplitn(4, '-');
+    let row = parts.next()?.parse().ok()?;
+    let direction = Direction::parse(parts.next()?)?;
+    let day = DayType::parse(parts.next()?)?;
+    Some((parts.next()?, day, direction, row))
+}
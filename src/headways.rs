@@ -0,0 +1,253 @@
+use crate::negotiate::{self, Reply};
+use crate::openapi;
+use crate::reload::Current;
+use crate::schedule::{self, Line, Schedule, MINUTES_PER_DAY};
+use crate::span;
+use crate::v2::StationRef;
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
+
+// How often trains leave a station, hour by hour, for each day type and
+// direction, from the schedule version in effect today like the span. Each
+// gap between two trains counts towards the hour the second one leaves in.
+// Where both lines stop, a combined row covers the shared trunk.
+
+#[derive(Serialize, JsonSchema)]
+pub struct Headway {
+    day: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+    // One line, or both for the combined frequency on the trunk.
+    lines: Vec<String>,
+    // The start of the hour.
+    #[schemars(schema_with = "openapi::time_schema")]
+    hour: String,
+    after_midnight: bool,
+    trains: usize,
+    // Gaps in minutes; null for an hour with only the day's first train.
+    min_minutes: Option<u32>,
+    median_minutes: Option<f64>,
+    max_minutes: Option<u32>,
+    // How long someone turning up at a random time waits on average. Uneven
+    // gaps make it longer than half the median, since more riders turn up in
+    // the long ones.
+    expected_wait_minutes: Option<f64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HeadwaysResponse {
+    station: StationRef,
+    schedule_version: String,
+    headways: Vec<Headway>,
+}
+
+impl Reply for HeadwaysResponse {
+    const NAME: &'static str = "headways";
+
+    fn text(&self) -> String {
+        let rows: Vec<String> = self
+            .headways
+            .iter()
+            .map(|h| {
+                let every = match h.median_minutes {
+                    Some(m) => format!("every {} min", m.round()),
+                    None => "first train".to_string(),
+                };
+                format!(
+                    "{} {}bound {} {}: {}",
+                    h.day,
+                    h.direction,
+                    h.lines.join("+"),
+                    h.hour,
+                    every
+                )
+            })
+            .collect();
+        format!("{}: {}", self.station.name, rows.join("; "))
+    }
+}
+
+fn median(sorted: &[u32]) -> Option<f64> {
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) as f64 / 2.0),
+        _ => Some(sorted[mid] as f64),
+    }
+}
+
+// A wait is uniform within a gap, and riders land in a gap in proportion to
+// its length.
+fn expected_wait(gaps: &[u32]) -> Option<f64> {
+    let total: u32 = gaps.iter().sum();
+    match total {
+        0 => None,
+        _ => {
+            let wait = gaps.iter().map(|g| (g * g) as f64).sum::<f64>() / (2.0 * total as f64);
+            Some((wait * 10.0).round() / 10.0)
+        }
+    }
+}
+
+// One row per hour with a train leaving, for the given lines together.
+// Trains ending at the station don't leave it.
+fn by_hour(schedule: &Schedule, station: usize, lines: &[Line]) -> Vec<Headway> {
+    let times: Vec<u32> = schedule
+        .departures(station)
+        .into_iter()
+        .filter(|(_, trip)| lines.contains(&trip.line))
+        .filter(|(_, trip)| schedule.terminal(trip).is_some_and(|t| t != station))
+        .map(|(time, _)| time)
+        .collect();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    while start < times.len() {
+        let hour = times[start] / 60;
+        let end = start
+            + times[start..]
+                .iter()
+                .take_while(|t| **t / 60 == hour)
+                .count();
+        let mut gaps: Vec<u32> = (start.max(1)..end)
+            .map(|i| times[i] - times[i - 1])
+            .collect();
+        let wait = expected_wait(&gaps);
+        gaps.sort_unstable();
+        rows.push(Headway {
+            day: schedule.day.as_str().to_string(),
+            direction: schedule.direction.as_str().to_string(),
+            lines: lines.iter().map(|l| l.as_str().to_string()).collect(),
+            hour: schedule::format_time(hour * 60),
+            after_midnight: hour * 60 >= MINUTES_PER_DAY,
+            trains: end - start,
+            min_minutes: gaps.first().copied(),
+            median_minutes: median(&gaps),
+            max_minutes: gaps.last().copied(),
+            expected_wait_minutes: wait,
+        });
+        start = end;
+    }
+    rows
+}
+
+// Each line on its own, and both together where both stop.
+fn rows(schedule: &Schedule, station: usize) -> Vec<Headway> {
+    let mut rows = Vec::new();
+    let mut served = Vec::new();
+    for line in Line::ALL.iter().copied() {
+        let line_rows = by_hour(schedule, station, &[line]);
+        if !line_rows.is_empty() {
+            served.push(line);
+        }
+        rows.extend(line_rows);
+    }
+    if served.len() > 1 {
+        rows.extend(by_hour(schedule, station, &served));
+    }
+    rows
+}
+
+#[get("/stations/{id}/headways")]
+pub async fn station_headways(
+    http: HttpRequest,
+    tt: Current,
+    path: web::Path<String>,
+) -> HttpResponse {
+    match span::by_schedule(&http, &tt, &path, rows) {
+        Ok((station, schedule_version, headways)) => negotiate::reply(
+            &http,
+            StatusCode::OK,
+            &HeadwaysResponse {
+                station,
+                schedule_version,
+                headways,
+            },
+        ),
+        Err(res) => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::{find_station, DayType, Direction};
+
+    // The day starts with a red train at 5:50A; the blue trains in the 6AM
+    // hour fall unevenly between the red ones.
+    const EASTBOUND: &str = "\
+Central West End Station,Grand Station
+5:50AR,5:55AR
+6:00AR,6:05AR
+6:05AB,6:10AB
+6:10AR,6:15AR
+6:25AB,6:30AB
+6:40AR,6:45AR
+";
+
+    fn rows_at_cwe() -> Vec<Headway> {
+        let schedule =
+            Schedule::parse(DayType::Weekday, Direction::East, EASTBOUND.as_bytes()).unwrap();
+        rows(&schedule, find_station("cwe").unwrap())
+    }
+
+    fn row<'a>(rows: &'a [Headway], lines: &[&str], hour: &str) -> &'a Headway {
+        rows.iter()
+            .find(|r| r.lines == lines && r.hour == hour)
+            .unwrap()
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[5, 10, 30]), Some(10.0));
+        assert_eq!(median(&[5, 10, 15, 30]), Some(12.5));
+    }
+
+    #[test]
+    fn uneven_gaps_mean_a_longer_wait() {
+        assert_eq!(expected_wait(&[10, 10]), Some(5.0));
+        // (10² + 30²) / (2 × 40), more than half of the 20-minute median.
+        assert_eq!(expected_wait(&[10, 30]), Some(12.5));
+        assert_eq!(expected_wait(&[]), None);
+    }
+
+    #[test]
+    fn first_train_of_the_day_has_no_gap() {
+        let rows = rows_at_cwe();
+        let first = row(&rows, &["red"], "5:00A");
+        assert_eq!(first.trains, 1);
+        assert_eq!(
+            (first.min_minutes, first.median_minutes, first.max_minutes),
+            (None, None, None)
+        );
+        assert_eq!(first.expected_wait_minutes, None);
+
+        // Later hours count the gap back to the train before.
+        let red = row(&rows, &["red"], "6:00A");
+        assert_eq!(red.trains, 3);
+        assert_eq!(
+            (red.min_minutes, red.median_minutes, red.max_minutes),
+            (Some(10), Some(10.0), Some(30))
+        );
+        assert_eq!(red.expected_wait_minutes, Some(11.0));
+    }
+
+    #[test]
+    fn trunk_row_combines_both_lines() {
+        let rows = rows_at_cwe();
+        let blue = row(&rows, &["blue"], "6:00A");
+        assert_eq!((blue.trains, blue.median_minutes), (2, Some(20.0)));
+
+        // 5:50, 6:00, 6:05, 6:10, 6:25 and 6:40: gaps of 10, 5, 5, 15 and 15.
+        let both = row(&rows, &["red", "blue"], "6:00A");
+        assert_eq!(both.trains, 5);
+        assert_eq!(
+            (both.min_minutes, both.median_minutes, both.max_minutes),
+            (Some(5), Some(10.0), Some(15))
+        );
+        assert_eq!(both.expected_wait_minutes, Some(6.0));
+        let first = row(&rows, &["red", "blue"], "5:00A");
+        assert_eq!((first.trains, first.median_minutes), (1, None));
+    }
+}
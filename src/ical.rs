@@ -0,0 +1,256 @@
+use crate::planner::Plan;
+use crate::schedule::{self, DayType, Schedule, STATIONS, TIMEZONE};
+use crate::v2;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+
+const PRODID: &str = "-//metro-schedule-api//EN";
+
+// US Central time rules, so clients don't have to know the TZID.
+const VTIMEZONE: [&str; 16] = [
+    "BEGIN:VTIMEZONE",
+    "TZID:America/Chicago",
+    "BEGIN:DAYLIGHT",
+    "TZOFFSETFROM:-0600",
+    "TZOFFSETTO:-0500",
+    "TZNAME:CDT",
+    "DTSTART:19700308T020000",
+    "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU",
+    "END:DAYLIGHT",
+    "BEGIN:STANDARD",
+    "TZOFFSETFROM:-0500",
+    "TZOFFSETTO:-0600",
+    "TZNAME:CST",
+    "DTSTART:19701101T020000",
+    "RRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=1SU",
+    "END:STANDARD",
+];
+
+struct Calendar {
+    lines: Vec<String>,
+    stamp: String,
+}
+
+impl Calendar {
+    fn new(name: &str) -> Calendar {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            format!("PRODID:{}", PRODID),
+            "CALSCALE:GREGORIAN".to_string(),
+            "METHOD:PUBLISH".to_string(),
+            format!("X-WR-CALNAME:{}", escape(name)),
+            format!("X-WR-TIMEZONE:{}", TIMEZONE),
+        ];
+        lines.extend(VTIMEZONE.iter().map(|l| l.to_string()));
+        lines.push("END:VTIMEZONE".to_string());
+        Calendar {
+            lines,
+            stamp: Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+        }
+    }
+
+    fn event(
+        &mut self,
+        uid: String,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        summary: &str,
+        location: &str,
+        rrule: Option<String>,
+    ) {
+        self.lines.push("BEGIN:VEVENT".to_string());
+        self.lines.push(format!("UID:{}@metro-schedule-api", uid));
+        self.lines.push(format!("DTSTAMP:{}", self.stamp));
+        self.lines
+            .push(format!("DTSTART;TZID={}:{}", TIMEZONE, local(start)));
+        self.lines
+            .push(format!("DTEND;TZID={}:{}", TIMEZONE, local(end)));
+        if let Some(rule) = rrule {
+            self.lines.push(format!("RRULE:{}", rule));
+        }
+        self.lines.push(format!("SUMMARY:{}", escape(summary)));
+        self.lines.push(format!("LOCATION:{}", escape(location)));
+        self.lines.push("END:VEVENT".to_string());
+    }
+
+    fn finish(mut self) -> String {
+        self.lines.push("END:VCALENDAR".to_string());
+        let mut out = String::new();
+        for line in self.lines.iter() {
+            out.push_str(&fold(line));
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+fn local(t: NaiveDateTime) -> String {
+    t.format("%Y%m%dT%H%M%S").to_string()
+}
+
+// The last minute of a service date in UTC, which is how UNTIL has to be given when
+// the start has a TZID.
+fn end_of(date: NaiveDate) -> String {
+    let end = schedule::service_datetime(
+        date,
+        schedule::MINUTES_PER_DAY + schedule::SERVICE_DAY_START - 1,
+    );
+    v2::local(end)
+        .with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Content lines longer than 75 octets continue on the next line after a space.
+fn fold(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Departures after midnight land on the following calendar day, so the
+// weekly rule shifts with them.
+fn by_day(day: DayType, after_midnight: bool) -> &'static str {
+    match (day, after_midnight) {
+        (DayType::Weekday, false) => "MO,TU,WE,TH,FR",
+        (DayType::Weekday, true) => "TU,WE,TH,FR,SA",
+        (DayType::Saturday, false) => "SA",
+        (DayType::Saturday, true) => "SU",
+        (DayType::Sunday, false) => "SU",
+        (DayType::Sunday, true) => "MO",
+    }
+}
+
+pub fn trip_plan(plan: &Plan) -> String {
+    let first = &STATIONS[plan.legs[0].from];
+    let last = &STATIONS[plan.legs[plan.legs.len() - 1].to];
+    let mut cal = Calendar::new(&format!("{} to {}", first.name, last.name));
+    for leg in plan.legs.iter() {
+        let from = &STATIONS[leg.from];
+        let to = &STATIONS[leg.to];
+        cal.event(
+            format!("{}-{}-{}-{}", plan.date, from.id, to.id, leg.departure),
+            schedule::service_datetime(plan.date, leg.departure),
+            schedule::service_datetime(plan.date, leg.arrival),
+            &format!(
+                "{} line {}bound: {} to {}",
+                capitalize(leg.line.as_str()),
+                leg.direction.as_str(),
+                from.name,
+                to.name
+            ),
+            from.name,
+            None,
+        );
+    }
+    cal.finish()
+}
+
+// Every departure from a station as a weekly recurring event, anchored on the
+// first date on or after `from` that runs this schedule's day type, and
+// repeating through the service date `until`.
+pub fn timetable(
+    schedule: &Schedule,
+    station: usize,
+    from: NaiveDate,
+    until: Option<NaiveDate>,
+) -> String {
+    let mut anchor = from;
+    while DayType::for_date(anchor) != schedule.day {
+        anchor += Duration::days(1);
+    }
+    let name = STATIONS[station].name;
+    let direction = schedule.direction.as_str();
+    let mut cal = Calendar::new(&format!(
+        "{} {}bound ({})",
+        name,
+        direction,
+        schedule.day.as_str()
+    ));
+    for (time, trip) in schedule.departures(station) {
+        let terminal = match schedule.terminal(trip) {
+            Some(t) if t != station => t,
+            _ => continue,
+        };
+        let end = trip.times[terminal].unwrap_or(time);
+        cal.event(
+            format!(
+                "{}-{}-{}-{}-{}",
+                schedule.day.as_str(),
+                direction,
+                STATIONS[station].id,
+                trip.line.as_str(),
+                time
+            ),
+            schedule::service_datetime(anchor, time),
+            schedule::service_datetime(anchor, end),
+            &format!(
+                "{} line {}bound to {}",
+                capitalize(trip.line.as_str()),
+                direction,
+                STATIONS[terminal].name
+            ),
+            name,
+            Some(format!(
+                "FREQ=WEEKLY;BYDAY={}{}",
+                by_day(schedule.day, time >= schedule::MINUTES_PER_DAY),
+                until.map_or(String::new(), |date| format!(";UNTIL={}", end_of(date)))
+            )),
+        );
+    }
+    cal.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::{find_station, Direction, Timetable};
+
+    #[test]
+    fn timetable_stops_when_the_next_version_starts() {
+        let mut tt = Timetable::embedded().unwrap();
+        let mut next = tt.base().clone();
+        next.id = "2026-11".to_string();
+        next.effective_from = NaiveDate::from_ymd_opt(2026, 11, 2);
+        tt.versions.push(next);
+
+        let today = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let until = tt.in_effect_until(0, today);
+        assert_eq!(until, NaiveDate::from_ymd_opt(2026, 11, 1));
+        assert_eq!(tt.in_effect_until(1, today), None);
+
+        let schedule = tt.versions[0].schedule(DayType::Weekday, Direction::West);
+        let calendar = timetable(schedule, find_station("cwe").unwrap(), today, until);
+        let rules: Vec<&str> = calendar
+            .lines()
+            .filter(|l| l.starts_with("RRULE:FREQ=WEEKLY"))
+            .collect();
+        assert!(!rules.is_empty());
+        let end = format!(";UNTIL={}", end_of(until.unwrap()));
+        assert!(rules.iter().all(|r| r.ends_with(&end)));
+        assert!(end.starts_with(";UNTIL=2026110"));
+    }
+}
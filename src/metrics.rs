@@ -0,0 +1,236 @@
+use crate::reload::Schedules;
+use crate::schedule::{self, Direction, Line, STATIONS};
+use actix_web::web;
+use chrono::Local;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+use std::sync::atomic::Ordering;
+
+// Schedule gauges for /metrics, next to the HTTP metrics from actix-web-prom.
+// They are worked out from the timetable whenever Prometheus scrapes, so they
+// are never stale. Each scrape fills its own set of metrics, so overlapping
+// scrapes never see each other's half-filled families. The reload counters
+// mirror the ones kept by Schedules.
+
+pub struct ScheduleCollector {
+    namespace: String,
+    schedules: web::Data<Schedules>,
+    watch: Vec<(usize, Direction)>,
+    descs: Vec<Desc>,
+}
+
+impl ScheduleCollector {
+    pub fn new(
+        namespace: &str,
+        schedules: web::Data<Schedules>,
+        watch: Vec<(usize, Direction)>,
+    ) -> ScheduleCollector {
+        let descs = Gauges::new(namespace)
+            .families()
+            .iter()
+            .flat_map(|family| family.desc())
+            .cloned()
+            .collect();
+        ScheduleCollector {
+            namespace: namespace.to_string(),
+            schedules,
+            watch,
+            descs,
+        }
+    }
+
+    fn fill(&self, gauges: &Gauges) {
+        let tt = self.schedules.current();
+        let now = Local::now().naive_local();
+        let (date, minute) = schedule::service_day(now);
+        for (station, direction) in self.watch.iter().copied() {
+            let departures = tt.between(station, direction, now, now + chrono::Duration::days(1));
+            for line in Line::ALL.iter().copied() {
+                let mut times = departures
+                    .iter()
+                    .filter(|d| d.line == line && d.terminal != station)
+                    .map(|d| d.datetime());
+                let labels = [STATIONS[station].id, direction.as_str(), line.as_str()];
+                if let Some(next) = times.next() {
+                    gauges
+                        .next_departure
+                        .with_label_values(&labels)
+                        .set((next - now).num_seconds() as f64);
+                    if let Some(after) = times.next() {
+                        gauges
+                            .headway
+                            .with_label_values(&labels)
+                            .set((after - next).num_seconds() as f64);
+                    }
+                }
+            }
+        }
+        for line in Line::ALL.iter().copied() {
+            let running = Direction::ALL
+                .iter()
+                .flat_map(|d| tt.schedule(date, *d).trips.iter())
+                .filter(|trip| trip.line == line)
+                .filter(|trip| {
+                    let times = trip.times.iter().flatten();
+                    let first = times.clone().min();
+                    let last = times.max();
+                    first.is_some_and(|f| *f <= minute) && last.is_some_and(|l| minute <= *l)
+                })
+                .count();
+            gauges
+                .in_service
+                .with_label_values(&[line.as_str()])
+                .set(running as i64);
+        }
+        for (result, count) in [
+            ("success", &self.schedules.reloads),
+            ("failure", &self.schedules.failures),
+        ] {
+            gauges
+                .reloads
+                .with_label_values(&[result])
+                .inc_by(count.load(Ordering::Relaxed));
+        }
+        gauges
+            .loaded
+            .set(self.schedules.loaded_at.load(Ordering::Relaxed));
+        gauges
+            .failing
+            .set(self.schedules.failing.load(Ordering::Relaxed) as i64);
+    }
+}
+
+impl Collector for ScheduleCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let gauges = Gauges::new(&self.namespace);
+        self.fill(&gauges);
+        gauges
+            .families()
+            .iter()
+            .flat_map(|family| family.collect())
+            .collect()
+    }
+}
+
+struct Gauges {
+    next_departure: GaugeVec,
+    headway: GaugeVec,
+    in_service: IntGaugeVec,
+    reloads: IntCounterVec,
+    loaded: IntGauge,
+    failing: IntGauge,
+}
+
+impl Gauges {
+    fn new(namespace: &str) -> Gauges {
+        let labels = ["station", "direction", "line"];
+        let next_departure = GaugeVec::new(
+            Opts::new(
+                "next_departure_seconds",
+                "Seconds until the next scheduled departure",
+            )
+            .namespace(namespace),
+            &labels,
+        )
+        .expect("valid metric");
+        let headway = GaugeVec::new(
+            Opts::new(
+                "scheduled_headway_seconds",
+                "Seconds between the next two scheduled departures",
+            )
+            .namespace(namespace),
+            &labels,
+        )
+        .expect("valid metric");
+        let in_service = IntGaugeVec::new(
+            Opts::new(
+                "trains_in_service",
+                "Trains between their first and last stop right now",
+            )
+            .namespace(namespace),
+            &["line"],
+        )
+        .expect("valid metric");
+        let reloads = IntCounterVec::new(
+            Opts::new(
+                "schedule_reloads_total",
+                "Schedule reloads from --schedule-dir by result",
+            )
+            .namespace(namespace),
+            &["result"],
+        )
+        .expect("valid metric");
+        let loaded = IntGauge::with_opts(
+            Opts::new(
+                "schedule_loaded_timestamp_seconds",
+                "When the schedules in use were loaded",
+            )
+            .namespace(namespace),
+        )
+        .expect("valid metric");
+        let failing = IntGauge::with_opts(
+            Opts::new(
+                "schedule_reload_failing",
+                "1 if the last reload failed and older schedules are still in use",
+            )
+            .namespace(namespace),
+        )
+        .expect("valid metric");
+        Gauges {
+            next_departure,
+            headway,
+            in_service,
+            reloads,
+            loaded,
+            failing,
+        }
+    }
+
+    fn families(&self) -> [&dyn Collector; 6] {
+        [
+            &self.next_departure,
+            &self.headway,
+            &self.in_service,
+            &self.reloads,
+            &self.loaded,
+            &self.failing,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reloads(families: &[MetricFamily], result: &str) -> f64 {
+        let family = families
+            .iter()
+            .find(|f| f.get_name() == "metro_schedule_reloads_total")
+            .unwrap();
+        family
+            .get_metric()
+            .iter()
+            .find(|m| m.get_label()[0].get_value() == result)
+            .unwrap()
+            .get_counter()
+            .get_value()
+    }
+
+    #[test]
+    fn every_scrape_starts_afresh() {
+        let schedules = web::Data::new(Schedules::new(None, None, None).unwrap());
+        schedules.reloads.store(3, Ordering::Relaxed);
+        let collector = ScheduleCollector::new("metro", schedules, vec![(0, Direction::East)]);
+        let first = collector.collect();
+        let second = collector.collect();
+        assert_eq!(first.len(), collector.desc().len());
+        assert_eq!(reloads(&first, "success"), 3.0);
+        assert_eq!(reloads(&second, "success"), 3.0);
+        assert_eq!(reloads(&second, "failure"), 0.0);
+    }
+}
@@ -0,0 +1,402 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Picks a response format from the Accept header so every endpoint can answer
+// in JSON, XML, CSV, MessagePack or a single line of plain text.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Xml,
+    Csv,
+    MessagePack,
+    Text,
+}
+
+const MEDIA_TYPES: [(&str, Format); 8] = [
+    ("application/json", Format::Json),
+    ("application/xml", Format::Xml),
+    ("text/xml", Format::Xml),
+    ("text/csv", Format::Csv),
+    ("application/msgpack", Format::MessagePack),
+    ("application/x-msgpack", Format::MessagePack),
+    ("application/vnd.msgpack", Format::MessagePack),
+    ("text/plain", Format::Text),
+];
+
+pub fn supported_media_types() -> Vec<&'static str> {
+    MEDIA_TYPES.iter().map(|(m, _)| *m).collect()
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Xml => "application/xml; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::MessagePack => "application/msgpack",
+            Format::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    fn for_media_type(media: &str) -> Option<Format> {
+        match media {
+            "*/*" | "application/*" => Some(Format::Json),
+            "text/*" => Some(Format::Text),
+            _ => MEDIA_TYPES
+                .iter()
+                .find(|(m, _)| m.eq_ignore_ascii_case(media))
+                .map(|(_, f)| *f),
+        }
+    }
+
+    // None means the client asked only for types we can't produce.
+    pub fn from_request(req: &HttpRequest) -> Option<Format> {
+        let ranges = match accepted(req) {
+            Some(r) => r,
+            None => return Some(Format::Json),
+        };
+        let mut choices: Vec<(f32, usize, Format)> = Vec::new();
+        for (i, (media, q)) in ranges.into_iter().enumerate() {
+            if let Some(format) = Format::for_media_type(&media) {
+                choices.push((q, i, format));
+            }
+        }
+        // Highest quality wins, ties go to the type listed first.
+        choices.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.1.cmp(&b.1))
+        });
+        choices.first().map(|c| c.2)
+    }
+}
+
+// The media ranges in the Accept header that have a quality above zero, in
+// the order listed; None when there's no header, which accepts anything.
+fn accepted(req: &HttpRequest) -> Option<Vec<(String, f32)>> {
+    let accept = match req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(a) if !a.trim().is_empty() => a,
+        _ => return None,
+    };
+    let mut ranges = Vec::new();
+    for part in accept.split(',') {
+        let mut params = part.split(';');
+        let media = params.next().unwrap_or("").trim();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if q > 0.0 {
+            ranges.push((media.to_ascii_lowercase(), q));
+        }
+    }
+    Some(ranges)
+}
+
+// Whether the client takes `media`, by name or through a wildcard.
+pub fn accepts(req: &HttpRequest, media: &str) -> bool {
+    let kind = media.split('/').next().unwrap_or("");
+    accepted(req).is_none_or(|ranges| {
+        ranges.iter().any(|(r, _)| {
+            r == "*/*" || r.eq_ignore_ascii_case(media) || *r == format!("{}/*", kind)
+        })
+    })
+}
+
+// A response body that can be rendered in every supported format.
+pub trait Reply: Serialize {
+    // Root element name in XML output.
+    const NAME: &'static str;
+
+    // The whole answer on one line, for shell scripts.
+    fn text(&self) -> String;
+}
+
+pub fn reply<T: Reply>(req: &HttpRequest, status: StatusCode, body: &T) -> HttpResponse {
+    match Format::from_request(req) {
+        Some(format) => match render(format, body) {
+            Ok(bytes) => HttpResponse::build(status)
+                .content_type(format.content_type())
+                .body(bytes),
+            Err(e) => {
+                println!("failed to render {} as {:?}: {}", T::NAME, format, e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        None => not_acceptable(),
+    }
+}
+
+// For answers whose format a protocol fixes, like Grafana's or the
+// GTFS-Realtime debugging feeds: JSON or nothing.
+pub fn json<T: Serialize>(req: &HttpRequest, status: StatusCode, body: &T) -> HttpResponse {
+    match accepts(req, "application/json") {
+        true => HttpResponse::build(status).json(body),
+        false => not_acceptable_for(&["application/json"]),
+    }
+}
+
+pub fn not_acceptable() -> HttpResponse {
+    not_acceptable_for(&supported_media_types())
+}
+
+pub fn not_acceptable_for(types: &[&str]) -> HttpResponse {
+    HttpResponse::NotAcceptable()
+        .content_type("text/plain; charset=utf-8")
+        .body(format!("supported types: {}\n", types.join(", ")))
+}
+
+fn render<T: Reply>(format: Format, body: &T) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Json => serde_json::to_vec(body).map_err(|e| e.to_string()),
+        Format::Xml => quick_xml::se::to_string_with_root(T::NAME, body)
+            .map(|xml| {
+                format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}\n", xml).into_bytes()
+            })
+            .map_err(|e| e.to_string()),
+        Format::Csv => {
+            let value = serde_json::to_value(body).map_err(|e| e.to_string())?;
+            csv_table(&value)
+        }
+        Format::MessagePack => rmp_serde::to_vec_named(body).map_err(|e| e.to_string()),
+        Format::Text => Ok(format!("{}\n", body.text()).into_bytes()),
+    }
+}
+
+// One row per element of the first list of records in the body (arrivals,
+// legs, ...), with the surrounding fields repeated on each row. Nested
+// objects become dotted column names.
+fn csv_table(value: &Value) -> Result<Vec<u8>, String> {
+    let top = match value {
+        Value::Object(map) => map.clone(),
+        other => {
+            let mut map = Map::new();
+            map.insert("value".to_string(), other.clone());
+            map
+        }
+    };
+    let list = top.iter().find_map(|(k, v)| match v {
+        Value::Array(items) if items.iter().all(|i| i.is_object()) => {
+            Some((k.clone(), items.clone()))
+        }
+        _ => None,
+    });
+    let mut shared = Vec::new();
+    for (k, v) in top.iter() {
+        if list.as_ref().is_none_or(|(name, _)| name != k) {
+            flatten(k, v, &mut shared);
+        }
+    }
+    let rows: Vec<Vec<(String, String)>> = match list {
+        Some((name, items)) if !items.is_empty() => items
+            .iter()
+            .map(|item| {
+                let mut row = shared.clone();
+                flatten(&name, item, &mut row);
+                row
+            })
+            .collect(),
+        _ => vec![shared],
+    };
+
+    let mut headers: Vec<String> = Vec::new();
+    for row in rows.iter() {
+        for (k, _) in row.iter() {
+            if !headers.contains(k) {
+                headers.push(k.clone());
+            }
+        }
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&headers).map_err(|e| e.to_string())?;
+    for row in rows.iter() {
+        let record: Vec<&str> = headers
+            .iter()
+            .map(|h| {
+                row.iter()
+                    .find(|(k, _)| k == h)
+                    .map_or("", |(_, v)| v.as_str())
+            })
+            .collect();
+        writer.write_record(&record).map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter() {
+                flatten(&format!("{}.{}", prefix, k), v, out);
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                flatten(&format!("{}.{}", prefix, i), v, out);
+            }
+        }
+        Value::Null => out.push((prefix.to_string(), String::new())),
+        Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        other => out.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[derive(Serialize)]
+    struct Stop {
+        id: String,
+        platform: Option<u32>,
+    }
+
+    #[derive(Serialize)]
+    struct Arrival {
+        line: String,
+        time: String,
+        stop: Stop,
+    }
+
+    #[derive(Serialize)]
+    struct Board {
+        station: String,
+        arrivals: Vec<Arrival>,
+    }
+
+    impl Reply for Board {
+        const NAME: &'static str = "board";
+
+        fn text(&self) -> String {
+            self.station.clone()
+        }
+    }
+
+    fn board() -> Board {
+        let arrival = |line: &str, time: &str, platform| Arrival {
+            line: line.to_string(),
+            time: time.to_string(),
+            stop: Stop {
+                id: "cwe".to_string(),
+                platform,
+            },
+        };
+        Board {
+            station: "cwe".to_string(),
+            arrivals: vec![
+                arrival("red", "1:34P", Some(2)),
+                arrival("blue", "1:41P", None),
+            ],
+        }
+    }
+
+    fn format(accept: &str) -> Option<Format> {
+        Format::from_request(
+            &TestRequest::default()
+                .header("Accept", accept)
+                .to_http_request(),
+        )
+    }
+
+    #[test]
+    fn no_accept_header_gets_json() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(Format::from_request(&req), Some(Format::Json));
+        assert!(accepts(&req, "application/x-protobuf"));
+    }
+
+    #[test]
+    fn highest_quality_wins() {
+        assert_eq!(
+            format("text/csv;q=0.5, application/xml;q=0.9"),
+            Some(Format::Xml)
+        );
+        assert_eq!(
+            format("text/plain; q=0.2, application/msgpack"),
+            Some(Format::MessagePack)
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_type_listed_first() {
+        assert_eq!(format("text/csv, application/json"), Some(Format::Csv));
+        assert_eq!(
+            format("application/json;q=0.5, text/csv;q=0.5"),
+            Some(Format::Json)
+        );
+    }
+
+    #[test]
+    fn wildcards_pick_a_default() {
+        assert_eq!(format("*/*"), Some(Format::Json));
+        assert_eq!(format("application/*"), Some(Format::Json));
+        assert_eq!(format("text/*"), Some(Format::Text));
+        assert_eq!(format("image/png, */*;q=0.1"), Some(Format::Json));
+    }
+
+    #[test]
+    fn zero_quality_rules_a_type_out() {
+        assert_eq!(format("application/json;q=0, text/csv"), Some(Format::Csv));
+        assert_eq!(format("application/json;q=0"), None);
+    }
+
+    #[test]
+    fn unsupported_types_get_a_406() {
+        let req = TestRequest::default()
+            .header("Accept", "image/png, application/pdf")
+            .to_http_request();
+        assert_eq!(Format::from_request(&req), None);
+        let res = reply(&req, StatusCode::OK, &board());
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+
+        let req = TestRequest::default()
+            .header("Accept", "text/*")
+            .to_http_request();
+        assert!(accepts(&req, "text/csv"));
+        assert!(!accepts(&req, "application/json"));
+        let res = json(&req, StatusCode::OK, &board());
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_record() {
+        let csv = String::from_utf8(render(Format::Csv, &board()).unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "station,arrivals.line,arrivals.time,arrivals.stop.id,arrivals.stop.platform\n\
+             cwe,red,1:34P,cwe,2\n\
+             cwe,blue,1:41P,cwe,\n"
+        );
+    }
+
+    #[test]
+    fn csv_without_records_is_one_row() {
+        let value = serde_json::json!({ "station": "cwe", "lines": ["red", "blue"] });
+        let csv = String::from_utf8(csv_table(&value).unwrap()).unwrap();
+        assert_eq!(csv, "station,lines.0,lines.1\ncwe,red,blue\n");
+    }
+
+    #[test]
+    fn msgpack_keeps_field_names() {
+        let bytes = render(Format::MessagePack, &board()).unwrap();
+        let decoded: Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, serde_json::to_value(board()).unwrap());
+    }
+
+    #[test]
+    fn xml_is_rooted_at_the_reply_name() {
+        let xml = String::from_utf8(render(Format::Xml, &board()).unwrap()).unwrap();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<board>"));
+        assert!(xml.contains("<station>cwe</station>"));
+        assert!(xml.contains("<arrivals><line>red</line><time>1:34P</time>"));
+        assert!(xml.trim_end().ends_with("</board>"));
+    }
+}
@@ -0,0 +1,741 @@
+use crate::schedule::{DayType, Direction, STATIONS};
+use crate::{alerts, ask, events, headways, negotiate, span, speech, v1, v2, versions, Static};
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use serde_json::{json, Map, Value};
+
+// Field schemas shared by the request and response types.
+pub fn station_schema(_: &mut SchemaGenerator) -> Schema {
+    let mut names: Vec<&str> = Vec::new();
+    for station in STATIONS.iter() {
+        names.push(station.id);
+        names.extend(station.aliases.iter().filter(|a| **a != station.id));
+    }
+    schema(json!({
+        "type": "string",
+        "description": "station id or one of its short names",
+        "enum": names,
+    }))
+}
+
+pub fn direction_schema(_: &mut SchemaGenerator) -> Schema {
+    let directions: Vec<&str> = Direction::ALL.iter().map(|d| d.as_str()).collect();
+    schema(json!({ "type": "string", "enum": directions }))
+}
+
+pub fn time_schema(_: &mut SchemaGenerator) -> Schema {
+    schema(json!({
+        "type": "string",
+        "description": "12-hour schedule time with A or P, e.g. 1:34P",
+        "pattern": "^(1[0-2]|[1-9]):[0-5][0-9][AP]$",
+        "example": "1:34P",
+    }))
+}
+
+pub fn line_schema(_: &mut SchemaGenerator) -> Schema {
+    schema(json!({ "type": "string", "enum": ["red", "blue"] }))
+}
+
+fn schema(value: Value) -> Schema {
+    serde_json::from_value(value).expect("hand-written schema is valid")
+}
+
+fn error(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn json_body(schema: &Schema) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+// Bodies can be rendered in any format the client accepts; CSV and plain text
+// are flattened renderings of the same data.
+fn json_response(description: &str, schema: &Schema) -> Value {
+    let mut content = Map::new();
+    for media in negotiate::supported_media_types() {
+        let entry = match media {
+            "text/csv" | "text/plain" => json!({ "schema": { "type": "string" } }),
+            _ => json!({ "schema": schema }),
+        };
+        content.insert(media.to_string(), entry);
+    }
+    json!({ "description": description, "content": content })
+}
+
+// Adds a 406 answer to every operation that goes through content negotiation.
+fn add_not_acceptable(paths: &mut Map<String, Value>) {
+    for item in paths.values_mut() {
+        for operation in item
+            .as_object_mut()
+            .into_iter()
+            .flat_map(|m| m.values_mut())
+        {
+            let negotiated = operation["responses"].as_object().is_some_and(|r| {
+                r.values()
+                    .any(|v| !v["content"]["application/xml"].is_null())
+            });
+            if negotiated {
+                operation["responses"]["406"] =
+                    error("none of the accepted media types are supported");
+            }
+        }
+    }
+}
+
+// A body whose format the protocol fixes, like a webhook's or a feed's.
+fn fixed_response(description: &str, media: &str, schema: &Value) -> Value {
+    json!({ "description": description, "content": { media: { "schema": schema } } })
+}
+
+fn with_headers(mut response: Value, headers: &[(&str, &str)]) -> Value {
+    for (name, description) in headers {
+        response["headers"][*name] =
+            json!({ "description": description, "schema": { "type": "string" } });
+    }
+    response
+}
+
+fn calendar_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/calendar": { "schema": { "type": "string" } } },
+    })
+}
+
+fn path_param(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+fn query_param(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "schema": schema })
+}
+
+fn closed_or_no_trip() -> Value {
+    with_headers(
+        error("no train arrives by that time, or a station on the trip is closed"),
+        &[(
+            "Service-Closure",
+            "the closure notice and what to do instead",
+        )],
+    )
+}
+
+fn v1_paths(gen: &mut SchemaGenerator) -> Map<String, Value> {
+    let next_arrival_request = gen.subschema_for::<v1::NextArrivalRequest>();
+    let next_arrival_response = gen.subschema_for::<v1::NextArrivalResponse>();
+    let arrive_by_request = gen.subschema_for::<v1::ArriveByRequest>();
+    let trip_plan_response = gen.subschema_for::<v1::TripPlanResponse>();
+    let station = station_schema(gen);
+    let direction = direction_schema(gen);
+    let days: Vec<&str> = DayType::ALL.iter().map(|d| d.as_str()).collect();
+
+    let paths = json!({
+        "/next-arrival": {
+            "post": {
+                "summary": "Next scheduled train at a station",
+                "operationId": "nextArrival",
+                "requestBody": json_body(&next_arrival_request),
+                "responses": {
+                    "200": with_headers(json_response("the next departure", &next_arrival_response), &[
+                        ("Schedule-Version", "the schedule version the train runs under"),
+                        ("Next-Service-Day", "the train's service date when it isn't today"),
+                        ("Last-Train", "true when it's the last train on the line tonight"),
+                        ("Service-Alerts", "ids of the alerts affecting the train, see /alerts"),
+                    ]),
+                    "400": error("direction must be 'east' or 'west', or the body isn't a request"),
+                    "404": with_headers(
+                        error("the station is closed, or no trains are scheduled in the next week"),
+                        &[("Service-Closure", "the closure notice and what to do instead")],
+                    ),
+                    "500": error("unknown station"),
+                },
+            },
+        },
+        "/arrive-by": {
+            "post": {
+                "summary": "Latest departure that reaches a destination in time",
+                "operationId": "arriveBy",
+                "requestBody": json_body(&arrive_by_request),
+                "responses": {
+                    "200": with_headers(json_response("the trip plan", &trip_plan_response), &[
+                        ("Service-Alerts", "ids of the alerts affecting the trip, see /alerts"),
+                    ]),
+                    "400": error("unknown station, arrive_by is not a schedule time, or the body isn't a request"),
+                    "404": closed_or_no_trip(),
+                },
+            },
+        },
+        "/arrive-by.ics": {
+            "post": {
+                "summary": "Arrive-by trip plan as an iCalendar file",
+                "operationId": "arriveByIcs",
+                "requestBody": json_body(&arrive_by_request),
+                "responses": {
+                    "200": calendar_response("one event per leg"),
+                    "400": error("unknown station, arrive_by is not a schedule time, or the body isn't a request"),
+                    "404": closed_or_no_trip(),
+                },
+            },
+        },
+        "/timetable/{station}/{direction}/{day}.ics": {
+            "get": {
+                "summary": "Weekly recurring departures from a station",
+                "operationId": "timetableIcs",
+                "parameters": [
+                    path_param("station", json!(station)),
+                    path_param("direction", json!(direction)),
+                    path_param("day", json!({ "type": "string", "enum": days })),
+                ],
+                "responses": {
+                    "200": calendar_response("one recurring event per departure"),
+                    "400": error("direction or day type is not recognised"),
+                    "404": error("that station is not in the schedule"),
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+fn v2_paths(gen: &mut SchemaGenerator) -> Map<String, Value> {
+    let next_arrival_request = gen.subschema_for::<v2::NextArrivalRequest>();
+    let next_arrival_response = gen.subschema_for::<v2::NextArrivalResponse>();
+    let arrive_by_request = gen.subschema_for::<v2::ArriveByRequest>();
+    let trip_plan = gen.subschema_for::<v2::TripPlan>();
+    let error_body = gen.subschema_for::<v2::ErrorBody>();
+    let speech_response = gen.subschema_for::<speech::SpeechResponse>();
+
+    let paths = json!({
+        "/next-arrival": {
+            "post": {
+                "summary": "Upcoming trains at a station with headsigns",
+                "operationId": "nextArrivalV2",
+                "requestBody": json_body(&next_arrival_request),
+                "responses": {
+                    "200": json_response("the next departures", &next_arrival_response),
+                    "400": json_response("invalid_direction, or a plain 400 when the body isn't a request", &error_body),
+                    "404": json_response("unknown_station, or no_service including at a closed station", &error_body),
+                },
+            },
+        },
+        "/next-arrival/speech": {
+            "post": {
+                "summary": "The next two trains as a sentence and as SSML for text-to-speech",
+                "operationId": "nextArrivalSpeech",
+                "requestBody": json_body(&next_arrival_request),
+                "responses": {
+                    "200": json_response("the sentence", &speech_response),
+                    "400": json_response("invalid_direction, or a plain 400 when the body isn't a request", &error_body),
+                    "404": json_response("unknown_station", &error_body),
+                },
+            },
+        },
+        "/arrive-by": {
+            "post": {
+                "summary": "Latest departure that reaches a destination in time",
+                "operationId": "arriveByV2",
+                "requestBody": json_body(&arrive_by_request),
+                "responses": {
+                    "200": json_response("the trip plan", &trip_plan),
+                    "400": json_response("invalid_time, or a plain 400 when the body isn't a request", &error_body),
+                    "404": json_response("unknown_station, no_trip, or no_service at a closed station", &error_body),
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+// Mounted only when an admin token is configured.
+fn admin_paths(gen: &mut SchemaGenerator) -> Map<String, Value> {
+    let alert = gen.subschema_for::<alerts::Alert>();
+    let error_body = gen.subschema_for::<v2::ErrorBody>();
+    let id = json!({ "type": "string" });
+
+    let paths = json!({
+        "/admin/alerts": {
+            "post": {
+                "summary": "Post a service alert",
+                "operationId": "createAlert",
+                "security": [{ "adminToken": [] }],
+                "requestBody": json_body(&alert),
+                "responses": {
+                    "201": json_response("the alert as stored", &alert),
+                    "400": json_response("invalid_alert, or a plain 400 when the body isn't an alert", &error_body),
+                    "401": json_response("unauthorized", &error_body),
+                    "409": json_response("duplicate_id", &error_body),
+                },
+            },
+        },
+        "/admin/alerts/{id}": {
+            "put": {
+                "summary": "Replace a posted service alert",
+                "operationId": "replaceAlert",
+                "security": [{ "adminToken": [] }],
+                "parameters": [path_param("id", id.clone())],
+                "requestBody": json_body(&alert),
+                "responses": {
+                    "200": json_response("the alert as stored", &alert),
+                    "400": json_response("invalid_alert, or a plain 400 when the body isn't an alert", &error_body),
+                    "401": json_response("unauthorized", &error_body),
+                    "404": json_response("unknown_alert", &error_body),
+                },
+            },
+            "delete": {
+                "summary": "Withdraw a posted service alert",
+                "operationId": "deleteAlert",
+                "security": [{ "adminToken": [] }],
+                "parameters": [path_param("id", id)],
+                "responses": {
+                    "204": { "description": "the alert is gone" },
+                    "401": json_response("unauthorized", &error_body),
+                    "404": json_response("unknown_alert", &error_body),
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+// Feeds whose formats a protocol fixes, so they aren't negotiated.
+fn feed_paths() -> Map<String, Value> {
+    let object = json!({ "type": "object" });
+    let json_only = error("the client doesn't accept application/json");
+    let not_json = error("the body isn't a request");
+
+    let paths = json!({
+        "/gtfs-rt/{feed}.{format}": {
+            "get": {
+                "summary": "The schedule and alerts as a GTFS-Realtime feed",
+                "operationId": "gtfsRealtime",
+                "parameters": [
+                    path_param("feed", json!({ "type": "string", "enum": ["trip-updates", "vehicle-positions", "alerts"] })),
+                    path_param("format", json!({ "type": "string", "enum": ["pb", "json"] })),
+                ],
+                "responses": {
+                    "200": {
+                        "description": "a FeedMessage, encoded as protobuf for pb and as JSON for json",
+                        "content": {
+                            "application/x-protobuf": { "schema": { "type": "string", "format": "binary" } },
+                            "application/json": { "schema": object },
+                        },
+                    },
+                    "404": error("unknown feed or format"),
+                    "406": error("the client doesn't accept the format it asked for"),
+                },
+            },
+        },
+        "/grafana/": {
+            "get": {
+                "summary": "Grafana JSON datasource health check",
+                "operationId": "grafanaHealth",
+                "responses": {
+                    "200": { "description": "the datasource is up" },
+                },
+            },
+        },
+        "/grafana/search": {
+            "post": {
+                "summary": "Grafana JSON datasource metric names",
+                "operationId": "grafanaSearch",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": object } } },
+                "responses": {
+                    "200": fixed_response("the station and direction targets", "application/json", &json!({
+                        "type": "array",
+                        "items": { "type": "string" },
+                    })),
+                    "400": not_json,
+                    "406": json_only,
+                },
+            },
+        },
+        "/grafana/query": {
+            "post": {
+                "summary": "Grafana JSON datasource departures per hour",
+                "operationId": "grafanaQuery",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": object } } },
+                "responses": {
+                    "200": fixed_response("one time series per target", "application/json", &json!({
+                        "type": "array",
+                        "items": object,
+                    })),
+                    "400": not_json,
+                    "406": json_only,
+                },
+            },
+        },
+        "/grafana/annotations": {
+            "post": {
+                "summary": "Grafana JSON datasource first and last trains, and service alerts",
+                "operationId": "grafanaAnnotations",
+                "requestBody": { "required": true, "content": { "application/json": { "schema": object } } },
+                "responses": {
+                    "200": fixed_response("annotations in the requested range", "application/json", &json!({
+                        "type": "array",
+                        "items": object,
+                    })),
+                    "400": not_json,
+                    "406": json_only,
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+// The chat and voice platforms call these; each answers in its platform's own
+// format. All but /sms and /alexa are mounted only when configured.
+fn webhook_paths() -> Map<String, Value> {
+    let object = json!({ "type": "object" });
+    let json_request =
+        json!({ "required": true, "content": { "application/json": { "schema": object } } });
+    let form_request = json!({
+        "required": true,
+        "content": { "application/x-www-form-urlencoded": { "schema": object } },
+    });
+
+    let paths = json!({
+        "/sms": {
+            "post": {
+                "summary": "Twilio inbound SMS",
+                "operationId": "sms",
+                "requestBody": form_request,
+                "responses": {
+                    "200": fixed_response("the reply as TwiML", "text/xml", &json!({ "type": "string" })),
+                    "400": error("the body isn't a form"),
+                    "403": error("invalid Twilio signature"),
+                },
+            },
+        },
+        "/alexa": {
+            "post": {
+                "summary": "Alexa skill requests",
+                "operationId": "alexa",
+                "requestBody": json_request,
+                "responses": {
+                    "200": fixed_response("the Alexa response envelope", "application/json", &object),
+                    "400": error("invalid Alexa signature, not an Alexa request, or invalid Alexa request"),
+                },
+            },
+        },
+        "/dialogflow/es": {
+            "post": {
+                "summary": "Dialogflow ES fulfillment",
+                "operationId": "dialogflowEs",
+                "security": [{ "dialogflowAuth": [] }],
+                "requestBody": json_request,
+                "responses": {
+                    "200": fixed_response("the fulfillment text", "application/json", &object),
+                    "400": error("the body isn't a webhook request"),
+                    "401": error("invalid Dialogflow credentials"),
+                },
+            },
+        },
+        "/dialogflow/cx": {
+            "post": {
+                "summary": "Dialogflow CX webhook",
+                "operationId": "dialogflowCx",
+                "security": [{ "dialogflowAuth": [] }],
+                "requestBody": json_request,
+                "responses": {
+                    "200": fixed_response("the fulfillment messages", "application/json", &object),
+                    "400": error("the body isn't a webhook request"),
+                    "401": error("invalid Dialogflow credentials"),
+                },
+            },
+        },
+        "/slack/commands": {
+            "post": {
+                "summary": "Slack slash commands",
+                "operationId": "slackCommand",
+                "requestBody": form_request,
+                "responses": {
+                    "200": fixed_response("the message to post", "application/json", &object),
+                    "400": error("not a slash command"),
+                    "401": error("invalid Slack signature"),
+                },
+            },
+        },
+        "/discord/interactions": {
+            "post": {
+                "summary": "Discord slash command interactions",
+                "operationId": "discordInteraction",
+                "requestBody": json_request,
+                "responses": {
+                    "200": fixed_response("a pong, or the message to post", "application/json", &object),
+                    "400": error("not an interaction, or unsupported interaction type"),
+                    "401": error("invalid request signature"),
+                },
+            },
+        },
+        "/telegram/{secret}": {
+            "post": {
+                "summary": "Telegram bot updates; replies are sent through the Bot API",
+                "operationId": "telegram",
+                "parameters": [path_param("secret", json!({ "type": "string" }))],
+                "requestBody": json_request,
+                "responses": {
+                    "200": { "description": "the update was handled, even if replying failed" },
+                    "400": error("the body isn't an update"),
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+fn docs_paths() -> Map<String, Value> {
+    let paths = json!({
+        "/openapi.json": {
+            "get": {
+                "summary": "This document",
+                "operationId": "openapi",
+                "responses": {
+                    "200": fixed_response("the OpenAPI document", "application/json", &json!({ "type": "object" })),
+                    "406": error("the client doesn't accept application/json"),
+                },
+            },
+        },
+        "/docs": {
+            "get": {
+                "summary": "An API explorer for this document",
+                "operationId": "explorer",
+                "responses": {
+                    "200": fixed_response("the explorer page", "text/html", &json!({ "type": "string" })),
+                },
+            },
+        },
+        "/docs/{file}": {
+            "get": {
+                "summary": "The explorer's script and stylesheet",
+                "operationId": "explorerAsset",
+                "parameters": [path_param("file", json!({ "type": "string", "enum": ["explorer.js", "explorer.css"] }))],
+                "responses": {
+                    "200": {
+                        "description": "the file",
+                        "content": {
+                            "text/javascript": { "schema": { "type": "string" } },
+                            "text/css": { "schema": { "type": "string" } },
+                        },
+                    },
+                    "404": error("no such file"),
+                },
+            },
+        },
+    });
+    match paths {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for (path, item) in v1_paths(&mut gen) {
+        paths.insert(format!("/v1{}", path), item.clone());
+        // The unversioned aliases answer exactly like v1.
+        let mut alias = item;
+        for operation in alias
+            .as_object_mut()
+            .into_iter()
+            .flat_map(|m| m.values_mut())
+        {
+            operation["deprecated"] = json!(true);
+            if let Some(id) = operation["operationId"]
+                .as_str()
+                .map(|id| format!("{}Unversioned", id))
+            {
+                operation["operationId"] = json!(id);
+            }
+        }
+        paths.insert(path, alias);
+    }
+    for (path, item) in v2_paths(&mut gen) {
+        paths.insert(format!("/v2{}", path), item);
+    }
+    let ask_request = gen.subschema_for::<ask::AskRequest>();
+    let ask_response = gen.subschema_for::<ask::AskResponse>();
+    let error_body = gen.subschema_for::<v2::ErrorBody>();
+    paths.insert(
+        "/ask".to_string(),
+        json!({
+            "post": {
+                "summary": "Answer a free-text question about trains",
+                "operationId": "ask",
+                "requestBody": json_body(&ask_request),
+                "responses": {
+                    "200": json_response("the answer and how the question was read", &ask_response),
+                    "400": error("the body isn't a request"),
+                    "422": json_response("no_station", &error_body),
+                },
+            },
+        }),
+    );
+    let versions_response = gen.subschema_for::<versions::VersionsResponse>();
+    paths.insert(
+        "/schedule/versions".to_string(),
+        json!({
+            "get": {
+                "summary": "List the loaded schedule versions and their effective dates",
+                "operationId": "scheduleVersions",
+                "responses": {
+                    "200": json_response("the versions and the one in effect today", &versions_response),
+                },
+            },
+        }),
+    );
+    let alerts_response = gen.subschema_for::<alerts::AlertsResponse>();
+    let station = station_schema(&mut gen);
+    let direction = direction_schema(&mut gen);
+    let line = line_schema(&mut gen);
+    paths.insert(
+        "/alerts".to_string(),
+        json!({
+            "get": {
+                "summary": "List service alerts that haven't ended",
+                "operationId": "alerts",
+                "parameters": [
+                    query_param("station", json!(station)),
+                    query_param("line", json!(line)),
+                    query_param("direction", json!(direction)),
+                ],
+                "responses": {
+                    "200": json_response("alerts in effect now or later", &alerts_response),
+                    "400": json_response("invalid_line or invalid_direction", &error_body),
+                    "404": json_response("unknown_station", &error_body),
+                },
+            },
+        }),
+    );
+    let events_response = gen.subschema_for::<events::EventsResponse>();
+    paths.insert(
+        "/events".to_string(),
+        json!({
+            "get": {
+                "summary": "List upcoming special events and the extra trains they add",
+                "operationId": "events",
+                "responses": {
+                    "200": json_response("events from today's service date on", &events_response),
+                },
+            },
+        }),
+    );
+    let span_response = gen.subschema_for::<span::SpanResponse>();
+    paths.insert(
+        "/stations/{id}/span".to_string(),
+        json!({
+            "get": {
+                "summary": "First and last trains at a station for each day type, direction and line",
+                "operationId": "stationSpan",
+                "parameters": [path_param("id", json!(station))],
+                "responses": {
+                    "200": json_response("the service span from the schedule version in effect today", &span_response),
+                    "404": json_response("unknown_station", &error_body),
+                },
+            },
+        }),
+    );
+    let headways_response = gen.subschema_for::<headways::HeadwaysResponse>();
+    paths.insert(
+        "/stations/{id}/headways".to_string(),
+        json!({
+            "get": {
+                "summary": "Gaps between trains at a station by hour, for each day type, direction and line",
+                "operationId": "stationHeadways",
+                "parameters": [path_param("id", json!(station))],
+                "responses": {
+                    "200": json_response("headways from the schedule version in effect today", &headways_response),
+                    "404": json_response("unknown_station", &error_body),
+                },
+            },
+        }),
+    );
+    paths.extend(admin_paths(&mut gen));
+    add_not_acceptable(&mut paths);
+    paths.extend(feed_paths());
+    paths.extend(webhook_paths());
+    paths.extend(docs_paths());
+    paths.insert(
+        "/metrics".to_string(),
+        json!({
+            "get": {
+                "summary": "Prometheus metrics",
+                "operationId": "metrics",
+                "responses": {
+                    "200": {
+                        "description": "metrics in the Prometheus text format",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                },
+            },
+        }),
+    );
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Metro Schedule API",
+            "description": "Scheduled arrival times for St. Louis MetroLink trains.",
+            "version": env!("CARGO_PKG_VERSION"),
+            "license": { "name": "MIT" },
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" },
+                "dialogflowAuth": { "type": "http", "scheme": "basic" },
+            },
+        },
+    })
+}
+
+#[get("/openapi.json")]
+pub async fn openapi_json(http: HttpRequest) -> HttpResponse {
+    negotiate::json(&http, StatusCode::OK, &document())
+}
+
+#[get("/docs")]
+pub async fn explorer() -> HttpResponse {
+    match Static::get("explorer.html") {
+        Some(page) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(page.into_owned()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// The explorer's script and stylesheet, so the page needs no other site.
+#[get("/docs/{file}")]
+pub async fn explorer_asset(path: web::Path<String>) -> HttpResponse {
+    let file = path.into_inner();
+    let content_type = match file.as_str() {
+        "explorer.js" => "text/javascript; charset=utf-8",
+        "explorer.css" => "text/css; charset=utf-8",
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    match Static::get(&file) {
+        Some(asset) => HttpResponse::Ok()
+            .content_type(content_type)
+            .body(asset.into_owned()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
@@ -0,0 +1,355 @@
+use crate::events::{self, Event};
+use crate::gtfs_rt;
+use crate::schedule::{
+    self, DayType, Direction, Line, Schedule, Timetable, Trip, Version, STATIONS,
+};
+use chrono::{Duration, NaiveDate};
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Temporary changes laid over the schedule versions for a few service dates,
+// like a bus bridge around stations closed for track work or a thinned-out
+// timetable. They are kept in their own JSON file, which is reloaded along
+// with the schedules, so they come and go without touching the CSVs.
+
+// Anything longer is a new schedule version.
+const MAX_DAYS: i64 = 366;
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Overlay {
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    // Service dates, both inclusive.
+    pub effective_from: NaiveDate,
+    pub effective_until: NaiveDate,
+    // Trains run through these without stopping.
+    #[serde(default)]
+    pub closed_stations: Vec<String>,
+    // What riders at a closed station should do instead.
+    #[serde(default)]
+    pub alternative: String,
+    // Trip ids like "base-weekday-east-12", the ones the GTFS-Realtime feeds use.
+    #[serde(default)]
+    pub suppressed_trips: Vec<String>,
+    #[serde(default)]
+    pub extra_trips: Vec<ExtraTrip>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExtraTrip {
+    // Day types it runs on; every one when left out.
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub direction: String,
+    pub line: String,
+    // Station to "1:34P"-style time.
+    pub times: BTreeMap<String, String>,
+}
+
+impl Overlay {
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        self.effective_from <= date && date <= self.effective_until
+    }
+
+    pub fn closes(&self, station: usize) -> bool {
+        self.closed_stations
+            .iter()
+            .any(|s| s == STATIONS[station].id)
+    }
+
+    // Station names become ids, and anything unknown is refused.
+    fn check(&mut self) -> Result<(), String> {
+        if self.id.is_empty() {
+            return Err("id is required".to_string());
+        }
+        if self.effective_until < self.effective_from {
+            return Err("effective_until is before effective_from".to_string());
+        }
+        if self.effective_until - self.effective_from >= Duration::days(MAX_DAYS) {
+            return Err(format!(
+                "longer than {} days; make it a schedule version instead",
+                MAX_DAYS
+            ));
+        }
+        for station in self.closed_stations.iter_mut() {
+            *station = station_id(station)?;
+        }
+        if let Some(id) = self
+            .suppressed_trips
+            .iter()
+            .find(|id| gtfs_rt::parse_trip_id(id).is_none())
+        {
+            return Err(format!(
+                "'{}' is not a trip id like base-weekday-east-12",
+                id
+            ));
+        }
+        for extra in self.extra_trips.iter_mut() {
+            extra.check()?;
+        }
+        Ok(())
+    }
+}
+
+impl ExtraTrip {
+    pub fn check(&mut self) -> Result<(), String> {
+        if let Some(day) = self.days.iter().find(|d| DayType::parse(d).is_none()) {
+            return Err(format!(
+                "day must be 'weekday', 'saturday' or 'sunday', not '{}'",
+                day
+            ));
+        }
+        if Direction::parse(&self.direction).is_none() {
+            return Err(format!(
+                "direction must be 'east' or 'west', not '{}'",
+                self.direction
+            ));
+        }
+        if Line::parse(&self.line).is_none() {
+            return Err(format!("line must be 'red' or 'blue', not '{}'", self.line));
+        }
+        let mut times = BTreeMap::new();
+        for (station, time) in self.times.iter() {
+            if schedule::parse_time(time).is_none() {
+                return Err(format!("bad time '{}' at {}", time, station));
+            }
+            times.insert(station_id(station)?, time.clone());
+        }
+        if times.len() < 2 {
+            return Err("an extra trip needs at least two stops".to_string());
+        }
+        self.times = times;
+        Ok(())
+    }
+
+    pub fn runs(&self, day: DayType, direction: Direction) -> bool {
+        Direction::parse(&self.direction) == Some(direction)
+            && (self.days.is_empty() || self.days.iter().any(|d| DayType::parse(d) == Some(day)))
+    }
+
+    // The trip as it goes into a schedule, numbered `row`.
+    pub fn trip(
+        &self,
+        schedule: &Schedule,
+        row: usize,
+        event: Option<&str>,
+    ) -> Result<Trip, String> {
+        let mut times = vec![None; STATIONS.len()];
+        for (station, time) in self.times.iter() {
+            let station = schedule::find_station(station).expect("checked on load");
+            if !schedule.order.contains(&station) {
+                return Err(format!(
+                    "an extra {}bound trip stops at {}, which isn't in the {} schedule",
+                    schedule.direction.as_str(),
+                    STATIONS[station].id,
+                    schedule.day.as_str()
+                ));
+            }
+            times[station] = schedule::parse_time(time).map(schedule::service_minutes);
+        }
+        Ok(Trip {
+            line: Line::parse(&self.line).expect("checked on load"),
+            row,
+            times,
+            extra: true,
+            event: event.map(String::from),
+        })
+    }
+}
+
+fn station_id(name: &str) -> Result<String, String> {
+    match schedule::find_station(name) {
+        Some(s) => Ok(STATIONS[s].id.to_string()),
+        None => Err(format!("unknown station '{}'", name)),
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<Overlay>, String> {
+    let contents = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut overlays: Vec<Overlay> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    for i in 0..overlays.len() {
+        if overlays[..i].iter().any(|o| o.id == overlays[i].id) {
+            return Err(format!(
+                "{}: overlay '{}' is listed twice",
+                path, overlays[i].id
+            ));
+        }
+        let overlay = &mut overlays[i];
+        overlay
+            .check()
+            .map_err(|e| format!("{}: overlay '{}': {}", path, overlay.id, e))?;
+    }
+    Ok(overlays)
+}
+
+// "Grand Station is closed through November 3. Take the shuttle bus."
+pub fn closed_message(overlay: &Overlay, station: usize) -> String {
+    let mut message = format!(
+        "{} is closed through {}.",
+        STATIONS[station].name,
+        overlay.effective_until.format("%B %-d")
+    );
+    if !overlay.alternative.is_empty() {
+        message.push(' ');
+        message.push_str(&overlay.alternative);
+    }
+    message
+}
+
+// Extra trips come first so closures apply to them too. Trips left with
+// fewer than two stops are dropped. Extra trips are numbered on from the
+// last row of their file.
+fn apply_one(version: &mut Version, overlay: &Overlay) -> Result<(), String> {
+    let closed: Vec<usize> = overlay
+        .closed_stations
+        .iter()
+        .filter_map(|s| schedule::find_station(s))
+        .collect();
+    let id = &version.id;
+    for schedule in version.schedules.iter_mut() {
+        let (day, direction) = (schedule.day, schedule.direction);
+        // Counted before suppressing, so an extra trip never takes the row,
+        // and so the trip id, of a suppressed one.
+        let last = schedule.trips.iter().map(|t| t.row).max().unwrap_or(1);
+        schedule.trips.retain(|trip| {
+            !overlay
+                .suppressed_trips
+                .contains(&gtfs_rt::trip_id(id, day, direction, trip.row))
+        });
+        let extras: Vec<&ExtraTrip> = overlay
+            .extra_trips
+            .iter()
+            .filter(|e| e.runs(day, direction))
+            .collect();
+        for (row, extra) in (last + 1..).zip(extras) {
+            let trip = extra.trip(schedule, row, None)?;
+            schedule.trips.push(trip);
+        }
+        for trip in schedule.trips.iter_mut() {
+            for station in closed.iter() {
+                trip.times[*station] = None;
+            }
+        }
+        schedule
+            .trips
+            .retain(|trip| trip.times.iter().flatten().count() >= 2);
+    }
+    Ok(())
+}
+
+// Lays the overlays and event supplements over the timetable. Every date
+// one covers gets a copy of its version with that date's events added and
+// then its overlays applied in file order, so a closure holds for event
+// trains too. Dates with the same version, events and overlays share a copy.
+pub fn apply(
+    mut tt: Timetable,
+    overlays: Vec<Overlay>,
+    events: Vec<Event>,
+) -> Result<Timetable, String> {
+    for overlay in overlays.iter() {
+        for id in overlay.suppressed_trips.iter() {
+            let (version, day, direction, row) =
+                gtfs_rt::parse_trip_id(id).expect("checked on load");
+            let known = tt
+                .versions
+                .iter()
+                .find(|v| v.id == version)
+                .is_some_and(|v| {
+                    v.schedule(day, direction)
+                        .trips
+                        .iter()
+                        .any(|t| t.row == row)
+                });
+            if !known {
+                return Err(format!(
+                    "overlay '{}': there is no trip '{}'",
+                    overlay.id, id
+                ));
+            }
+        }
+    }
+    let dates: BTreeSet<NaiveDate> = overlays
+        .iter()
+        .flat_map(|o| {
+            o.effective_from
+                .iter_days()
+                .take_while(move |d| *d <= o.effective_until)
+        })
+        .chain(events.iter().map(|e| e.date))
+        .collect();
+    let mut groups: BTreeMap<(usize, Vec<usize>, Vec<usize>), Vec<NaiveDate>> = BTreeMap::new();
+    for date in dates {
+        let active = (0..overlays.len())
+            .filter(|i| overlays[*i].covers(date))
+            .collect();
+        let on = (0..events.len())
+            .filter(|i| events[*i].date == date)
+            .collect();
+        groups
+            .entry((tt.version_index(date), on, active))
+            .or_default()
+            .push(date);
+    }
+    for ((version, on, active), dates) in groups {
+        let mut overlaid = tt.versions[version].clone();
+        for i in on {
+            events::apply_one(&mut overlaid, &events[i])
+                .map_err(|e| format!("event '{}': {}", events[i].id, e))?;
+        }
+        for i in active {
+            apply_one(&mut overlaid, &overlays[i])
+                .map_err(|e| format!("overlay '{}': {}", overlays[i].id, e))?;
+        }
+        for date in dates {
+            tt.overlaid_on.insert(date, tt.overlaid.len());
+        }
+        tt.overlaid.push(overlaid);
+    }
+    tt.overlays = overlays;
+    tt.events = events;
+    Ok(tt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::BASE_VERSION;
+
+    #[test]
+    fn extra_trips_do_not_reuse_a_suppressed_row() {
+        let tt = Timetable::embedded().unwrap();
+        let mut version = tt
+            .versions
+            .iter()
+            .find(|v| v.id == BASE_VERSION)
+            .unwrap()
+            .clone();
+        let last = version
+            .schedule(DayType::Saturday, Direction::East)
+            .trips
+            .iter()
+            .map(|t| t.row)
+            .max()
+            .unwrap();
+        let mut overlay: Overlay = serde_json::from_value(serde_json::json!({
+            "id": "late-swap",
+            "effective_from": "2026-11-07",
+            "effective_until": "2026-11-07",
+            "suppressed_trips": [gtfs_rt::trip_id(BASE_VERSION, DayType::Saturday, Direction::East, last)],
+            "extra_trips": [{ "direction": "east", "line": "red", "times": { "cortex": "11:58P", "civic-center": "12:05A" } }],
+        }))
+        .unwrap();
+        overlay.check().unwrap();
+        apply_one(&mut version, &overlay).unwrap();
+        let rows: Vec<usize> = version
+            .schedule(DayType::Saturday, Direction::East)
+            .trips
+            .iter()
+            .map(|t| t.row)
+            .collect();
+        assert!(!rows.contains(&last));
+        assert!(rows.contains(&(last + 1)));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::schedule::{self, Direction, Line, Timetable, MINUTES_PER_DAY, STATIONS};
+use chrono::{Duration, NaiveDate};
+use std::cmp::Reverse;
+
+// Time allowed to change trains at a shared station.
+pub const TRANSFER_MINUTES: u32 = 2;
+
+pub struct Leg {
+    pub from: usize,
+    pub to: usize,
+    pub line: Line,
+    pub direction: Direction,
+    // Where the train is headed, which may be past the end of the leg.
+    pub headsign: usize,
+    pub departure: u32,
+    pub arrival: u32,
+}
+
+pub struct Plan {
+    pub date: NaiveDate,
+    pub legs: Vec<Leg>,
+    // The schedule version the trains came from.
+    pub version: String,
+}
+
+impl Plan {
+    pub fn departure(&self) -> u32 {
+        self.legs[0].departure
+    }
+
+    pub fn arrival(&self) -> u32 {
+        self.legs[self.legs.len() - 1].arrival
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PlanError {
+    UnknownStation,
+    BadTime,
+    NoTrip,
+    // An overlay closes this station on that service date.
+    Closed(usize, NaiveDate),
+}
+
+impl PlanError {
+    pub fn message(self) -> &'static str {
+        match self {
+            PlanError::UnknownStation => "that station is not in the schedule",
+            PlanError::BadTime => "arrive_by must look like '6:40A'",
+            PlanError::NoTrip => "no train arrives by that time",
+            PlanError::Closed(..) => "that station is closed",
+        }
+    }
+}
+
+// Resolves the station names and "6:40A"-style deadline of an arrive-by
+// request before planning it.
+pub fn arrive_by_request(
+    tt: &Timetable,
+    from: &str,
+    to: &str,
+    arrive_by: &str,
+    date: NaiveDate,
+) -> Result<Plan, PlanError> {
+    let (from, to) = match (schedule::find_station(from), schedule::find_station(to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Err(PlanError::UnknownStation),
+    };
+    let deadline = match schedule::parse_time(arrive_by) {
+        Some(t) => schedule::service_minutes(t),
+        None => return Err(PlanError::BadTime),
+    };
+    // Early-morning deadlines fall at the end of the previous service day.
+    let date = if deadline >= MINUTES_PER_DAY {
+        date - Duration::days(1)
+    } else {
+        date
+    };
+    if let Some(closed) = [from, to]
+        .iter()
+        .copied()
+        .find(|s| tt.closure(*s, date).is_some())
+    {
+        return Err(PlanError::Closed(closed, date));
+    }
+    self::arrive_by(tt, from, to, date, deadline, None).ok_or(PlanError::NoTrip)
+}
+
+// A hop between two consecutive stops of one trip.
+#[derive(Clone, Copy)]
+struct Connection {
+    trip: usize,
+    from: usize,
+    to: usize,
+    departure: u32,
+    arrival: u32,
+}
+
+struct Network {
+    connections: Vec<Connection>,
+    trips: Vec<(Line, Direction, usize)>,
+}
+
+fn network(tt: &Timetable, date: NaiveDate, line: Option<Line>) -> Network {
+    let mut connections = Vec::new();
+    let mut trips = Vec::new();
+    for direction in Direction::ALL.iter() {
+        let schedule = tt.schedule(date, *direction);
+        for trip in schedule.trips.iter() {
+            if line.is_some_and(|l| l != trip.line) {
+                continue;
+            }
+            let id = trips.len();
+            let terminal = match schedule.terminal(trip) {
+                Some(t) => t,
+                None => continue,
+            };
+            trips.push((trip.line, *direction, terminal));
+            let stops: Vec<(usize, u32)> = schedule
+                .order
+                .iter()
+                .filter_map(|s| trip.times[*s].map(|t| (*s, t)))
+                .collect();
+            for pair in stops.windows(2) {
+                connections.push(Connection {
+                    trip: id,
+                    from: pair[0].0,
+                    to: pair[1].0,
+                    departure: pair[0].1,
+                    arrival: pair[1].1,
+                });
+            }
+        }
+    }
+    Network { connections, trips }
+}
+
+// Finds the latest departure from `from` that reaches `to` no later than
+// `deadline` (service-day minutes on `date`). Trips from the previous service
+// day are tried when nothing on `date` arrives in time.
+pub fn arrive_by(
+    tt: &Timetable,
+    from: usize,
+    to: usize,
+    date: NaiveDate,
+    deadline: u32,
+    line: Option<Line>,
+) -> Option<Plan> {
+    if from == to {
+        return None;
+    }
+    match latest_departure(tt, from, to, date, deadline, line) {
+        Some(plan) => Some(plan),
+        None => latest_departure(
+            tt,
+            from,
+            to,
+            date - Duration::days(1),
+            deadline + MINUTES_PER_DAY,
+            line,
+        ),
+    }
+}
+
+// Finds the soonest arrival at `to` leaving `from` no earlier than `start`,
+// then leaves as late as possible while still arriving then. Rolls over to the
+// first trains of the next service day when nothing is left on `date`.
+pub fn depart_after(
+    tt: &Timetable,
+    from: usize,
+    to: usize,
+    date: NaiveDate,
+    start: u32,
+    line: Option<Line>,
+) -> Option<Plan> {
+    if from == to {
+        return None;
+    }
+    let (date, arrival) = match earliest_arrival(tt, from, to, date, start, line) {
+        Some(arrival) => (date, arrival),
+        None => {
+            let next = date + Duration::days(1);
+            (next, earliest_arrival(tt, from, to, next, 0, line)?)
+        }
+    };
+    latest_departure(tt, from, to, date, arrival, line)
+}
+
+// Forward connection scan, returning only the arrival time.
+fn earliest_arrival(
+    tt: &Timetable,
+    from: usize,
+    to: usize,
+    date: NaiveDate,
+    start: u32,
+    line: Option<Line>,
+) -> Option<u32> {
+    let net = network(tt, date, line);
+    let mut connections = net.connections;
+    connections.sort_by_key(|c| c.departure);
+
+    let mut earliest: Vec<Option<u32>> = vec![None; STATIONS.len()];
+    earliest[from] = Some(start);
+    let mut boarded = vec![false; net.trips.len()];
+
+    for c in connections.iter().filter(|c| c.departure >= start) {
+        if earliest[to].is_some_and(|t| c.departure >= t) {
+            break;
+        }
+        if !boarded[c.trip] {
+            let ready = match earliest[c.from] {
+                Some(t) if c.from == from => t <= c.departure,
+                Some(t) => t + TRANSFER_MINUTES <= c.departure,
+                None => false,
+            };
+            boarded[c.trip] = ready;
+        }
+        if boarded[c.trip] && earliest[c.to].is_none_or(|t| c.arrival < t) {
+            earliest[c.to] = Some(c.arrival);
+        }
+    }
+    earliest[to]
+}
+
+// Reverse connection scan: walk hops from latest to earliest departure,
+// tracking the latest time each station can be left while still making it.
+fn latest_departure(
+    tt: &Timetable,
+    from: usize,
+    to: usize,
+    date: NaiveDate,
+    deadline: u32,
+    line: Option<Line>,
+) -> Option<Plan> {
+    let net = network(tt, date, line);
+    let mut connections = net.connections;
+    connections.sort_by_key(|c| Reverse(c.departure));
+
+    let mut latest: Vec<Option<u32>> = vec![None; STATIONS.len()];
+    // For each station, the hop boarded there and the hop where that trip is left.
+    let mut via: Vec<Option<(Connection, Connection)>> = vec![None; STATIONS.len()];
+    let mut exit: Vec<Option<Connection>> = vec![None; net.trips.len()];
+
+    // Hops come in reverse, so a trip's exits are seen from its last stop
+    // back. Unless the trip reaches `to` itself, the earliest stop to change
+    // at wins, rather than riding on and coming back.
+    for c in connections.iter().filter(|c| c.arrival <= deadline) {
+        if c.to == to {
+            exit[c.trip] = Some(*c);
+        } else if exit[c.trip].is_none_or(|out| out.to != to) {
+            let change = match (latest[c.to], via[c.to]) {
+                (Some(t), Some((board, _))) => {
+                    board.trip != c.trip && t >= c.arrival + TRANSFER_MINUTES
+                }
+                _ => false,
+            };
+            if change {
+                exit[c.trip] = Some(*c);
+            }
+        }
+        if let Some(out) = exit[c.trip] {
+            if c.from != to && latest[c.from].is_none_or(|t| c.departure > t) {
+                latest[c.from] = Some(c.departure);
+                via[c.from] = Some((*c, out));
+            }
+        }
+    }
+
+    let mut legs = Vec::new();
+    let mut at = from;
+    while at != to {
+        let (board, out) = via[at]?;
+        let (line, direction, headsign) = net.trips[board.trip];
+        legs.push(Leg {
+            from: at,
+            to: out.to,
+            line,
+            direction,
+            headsign,
+            departure: board.departure,
+            arrival: out.arrival,
+        });
+        at = out.to;
+    }
+    Some(Plan {
+        date,
+        legs,
+        version: tt.version(date).id.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(id: &str) -> usize {
+        schedule::find_station(id).unwrap()
+    }
+
+    // The blue line meets the red one at Forest Park, so the change is there
+    // rather than riding on to Grand and coming back past it.
+    #[test]
+    fn changes_at_the_first_shared_station() {
+        let tt = Timetable::embedded().unwrap();
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let deadline = schedule::parse_time("6:40A").unwrap();
+        let plan = arrive_by(
+            &tt,
+            station("brentwood"),
+            station("lambert"),
+            monday,
+            deadline,
+            None,
+        )
+        .unwrap();
+        let stops: Vec<&str> = plan.legs.iter().map(|l| STATIONS[l.to].id).collect();
+        assert_eq!(stops, ["forest-park", "lambert"]);
+        assert_eq!(plan.legs[0].direction, Direction::East);
+        assert_eq!(plan.legs[1].direction, Direction::West);
+        assert!(plan.arrival() <= deadline);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::alerts::{self, Alerts, Severity};
+use crate::gtfs_rt::{
+    self, EntitySelector, FeedEntity, FeedHeader, FeedMessage, StopTimeEvent, TimeRange,
+};
+use crate::reload::Current;
+use crate::schedule::{self, DayType, Direction, Line, Schedule, Timetable, Trip, STATIONS};
+use crate::{negotiate, v2};
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate};
+
+// Our own GTFS-Realtime feeds, mounted at /gtfs-rt, built from the timetable
+// for apps that only read GTFS-Realtime. Each feed is served as protobuf
+// (.pb) and, for debugging, as JSON (.json). Trip ids are the ones the
+// realtime module matches, and stop ids are our station ids.
+
+const PROTOBUF: &str = "application/x-protobuf";
+
+// How long a train is taken to stand at each station before moving on.
+const DWELL: i64 = 30;
+
+pub struct Config {
+    // Indexed like STATIONS; without coordinates vehicle positions only name
+    // the stop.
+    pub coordinates: Vec<Option<(f64, f64)>>,
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(feed);
+}
+
+// A trip between its first and last stop, with its stops in travel order.
+struct Active<'a> {
+    date: NaiveDate,
+    version: &'a str,
+    schedule: &'a Schedule,
+    trip: &'a Trip,
+    stops: Vec<(usize, u32)>,
+}
+
+impl Active<'_> {
+    fn id(&self) -> String {
+        gtfs_rt::trip_id(
+            self.version,
+            self.schedule.day,
+            self.schedule.direction,
+            self.trip.row,
+        )
+    }
+
+    fn epoch(&self, minutes: u32) -> i64 {
+        v2::local(schedule::service_datetime(self.date, minutes)).timestamp()
+    }
+
+    fn descriptor(&self) -> gtfs_rt::TripDescriptor {
+        let start = self.stops[0].1;
+        gtfs_rt::TripDescriptor {
+            trip_id: self.id(),
+            route_id: self.trip.line.as_str().to_string(),
+            direction_id: Some(direction_id(self.schedule.direction)),
+            // GTFS times run past 24:00 for trains after midnight, like ours.
+            start_time: format!("{:02}:{:02}:00", start / 60, start % 60),
+            start_date: self.date.format("%Y%m%d").to_string(),
+            schedule_relationship: 0,
+        }
+    }
+
+    fn vehicle(&self) -> gtfs_rt::VehicleDescriptor {
+        let terminal = self.stops[self.stops.len() - 1].0;
+        gtfs_rt::VehicleDescriptor {
+            id: self.id(),
+            label: format!(
+                "{} line to {}",
+                self.trip.line.as_str(),
+                STATIONS[terminal].name
+            ),
+        }
+    }
+}
+
+fn direction_id(direction: Direction) -> u32 {
+    match direction {
+        Direction::East => 0,
+        Direction::West => 1,
+    }
+}
+
+// Seconds into the service day.
+fn seconds(date: NaiveDate, now: DateTime<Local>) -> i64 {
+    (now.naive_local() - schedule::service_datetime(date, 0)).num_seconds()
+}
+
+fn active(tt: &Timetable, now: DateTime<Local>) -> Vec<Active<'_>> {
+    let (date, _) = schedule::service_day(now.naive_local());
+    let version = tt.version(date);
+    let now = seconds(date, now);
+    let mut found = Vec::new();
+    for direction in Direction::ALL.iter().copied() {
+        let schedule = version.schedule(DayType::for_date(date), direction);
+        for trip in schedule.trips.iter() {
+            let stops: Vec<(usize, u32)> = schedule
+                .order
+                .iter()
+                .filter_map(|s| Some((*s, trip.times[*s]?)))
+                .collect();
+            let running = match (stops.first(), stops.last()) {
+                (Some(first), Some(last)) => {
+                    first.1 as i64 * 60 <= now && now <= last.1 as i64 * 60 + DWELL
+                }
+                _ => false,
+            };
+            if running {
+                found.push(Active {
+                    date,
+                    version: &version.id,
+                    schedule,
+                    trip,
+                    stops,
+                });
+            }
+        }
+    }
+    found
+}
+
+fn message(entity: Vec<FeedEntity>, now: DateTime<Local>) -> FeedMessage {
+    FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".to_string(),
+            timestamp: Some(now.timestamp() as u64),
+        },
+        entity,
+    }
+}
+
+// The stops still ahead of each running train, at their scheduled times.
+fn trip_updates(tt: &Timetable, now: DateTime<Local>) -> FeedMessage {
+    let entity = active(tt, now)
+        .into_iter()
+        .map(|a| {
+            let since = seconds(a.date, now) - DWELL;
+            let event = |t: u32| StopTimeEvent {
+                delay: None,
+                time: Some(a.epoch(t)),
+            };
+            let stop_time_update = a
+                .stops
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, t))| *t as i64 * 60 >= since)
+                .map(|(i, (station, t))| gtfs_rt::StopTimeUpdate {
+                    stop_sequence: Some(i as u32 + 1),
+                    stop_id: STATIONS[*station].id.to_string(),
+                    arrival: Some(event(*t)),
+                    departure: Some(event(*t)),
+                    schedule_relationship: 0,
+                })
+                .collect();
+            FeedEntity {
+                id: a.id(),
+                trip_update: Some(gtfs_rt::TripUpdate {
+                    trip: a.descriptor(),
+                    vehicle: Some(a.vehicle()),
+                    stop_time_update,
+                    timestamp: Some(now.timestamp() as u64),
+                    delay: None,
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+    message(entity, now)
+}
+
+// Where each running train should be: standing at a station for DWELL
+// seconds from its scheduled time, then moving evenly to the next one.
+fn vehicle_positions(
+    tt: &Timetable,
+    coordinates: &[Option<(f64, f64)>],
+    now: DateTime<Local>,
+) -> FeedMessage {
+    let entity = active(tt, now)
+        .into_iter()
+        .map(|a| {
+            let since = seconds(a.date, now);
+            let at = a
+                .stops
+                .iter()
+                .rposition(|(_, t)| *t as i64 * 60 <= since)
+                .unwrap_or(0);
+            let (from, left) = (a.stops[at].0, a.stops[at].1 as i64 * 60);
+            let (status, sequence, stop, position) = match a.stops.get(at + 1) {
+                Some((to, arrives)) if since >= left + DWELL => {
+                    let travel = (*arrives as i64 * 60 - left - DWELL).max(1);
+                    let part = (since - left - DWELL) as f64 / travel as f64;
+                    let position = match (coordinates.get(from), coordinates.get(*to)) {
+                        (Some(Some(a)), Some(Some(b))) => {
+                            Some((a.0 + (b.0 - a.0) * part, a.1 + (b.1 - a.1) * part))
+                        }
+                        _ => None,
+                    };
+                    (gtfs_rt::IN_TRANSIT_TO, at + 2, *to, position)
+                }
+                _ => (
+                    gtfs_rt::STOPPED_AT,
+                    at + 1,
+                    from,
+                    coordinates.get(from).copied().flatten(),
+                ),
+            };
+            FeedEntity {
+                id: a.id(),
+                vehicle: Some(gtfs_rt::VehiclePosition {
+                    trip: a.descriptor(),
+                    vehicle: a.vehicle(),
+                    position: position.map(|(lat, lon)| gtfs_rt::Position {
+                        latitude: lat as f32,
+                        longitude: lon as f32,
+                    }),
+                    current_stop_sequence: sequence as u32,
+                    stop_id: STATIONS[stop].id.to_string(),
+                    current_status: status,
+                    timestamp: now.timestamp() as u64,
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+    message(entity, now)
+}
+
+// One informed entity per station, line and direction combination named. A
+// direction needs a line to go with it, so it gets both when none is named,
+// and an alert naming nothing at all is about both lines.
+fn informed(alert: &alerts::Alert) -> Vec<EntitySelector> {
+    let all_lines =
+        alert.lines.is_empty() && (alert.stations.is_empty() || !alert.directions.is_empty());
+    let lines: Vec<Option<&str>> = match all_lines {
+        true => Line::ALL.iter().map(|l| Some(l.as_str())).collect(),
+        false if alert.lines.is_empty() => vec![None],
+        false => alert.lines.iter().map(|l| Some(l.as_str())).collect(),
+    };
+    let stations: Vec<Option<&str>> = match alert.stations.is_empty() {
+        true => vec![None],
+        false => alert.stations.iter().map(|s| Some(s.as_str())).collect(),
+    };
+    let directions: Vec<Option<u32>> = match alert.directions.is_empty() {
+        true => vec![None],
+        false => alert
+            .directions
+            .iter()
+            .filter_map(|d| Direction::parse(d))
+            .map(|d| Some(direction_id(d)))
+            .collect(),
+    };
+    let mut selectors = Vec::new();
+    for station in stations.iter() {
+        for line in lines.iter() {
+            for direction in directions.iter() {
+                selectors.push(EntitySelector {
+                    route_id: line.unwrap_or_default().to_string(),
+                    direction_id: *direction,
+                    stop_id: station.unwrap_or_default().to_string(),
+                });
+            }
+        }
+    }
+    selectors
+}
+
+fn alerts_feed(alerts: &Alerts, now: DateTime<Local>) -> FeedMessage {
+    let entity = alerts
+        .all()
+        .iter()
+        .filter(|a| a.current(now))
+        .map(|a| FeedEntity {
+            id: a.id.clone(),
+            alert: Some(gtfs_rt::Alert {
+                active_period: match (a.active_from, a.active_until) {
+                    (None, None) => Vec::new(),
+                    (from, until) => vec![TimeRange {
+                        start: from.map(|t| t.timestamp() as u64),
+                        end: until.map(|t| t.timestamp() as u64),
+                    }],
+                },
+                informed_entity: informed(a),
+                header_text: a.header.clone(),
+                description_text: a.description.clone(),
+                severity_level: match a.severity {
+                    Severity::Info => gtfs_rt::INFO,
+                    Severity::Warning => gtfs_rt::WARNING,
+                    Severity::Severe => gtfs_rt::SEVERE,
+                },
+            }),
+            ..Default::default()
+        })
+        .collect();
+    message(entity, now)
+}
+
+#[get("/{feed}.{format}")]
+async fn feed(
+    http: HttpRequest,
+    tt: Current,
+    config: web::Data<Config>,
+    alerts: web::Data<Alerts>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (name, format) = path.into_inner();
+    let now = Local::now();
+    let feed = match name.as_str() {
+        "trip-updates" => trip_updates(&tt, now),
+        "vehicle-positions" => vehicle_positions(&tt, &config.coordinates, now),
+        "alerts" => alerts_feed(&alerts, now),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    match format.as_str() {
+        "pb" if !negotiate::accepts(&http, PROTOBUF) => negotiate::not_acceptable_for(&[PROTOBUF]),
+        "pb" => HttpResponse::Ok()
+            .content_type(PROTOBUF)
+            .body(gtfs_rt::encode(&feed)),
+        "json" => negotiate::json(&http, StatusCode::OK, &feed),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
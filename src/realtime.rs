@@ -0,0 +1,447 @@
+use crate::gtfs_rt::{self, FeedMessage, StopTimeEvent, StopTimeUpdate, TripUpdate};
+use crate::reload::Schedules;
+use crate::schedule::{self, DayType, Departure, Direction, Schedule, Timetable, Trip, STATIONS};
+use crate::{gtfs, v2};
+use actix_web::client::Client;
+use actix_web::web;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Optional GTFS-Realtime TripUpdates feed, polled from a URL or read from a
+// file. Each update is matched to a scheduled trip, by our own trip ids when
+// the feed uses them and otherwise by the stops and times it predicts, and
+// the delays move the arrival answers. When the feed is older than max_age,
+// or was never read, answers come from the schedule alone.
+
+const MAX_FEED_BYTES: usize = 16 * 1024 * 1024;
+// How far a predicted time may be from the schedule and still be matched to
+// that trip, in seconds.
+const MATCH_WINDOW: i64 = 20 * 60;
+// Trains scheduled this many minutes ago may still be on their way.
+const LOOKBACK: i64 = 30;
+
+pub struct Config {
+    // An http(s) URL, or else a file path.
+    pub source: String,
+    // Feed stop ids to our stations, from the agency's static GTFS.
+    pub stops: HashMap<String, usize>,
+    pub interval: std::time::Duration,
+    pub max_age: Duration,
+}
+
+#[derive(Clone, Copy)]
+enum Stop {
+    // No prediction; use the schedule.
+    Scheduled,
+    Delayed(i64),
+    Skipped,
+}
+
+struct Prediction {
+    version: String,
+    // Indexed by position in STATIONS.
+    stops: Vec<Stop>,
+}
+
+struct Snapshot {
+    // Unix time of the feed header, or of the fetch if the header has none.
+    timestamp: i64,
+    trips: HashMap<(NaiveDate, Direction, usize), Prediction>,
+}
+
+pub struct Realtime {
+    config: Option<Config>,
+    snapshot: RwLock<Option<Arc<Snapshot>>>,
+}
+
+// A scheduled trip on a service date.
+struct Match<'a> {
+    date: NaiveDate,
+    version: &'a str,
+    schedule: &'a Schedule,
+    trip: &'a Trip,
+}
+
+impl Match<'_> {
+    fn scheduled(&self, station: usize) -> Option<i64> {
+        self.trip.times[station].map(|t| epoch(self.date, t))
+    }
+}
+
+fn epoch(date: NaiveDate, minutes: u32) -> i64 {
+    v2::local(schedule::service_datetime(date, minutes)).timestamp()
+}
+
+fn today() -> NaiveDate {
+    schedule::service_day(Local::now().naive_local()).0
+}
+
+fn start_date(update: &TripUpdate) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&update.trip.start_date, "%Y%m%d").ok()
+}
+
+// The departure time wins over the arrival, since that's what riders wait for.
+fn event_delay(update: &StopTimeUpdate, scheduled: i64) -> Option<i64> {
+    let delay = |e: &StopTimeEvent| {
+        e.delay
+            .map(i64::from)
+            .or_else(|| e.time.map(|t| t - scheduled))
+    };
+    update
+        .departure
+        .as_ref()
+        .and_then(delay)
+        .or_else(|| update.arrival.as_ref().and_then(delay))
+}
+
+fn event_time(update: &StopTimeUpdate) -> Option<i64> {
+    update
+        .departure
+        .as_ref()
+        .and_then(|e| e.time)
+        .or_else(|| update.arrival.as_ref().and_then(|e| e.time))
+}
+
+fn station(update: &StopTimeUpdate, stops: &HashMap<String, usize>) -> Option<usize> {
+    if update.stop_id.is_empty() {
+        return None;
+    }
+    stops
+        .get(&update.stop_id)
+        .copied()
+        .or_else(|| gtfs::station_for(&update.stop_id))
+}
+
+fn by_id<'a>(tt: &'a Timetable, update: &TripUpdate) -> Option<Match<'a>> {
+    let (version, day, direction, row) = gtfs_rt::parse_trip_id(&update.trip.trip_id)?;
+    let date = start_date(update).unwrap_or_else(today);
+    let in_effect = tt.version(date);
+    if in_effect.id != version || DayType::for_date(date) != day {
+        return None;
+    }
+    let schedule = in_effect.schedule(day, direction);
+    let trip = schedule.trips.iter().find(|t| t.row == row)?;
+    Some(Match {
+        date,
+        version: &in_effect.id,
+        schedule,
+        trip,
+    })
+}
+
+// For feeds with their own trip ids: the trip in either direction whose
+// schedule is closest, on average, to the predicted times at the stops we
+// know, and stops at all of them. It takes two stops to tell the direction.
+fn by_time<'a>(
+    tt: &'a Timetable,
+    update: &TripUpdate,
+    stops: &HashMap<String, usize>,
+) -> Option<Match<'a>> {
+    let timed: Vec<(usize, i64)> = update
+        .stop_time_update
+        .iter()
+        .filter(|u| u.schedule_relationship != gtfs_rt::STOP_SKIPPED)
+        .filter_map(|u| Some((station(u, stops)?, event_time(u)?)))
+        .collect();
+    // One stop can't tell which way the train is going.
+    if timed.iter().all(|(s, _)| *s == timed[0].0) {
+        return None;
+    }
+    let first = timed.first()?;
+    let date = start_date(update).or_else(|| {
+        let t = Local.timestamp_opt(first.1, 0).single()?;
+        Some(schedule::service_day(t.naive_local()).0)
+    })?;
+    let version = tt.version(date);
+    let mut best: Option<(i64, Match)> = None;
+    for direction in Direction::ALL.iter().copied() {
+        let schedule = tt.schedule(date, direction);
+        for trip in schedule.trips.iter() {
+            let candidate = Match {
+                date,
+                version: &version.id,
+                schedule,
+                trip,
+            };
+            let offsets: Option<Vec<i64>> = timed
+                .iter()
+                .map(|(s, t)| {
+                    candidate
+                        .scheduled(*s)
+                        .map(|scheduled| (t - scheduled).abs())
+                })
+                .collect();
+            let cost = match offsets {
+                Some(o) if o.iter().all(|o| *o <= MATCH_WINDOW) => {
+                    o.iter().sum::<i64>() / o.len() as i64
+                }
+                _ => continue,
+            };
+            if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+                best = Some((cost, candidate));
+            }
+        }
+    }
+    best.map(|(_, m)| m)
+}
+
+// Delays carry on down the line from each prediction to the next, as the
+// GTFS-Realtime spec has it; stops before the first one are left alone.
+fn predict(m: &Match, update: &TripUpdate, stops: &HashMap<String, usize>) -> Prediction {
+    let travel: Vec<usize> = m
+        .schedule
+        .order
+        .iter()
+        .copied()
+        .filter(|s| m.trip.times[*s].is_some())
+        .collect();
+    let mut updates: HashMap<usize, &StopTimeUpdate> = HashMap::new();
+    for u in update.stop_time_update.iter() {
+        // stop_sequence counts the trip's stops from 1.
+        let by_sequence = u
+            .stop_sequence
+            .and_then(|n| travel.get((n as usize).checked_sub(1)?).copied());
+        if let Some(s) = station(u, stops).or(by_sequence) {
+            updates.insert(s, u);
+        }
+    }
+    let canceled = update.trip.schedule_relationship == gtfs_rt::TRIP_CANCELED;
+    let mut current = update
+        .delay
+        .map_or(Stop::Scheduled, |d| Stop::Delayed(d.into()));
+    let mut predicted = vec![Stop::Scheduled; STATIONS.len()];
+    for s in travel {
+        predicted[s] = match (canceled, updates.get(&s)) {
+            (true, _) => Stop::Skipped,
+            (false, Some(u)) if u.schedule_relationship == gtfs_rt::STOP_SKIPPED => Stop::Skipped,
+            (false, Some(u)) if u.schedule_relationship == gtfs_rt::STOP_NO_DATA => {
+                current = Stop::Scheduled;
+                current
+            }
+            (false, Some(u)) => {
+                if let Some(delay) = m
+                    .scheduled(s)
+                    .and_then(|scheduled| event_delay(u, scheduled))
+                {
+                    current = Stop::Delayed(delay);
+                }
+                current
+            }
+            (false, None) => current,
+        };
+    }
+    Prediction {
+        version: m.version.to_string(),
+        stops: predicted,
+    }
+}
+
+fn snapshot(
+    tt: &Timetable,
+    feed: &FeedMessage,
+    stops: &HashMap<String, usize>,
+) -> (Snapshot, usize, usize) {
+    let mut trips = HashMap::new();
+    let updates: Vec<&TripUpdate> = feed
+        .entity
+        .iter()
+        .filter(|e| !e.is_deleted)
+        .filter_map(|e| e.trip_update.as_ref())
+        .collect();
+    for update in updates.iter() {
+        let m = match by_id(tt, update).or_else(|| by_time(tt, update, stops)) {
+            Some(m) => m,
+            None => continue,
+        };
+        // Two updates claiming one trip: the first keeps it.
+        trips
+            .entry((m.date, m.schedule.direction, m.trip.row))
+            .or_insert_with(|| predict(&m, update, stops));
+    }
+    let timestamp = feed
+        .header
+        .timestamp
+        .map_or_else(|| Utc::now().timestamp(), |t| t as i64);
+    let matched = trips.len();
+    (Snapshot { timestamp, trips }, matched, updates.len())
+}
+
+pub async fn fetch(source: &str) -> Result<Vec<u8>, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let mut res = Client::default()
+            .get(source)
+            .timeout(std::time::Duration::from_secs(30))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("HTTP {}", res.status()));
+        }
+        let body = res
+            .body()
+            .limit(MAX_FEED_BYTES)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(body.to_vec())
+    } else {
+        std::fs::read(source.strip_prefix("file://").unwrap_or(source)).map_err(|e| e.to_string())
+    }
+}
+
+impl Realtime {
+    pub fn new(config: Option<Config>) -> Realtime {
+        Realtime {
+            config,
+            snapshot: RwLock::new(None),
+        }
+    }
+
+    // Reads the feed once and keeps what it predicts. Gives the number of
+    // trip updates matched and read.
+    async fn refresh(&self, tt: &Timetable) -> Result<(usize, usize), String> {
+        let config = match &self.config {
+            Some(c) => c,
+            None => return Ok((0, 0)),
+        };
+        let feed = gtfs_rt::decode(&fetch(&config.source).await?)?;
+        let (snapshot, matched, updates) = snapshot(tt, &feed, &config.stops);
+        *self.snapshot.write().expect("realtime lock") = Some(Arc::new(snapshot));
+        Ok((matched, updates))
+    }
+
+    fn fresh(&self) -> Option<Arc<Snapshot>> {
+        let config = self.config.as_ref()?;
+        let snapshot = self.snapshot.read().expect("realtime lock").clone()?;
+        match Utc::now().timestamp() - snapshot.timestamp <= config.max_age.num_seconds() {
+            true => Some(snapshot),
+            false => None,
+        }
+    }
+
+    // Like Timetable::upcoming, but in the order the trains are now expected,
+    // without canceled trains or skipped stops, and counting trains that were
+    // due a little while ago but are running late.
+    pub fn upcoming(
+        &self,
+        tt: &Timetable,
+        station: usize,
+        direction: Direction,
+        at: NaiveDateTime,
+        count: usize,
+    ) -> Vec<Departure> {
+        let snapshot = match self.fresh() {
+            Some(s) => s,
+            None => return tt.upcoming(station, direction, at, count),
+        };
+        let (date, now) = schedule::service_day(at);
+        let (now, end) = (
+            schedule::service_datetime(date, now),
+            schedule::service_datetime(
+                date,
+                schedule::SERVICE_DAY_START + schedule::MINUTES_PER_DAY,
+            ),
+        );
+        let mut found: Vec<Departure> = tt
+            .between(station, direction, now - Duration::minutes(LOOKBACK), end)
+            .into_iter()
+            .filter(|d| d.date == date)
+            .filter_map(|mut d| {
+                let prediction = snapshot
+                    .trips
+                    .get(&(d.date, direction, d.trip))
+                    .filter(|p| p.version == d.version);
+                match prediction.map(|p| p.stops[station]) {
+                    Some(Stop::Skipped) => return None,
+                    Some(Stop::Delayed(delay)) => d.delay = Some(delay),
+                    Some(Stop::Scheduled) | None => {}
+                }
+                Some(d)
+            })
+            .filter(|d| d.expected() >= now)
+            .collect();
+        found.sort_by_key(|d| d.expected());
+        found.truncate(count);
+        found
+    }
+}
+
+// Polls the feed for as long as the server runs. Must be called from within
+// the actix runtime.
+pub fn start(realtime: web::Data<Realtime>, schedules: web::Data<Schedules>) {
+    actix_web::rt::spawn(async move {
+        let config = match &realtime.config {
+            Some(c) => c,
+            None => return,
+        };
+        let mut failing = true;
+        loop {
+            match realtime.refresh(&schedules.current()).await {
+                Ok((matched, updates)) => {
+                    if failing {
+                        println!(
+                            "realtime feed {}: matched {} of {} trip updates",
+                            config.source, matched, updates
+                        );
+                        failing = false;
+                    }
+                }
+                Err(e) => {
+                    println!("realtime feed {} failed: {}", config.source, e);
+                    failing = true;
+                }
+            }
+            actix_web::rt::time::delay_for(config.interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A feed with one update: the weekday eastbound trip on row 5 running
+    // four minutes late on 2026-10-19.
+    const DELAYED: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/realtime/delayed.pb"
+    );
+
+    #[test]
+    fn delays_trains_from_a_feed_file() {
+        let tt = Arc::new(Timetable::embedded().unwrap());
+        let realtime = Arc::new(Realtime::new(Some(Config {
+            source: DELAYED.to_string(),
+            stops: HashMap::new(),
+            interval: std::time::Duration::from_secs(30),
+            max_age: Duration::minutes(5),
+        })));
+        let (reading, timetable) = (realtime.clone(), tt.clone());
+        let read = actix_web::rt::System::new("realtime")
+            .block_on(async move { reading.refresh(&timetable).await });
+        assert_eq!(read, Ok((1, 1)));
+
+        let date = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let schedule = tt.schedule(date, Direction::East);
+        let trip = schedule.trips.iter().find(|t| t.row == 5).unwrap();
+        let station = schedule
+            .order
+            .iter()
+            .copied()
+            .find(|s| trip.times[*s].is_some())
+            .unwrap();
+        let scheduled = schedule::service_datetime(date, trip.times[station].unwrap());
+        let found = realtime.upcoming(&tt, station, Direction::East, scheduled, 3);
+        let late = found.iter().find(|d| d.trip == 5).unwrap();
+        assert_eq!(late.delay, Some(240));
+        assert_eq!(late.expected(), scheduled + Duration::minutes(4));
+
+        let arrival = serde_json::to_value(v2::arrival(late, scheduled)).unwrap();
+        assert_eq!(arrival["source"], "realtime");
+        assert_eq!(arrival["delay_seconds"], 240);
+        let others = found.iter().filter(|d| d.trip != 5);
+        assert!(others
+            .map(|d| serde_json::to_value(v2::arrival(d, scheduled)).unwrap())
+            .all(|a| a["source"] == "scheduled"));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::schedule::{self, DayType, Direction, Timetable};
+use crate::{events, overlay, validate};
+use actix_web::dev::Payload;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{web, FromRequest, HttpRequest};
+use chrono::Utc;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+// The timetable in use. With --schedule-dir it is read from that directory at
+// startup and again on SIGHUP or when the files change; a reload that fails
+// validation leaves the old timetable in place. Without a directory, or if
+// the directory is bad at startup, the data built into the binary is used.
+// Overlays from --overlays-file and events from --events-file are laid over
+// whichever it is, and reloaded the same way.
+
+pub struct Schedules {
+    dir: Option<String>,
+    overlays: Option<String>,
+    events: Option<String>,
+    current: RwLock<Arc<Timetable>>,
+    pub reloads: AtomicU64,
+    pub failures: AtomicU64,
+    // Unix time the timetable in use was loaded, and whether the last reload
+    // attempt failed.
+    pub loaded_at: AtomicI64,
+    pub failing: AtomicBool,
+}
+
+// What handlers take instead of the timetable itself: the one in use when the
+// request came in, which stays put for the whole request even if a reload
+// lands meanwhile.
+pub struct Current(Arc<Timetable>);
+
+impl Deref for Current {
+    type Target = Timetable;
+
+    fn deref(&self) -> &Timetable {
+        &self.0
+    }
+}
+
+impl FromRequest for Current {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Current, actix_web::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match req.app_data::<web::Data<Schedules>>() {
+            Some(schedules) => Ok(Current(schedules.current())),
+            None => Err(ErrorInternalServerError("no schedules loaded")),
+        })
+    }
+}
+
+fn load(
+    dir: Option<&str>,
+    overlays: Option<&str>,
+    events: Option<&str>,
+) -> Result<Timetable, String> {
+    let tt = match dir {
+        Some(dir) => {
+            let report = validate::check(Some(dir));
+            if let Some(first) = report.errors().next() {
+                return Err(format!(
+                    "{} ({} errors in all)",
+                    first,
+                    report.errors().count()
+                ));
+            }
+            Timetable::load(Some(dir))?
+        }
+        None => Timetable::embedded()?,
+    };
+    let overlays = match overlays {
+        Some(path) => overlay::load(path)?,
+        None => Vec::new(),
+    };
+    let events = match events {
+        Some(path) => events::load(path)?,
+        None => Vec::new(),
+    };
+    overlay::apply(tt, overlays, events)
+}
+
+impl Schedules {
+    pub fn new(
+        dir: Option<&str>,
+        overlays: Option<&str>,
+        events: Option<&str>,
+    ) -> Result<Schedules, String> {
+        let schedules = Schedules {
+            dir: dir.map(String::from),
+            overlays: overlays.map(String::from),
+            events: events.map(String::from),
+            current: RwLock::new(Arc::new(Timetable::embedded()?)),
+            reloads: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            loaded_at: AtomicI64::new(Utc::now().timestamp()),
+            failing: AtomicBool::new(false),
+        };
+        if schedules.reloadable() && schedules.reload().is_err() {
+            println!("serving the built-in schedules instead");
+        }
+        Ok(schedules)
+    }
+
+    pub fn current(&self) -> Arc<Timetable> {
+        self.current.read().expect("timetable lock").clone()
+    }
+
+    pub fn reloadable(&self) -> bool {
+        self.dir.is_some() || self.overlays.is_some() || self.events.is_some()
+    }
+
+    pub fn reload(&self) -> Result<(), String> {
+        if !self.reloadable() {
+            return Err(
+                "no --schedule-dir, --overlays-file or --events-file to reload from".to_string(),
+            );
+        }
+        let from = self.dir.as_deref().unwrap_or("the built-in data");
+        match load(
+            self.dir.as_deref(),
+            self.overlays.as_deref(),
+            self.events.as_deref(),
+        ) {
+            Ok(tt) => {
+                *self.current.write().expect("timetable lock") = Arc::new(tt);
+                self.reloads.fetch_add(1, Ordering::Relaxed);
+                self.loaded_at
+                    .store(Utc::now().timestamp(), Ordering::Relaxed);
+                self.failing.store(false, Ordering::Relaxed);
+                println!("loaded schedules from {}", from);
+                Ok(())
+            }
+            Err(e) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                self.failing.store(true, Ordering::Relaxed);
+                println!("schedule reload from {} failed: {}", from, e);
+                Err(e)
+            }
+        }
+    }
+
+    // Modification times of versions.csv, every schedule file it and the
+    // base set name, and the overlays and events files.
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        let mut files = Vec::new();
+        if let Some(dir) = self.dir.as_deref() {
+            let mut subdirs = vec![None];
+            subdirs.extend(
+                schedule::read_versions(Some(dir))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| Some(v.version)),
+            );
+            files.push(Path::new(dir).join(schedule::VERSIONS_FILE));
+            for subdir in subdirs.iter() {
+                for day in DayType::ALL.iter() {
+                    for direction in Direction::ALL.iter() {
+                        files.push(Path::new(dir).join(schedule::version_file(
+                            subdir.as_deref(),
+                            *day,
+                            *direction,
+                        )));
+                    }
+                }
+            }
+        }
+        files.extend(
+            self.overlays
+                .iter()
+                .chain(self.events.iter())
+                .map(PathBuf::from),
+        );
+        files
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+// Reloads on SIGHUP. Must be called from within the actix runtime.
+pub fn on_hangup(schedules: web::Data<Schedules>) {
+    actix_web::rt::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("can't listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            let _ = schedules.reload();
+        }
+    });
+}
+
+// Polls the files' modification times and reloads once they have changed and
+// then held still for a whole interval, so a half-copied set isn't picked up.
+pub fn watch(schedules: web::Data<Schedules>, interval: Duration) {
+    thread::spawn(move || {
+        let mut seen = schedules.modified();
+        let mut pending = None;
+        loop {
+            thread::sleep(interval);
+            let now = schedules.modified();
+            if pending.as_ref() == Some(&now) {
+                pending = None;
+                seen = now;
+                let _ = schedules.reload();
+            } else if now != seen {
+                pending = Some(now);
+            } else {
+                pending = None;
+            }
+        }
+    });
+}
@@ -0,0 +1,885 @@
+use crate::events::Event;
+use crate::overlay::Overlay;
+use crate::Asset;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
+use csv::Reader;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Trains running after midnight belong to the previous day's timetable, so
+// anything before this minute is counted as the tail of the prior service day.
+pub const SERVICE_DAY_START: u32 = 3 * 60;
+pub const MINUTES_PER_DAY: u32 = 24 * 60;
+// All times in the schedule files are St. Louis wall-clock times.
+pub const TIMEZONE: &str = "America/Chicago";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    East,
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 2] = [Direction::East, Direction::West];
+
+    pub fn parse(s: &str) -> Option<Direction> {
+        match s {
+            "east" => Some(Direction::East),
+            "west" => Some(Direction::West),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DayType {
+    Weekday,
+    Saturday,
+    Sunday,
+}
+
+impl DayType {
+    pub const ALL: [DayType; 3] = [DayType::Weekday, DayType::Saturday, DayType::Sunday];
+
+    pub fn for_date(d: NaiveDate) -> DayType {
+        match d.weekday() {
+            Weekday::Sat => DayType::Saturday,
+            Weekday::Sun => DayType::Sunday,
+            _ => DayType::Weekday,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DayType> {
+        DayType::ALL.iter().copied().find(|d| d.as_str() == s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DayType::Weekday => "weekday",
+            DayType::Saturday => "saturday",
+            DayType::Sunday => "sunday",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Line {
+    Red,
+    Blue,
+}
+
+impl Line {
+    pub const ALL: [Line; 2] = [Line::Red, Line::Blue];
+
+    pub fn parse(s: &str) -> Option<Line> {
+        Line::ALL.iter().copied().find(|l| l.as_str() == s)
+    }
+
+    pub fn from_code(c: char) -> Option<Line> {
+        match c {
+            'R' => Some(Line::Red),
+            'B' => Some(Line::Blue),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Line::Red => "red",
+            Line::Blue => "blue",
+        }
+    }
+}
+
+pub struct Station {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+}
+
+// Listed in eastbound order, with the names used in the CSV headers.
+pub static STATIONS: [Station; 38] = [
+    Station {
+        id: "lambert",
+        name: "Lambert Airport Terminal # 1",
+        aliases: &["lambert"],
+    },
+    Station {
+        id: "lambert2",
+        name: "Lambert Airport Terminal # 2",
+        aliases: &["lambert2"],
+    },
+    Station {
+        id: "hanley",
+        name: "North Hanley Station",
+        aliases: &["hanley"],
+    },
+    Station {
+        id: "umsl-north",
+        name: "UMSL North Station",
+        aliases: &["umsl north", "umsl"],
+    },
+    Station {
+        id: "umsl-south",
+        name: "UMSL South Station",
+        aliases: &["umsl south"],
+    },
+    Station {
+        id: "rock-road",
+        name: "Rock Road Station",
+        aliases: &["rock road"],
+    },
+    Station {
+        id: "wellston",
+        name: "Wellston Station",
+        aliases: &["wellston"],
+    },
+    Station {
+        id: "delmar",
+        name: "Delmar Loop Station",
+        aliases: &["delmar"],
+    },
+    Station {
+        id: "shrewsbury",
+        name: "ShrewsburyLansdowne I44 Station",
+        aliases: &["shrewsbury"],
+    },
+    Station {
+        id: "sunnen",
+        name: "Sunnen Station",
+        aliases: &["sunnen"],
+    },
+    Station {
+        id: "maplewood",
+        name: "MaplewoodManchester Station",
+        aliases: &["maplewood"],
+    },
+    Station {
+        id: "brentwood",
+        name: "Brentwood I64 Station",
+        aliases: &["brentwood"],
+    },
+    Station {
+        id: "richmond-heights",
+        name: "Richmond Heights Station",
+        aliases: &["richmond", "richmond heights"],
+    },
+    Station {
+        id: "clayton",
+        name: "Clayton Station",
+        aliases: &["clayton"],
+    },
+    Station {
+        id: "forsyth",
+        name: "Forsyth Station",
+        aliases: &["forsyth"],
+    },
+    Station {
+        id: "u-city",
+        name: "University CityBig Bend Station",
+        aliases: &["u city", "university city"],
+    },
+    Station {
+        id: "skinker",
+        name: "Skinker Station",
+        aliases: &["skinker"],
+    },
+    Station {
+        id: "forest-park",
+        name: "Forest ParkDeBaliviere Station",
+        aliases: &["forest park"],
+    },
+    Station {
+        id: "cwe",
+        name: "Central West End Station",
+        aliases: &["cwe", "central west end"],
+    },
+    Station {
+        id: "cortex",
+        name: "Cortex Station",
+        aliases: &["cortex"],
+    },
+    Station {
+        id: "grand",
+        name: "Grand Station",
+        aliases: &["grand"],
+    },
+    Station {
+        id: "union",
+        name: "Union Station",
+        aliases: &["union"],
+    },
+    Station {
+        id: "civic-center",
+        name: "Civic Center Station",
+        aliases: &["civic center", "civic"],
+    },
+    Station {
+        id: "stadium",
+        name: "Stadium Station",
+        aliases: &["stadium"],
+    },
+    Station {
+        id: "8th-pine",
+        name: "8th & Pine Station",
+        aliases: &["8th and pine", "8th pine"],
+    },
+    Station {
+        id: "convention-center",
+        name: "Convention Center Station",
+        aliases: &["convention center", "convention"],
+    },
+    Station {
+        id: "lacledes-landing",
+        name: "Laclede's Landing Station",
+        aliases: &["lacledes", "lacledes landing"],
+    },
+    Station {
+        id: "east-riverfront",
+        name: "East Riverfront Station",
+        aliases: &["riverfront", "east riverfront"],
+    },
+    Station {
+        id: "5th-missouri",
+        name: "5th & Missouri Station",
+        aliases: &["fifth missouri", "5th missouri"],
+    },
+    Station {
+        id: "emerson-park",
+        name: "Emerson Park Station",
+        aliases: &["emerson", "emerson park"],
+    },
+    Station {
+        id: "jjk",
+        name: "JJK Center Station",
+        aliases: &["jjk", "jackie joiner"],
+    },
+    Station {
+        id: "washington-park",
+        name: "Washington Park Station",
+        aliases: &["washington"],
+    },
+    Station {
+        id: "fairview-heights",
+        name: "Fairview Heights Station",
+        aliases: &["fvh", "fairview heights"],
+    },
+    Station {
+        id: "memorial-hospital",
+        name: "Memorial Hospital Station",
+        aliases: &["memorial hospital"],
+    },
+    Station {
+        id: "swansea",
+        name: "Swansea Station",
+        aliases: &["swansea"],
+    },
+    Station {
+        id: "belleville",
+        name: "Belleville Station",
+        aliases: &["belleville"],
+    },
+    Station {
+        id: "college",
+        name: "College Station",
+        aliases: &["college"],
+    },
+    Station {
+        id: "shiloh-scott",
+        name: "ShilohScott Station",
+        aliases: &["shiloh", "shiloh scott"],
+    },
+];
+
+pub fn find_station(input: &str) -> Option<usize> {
+    let input = input.trim().to_lowercase();
+    STATIONS
+        .iter()
+        .position(|s| s.id == input || s.aliases.iter().any(|a| *a == input))
+}
+
+// "cwe:west" -> the station and direction, for the lists of stations to
+// watch in the MQTT and metrics options.
+pub fn parse_watch(s: &str) -> Result<(usize, Direction), String> {
+    let (station, direction) = s
+        .split_once(':')
+        .ok_or_else(|| format!("{}: expected station:direction, like cwe:west", s))?;
+    let station = find_station(station).ok_or_else(|| format!("{}: unknown station", s))?;
+    let direction = Direction::parse(direction)
+        .ok_or_else(|| format!("{}: direction must be east or west", s))?;
+    Ok((station, direction))
+}
+
+fn station_by_name(name: &str) -> Option<usize> {
+    STATIONS.iter().position(|s| s.name == name)
+}
+
+// Parses "1:34P" into minutes after midnight.
+pub fn parse_time(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let (clock, meridiem) = s.split_at(s.len().checked_sub(1)?);
+    let mut parts = clock.splitn(2, ':');
+    let hh: u32 = parts.next()?.parse().ok()?;
+    let mm: u32 = parts.next()?.parse().ok()?;
+    if hh == 0 || hh > 12 || mm > 59 {
+        return None;
+    }
+    match meridiem {
+        "A" | "a" => Some((hh % 12) * 60 + mm),
+        "P" | "p" => Some((hh % 12 + 12) * 60 + mm),
+        _ => None,
+    }
+}
+
+// Parses a schedule cell such as "1:34PR" into service-day minutes and line.
+pub fn parse_cell(s: &str) -> Option<(u32, Line)> {
+    let s = s.trim();
+    let line = Line::from_code(s.chars().last()?)?;
+    let minutes = parse_time(&s[..s.len() - 1])?;
+    Some((service_minutes(minutes), line))
+}
+
+pub fn service_minutes(minutes: u32) -> u32 {
+    if minutes < SERVICE_DAY_START {
+        minutes + MINUTES_PER_DAY
+    } else {
+        minutes
+    }
+}
+
+pub fn format_time(minutes: u32) -> String {
+    let hh = (minutes / 60) % 24;
+    let mm = minutes % 60;
+    let meridiem = if hh < 12 { "A" } else { "P" };
+    let hh = match hh % 12 {
+        0 => 12,
+        h => h,
+    };
+    format!("{}:{:02}{}", hh, mm, meridiem)
+}
+
+// Maps a wall-clock time onto the service day it belongs to and the minute
+// within that day.
+pub fn service_day(t: NaiveDateTime) -> (NaiveDate, u32) {
+    let minutes = t.hour() * 60 + t.minute();
+    if minutes < SERVICE_DAY_START {
+        (t.date() - Duration::days(1), minutes + MINUTES_PER_DAY)
+    } else {
+        (t.date(), minutes)
+    }
+}
+
+// The wall-clock time of a service-day minute, rolling past midnight as needed.
+pub fn service_datetime(date: NaiveDate, minutes: u32) -> NaiveDateTime {
+    date.and_time(NaiveTime::MIN) + Duration::minutes(minutes as i64)
+}
+
+// Lists the dated schedule sets next to the base files: one row per set, with
+// its files in a subdirectory named after the version.
+pub const VERSIONS_FILE: &str = "versions.csv";
+
+// The top-level files, used on any date no dated version covers.
+pub const BASE_VERSION: &str = "base";
+
+pub fn schedule_file(day: DayType, direction: Direction) -> String {
+    format!("{}bound-{}-schedule.csv", direction.as_str(), day.as_str())
+}
+
+pub fn read_file(dir: Option<&str>, name: &str) -> Result<Vec<u8>, String> {
+    match dir {
+        Some(dir) => std::fs::read(Path::new(dir).join(name)).map_err(|e| e.to_string()),
+        None => Asset::get(name)
+            .map(|data| data.into_owned())
+            .ok_or_else(|| "not in the embedded data".to_string()),
+    }
+}
+
+// A schedule file of a dated version, in its subdirectory, or of the base set.
+pub fn version_file(version: Option<&str>, day: DayType, direction: Direction) -> String {
+    match version {
+        Some(version) => format!("{}/{}", version, schedule_file(day, direction)),
+        None => schedule_file(day, direction),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VersionEntry {
+    pub version: String,
+    pub effective_from: NaiveDate,
+    pub effective_until: Option<NaiveDate>,
+}
+
+fn has_file(dir: Option<&str>, name: &str) -> bool {
+    match dir {
+        Some(dir) => Path::new(dir).join(name).is_file(),
+        None => Asset::get(name).is_some(),
+    }
+}
+
+// The dated versions listed in versions.csv, or none if there is no such file.
+pub fn read_versions(dir: Option<&str>) -> Result<Vec<VersionEntry>, String> {
+    if !has_file(dir, VERSIONS_FILE) {
+        return Ok(Vec::new());
+    }
+    let contents = read_file(dir, VERSIONS_FILE)?;
+    let mut entries: Vec<VersionEntry> = Vec::new();
+    for (row, entry) in Reader::from_reader(contents.as_slice())
+        .deserialize()
+        .enumerate()
+    {
+        let entry: VersionEntry = entry.map_err(|e| format!("row {}: {}", row + 2, e))?;
+        let simple = entry
+            .version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if entry.version.is_empty()
+            || !simple
+            || entry.version.starts_with('.')
+            || entry.version == BASE_VERSION
+        {
+            return Err(format!(
+                "row {}: '{}' can't name a version directory",
+                row + 2,
+                entry.version
+            ));
+        }
+        if entries.iter().any(|e| e.version == entry.version) {
+            return Err(format!(
+                "row {}: version '{}' is listed twice",
+                row + 2,
+                entry.version
+            ));
+        }
+        if entry
+            .effective_until
+            .is_some_and(|until| until < entry.effective_from)
+        {
+            return Err(format!(
+                "row {}: effective_until is before effective_from",
+                row + 2
+            ));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[derive(Clone)]
+pub struct Trip {
+    pub line: Line,
+    // The line of the file it came from, which is what trip ids are built on.
+    pub row: usize,
+    // Indexed by position in STATIONS; None where the trip does not stop.
+    pub times: Vec<Option<u32>>,
+    // Added by an overlay or an event supplement rather than in the files,
+    // and the id of the event it runs for.
+    pub extra: bool,
+    pub event: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Schedule {
+    pub day: DayType,
+    pub direction: Direction,
+    // Station indices in the order they appear in the file, i.e. travel order.
+    pub order: Vec<usize>,
+    pub trips: Vec<Trip>,
+}
+
+impl Schedule {
+    pub fn parse(day: DayType, direction: Direction, contents: &[u8]) -> Result<Schedule, String> {
+        let file = schedule_file(day, direction);
+        let mut reader = Reader::from_reader(contents);
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("{}: {}", file, e))?
+            .clone();
+        let mut order = Vec::with_capacity(headers.len());
+        for name in headers.iter() {
+            match station_by_name(name) {
+                Some(i) => order.push(i),
+                None => return Err(format!("{}: unknown station '{}'", file, name)),
+            }
+        }
+        let mut trips = Vec::new();
+        for (row, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| format!("{}: {}", file, e))?;
+            let mut times = vec![None; STATIONS.len()];
+            let mut line = None;
+            for (col, cell) in record.iter().enumerate() {
+                if cell.is_empty() {
+                    continue;
+                }
+                match (parse_cell(cell), order.get(col)) {
+                    (Some((t, l)), Some(&station)) => {
+                        times[station] = Some(t);
+                        line = Some(l);
+                    }
+                    _ => {
+                        return Err(format!("{}: row {} has bad time '{}'", file, row + 2, cell));
+                    }
+                }
+            }
+            if let Some(line) = line {
+                trips.push(Trip {
+                    line,
+                    row: row + 2,
+                    times,
+                    extra: false,
+                    event: None,
+                });
+            }
+        }
+        Ok(Schedule {
+            day,
+            direction,
+            order,
+            trips,
+        })
+    }
+
+    // Every trip stopping at a station, ordered by the time it stops there.
+    pub fn departures(&self, station: usize) -> Vec<(u32, &Trip)> {
+        let mut departures: Vec<(u32, &Trip)> = self
+            .trips
+            .iter()
+            .filter_map(|trip| trip.times[station].map(|t| (t, trip)))
+            .collect();
+        departures.sort_by_key(|(t, _)| *t);
+        departures
+    }
+
+    // Whether no later train of the trip's line leaves the station. Trains
+    // ending there don't leave it, so they are never the last one onward.
+    pub fn is_last(&self, station: usize, trip: &Trip) -> bool {
+        let leaves = |t: &Trip| self.terminal(t).is_some_and(|end| end != station);
+        let time = trip.times[station];
+        leaves(trip)
+            && !self
+                .trips
+                .iter()
+                .any(|t| t.line == trip.line && t.times[station] > time && leaves(t))
+    }
+
+    // The last station a trip stops at, which is what riders see as its destination.
+    pub fn terminal(&self, trip: &Trip) -> Option<usize> {
+        self.order
+            .iter()
+            .rev()
+            .copied()
+            .find(|s| trip.times[*s].is_some())
+    }
+}
+
+#[derive(Clone)]
+pub struct Departure {
+    pub date: NaiveDate,
+    pub time: u32,
+    pub line: Line,
+    pub terminal: usize,
+    // The schedule version it was read from.
+    pub version: String,
+    // The trip's row in its schedule file.
+    pub trip: usize,
+    // Seconds behind schedule according to the real-time feed; None when the
+    // answer comes from the schedule alone.
+    pub delay: Option<i64>,
+    // Copied from the trip, with the event's name in place of its id.
+    pub extra: bool,
+    pub event: Option<String>,
+    // The last train of the night from the station on its line.
+    pub last: bool,
+}
+
+impl Departure {
+    pub fn datetime(&self) -> NaiveDateTime {
+        service_datetime(self.date, self.time)
+    }
+
+    // When the train is now expected to leave.
+    pub fn expected(&self) -> NaiveDateTime {
+        self.datetime() + Duration::seconds(self.delay.unwrap_or(0))
+    }
+
+    // The same as a service-day minute, rounded to the nearest one.
+    pub fn expected_time(&self) -> u32 {
+        (self.time as i64 + (self.delay.unwrap_or(0) + 30).div_euclid(60)).max(0) as u32
+    }
+}
+
+// One complete set of schedules and the service dates it covers; None means
+// open-ended.
+#[derive(Clone)]
+pub struct Version {
+    pub id: String,
+    pub effective_from: Option<NaiveDate>,
+    pub effective_until: Option<NaiveDate>,
+    pub schedules: Vec<Schedule>,
+}
+
+impl Version {
+    // Reads the six schedule files of a dated version, or of the base set.
+    fn load(dir: Option<&str>, subdir: Option<&str>) -> Result<Vec<Schedule>, String> {
+        let mut schedules = Vec::new();
+        for day in DayType::ALL.iter() {
+            for direction in Direction::ALL.iter() {
+                let file = version_file(subdir, *day, *direction);
+                let contents = read_file(dir, &file)
+                    .map_err(|e| format!("missing schedule file {}: {}", file, e))?;
+                schedules.push(Schedule::parse(*day, *direction, &contents)?);
+            }
+        }
+        Ok(schedules)
+    }
+
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        self.effective_from.is_none_or(|from| from <= date)
+            && self.effective_until.is_none_or(|until| date <= until)
+    }
+
+    pub fn schedule(&self, day: DayType, direction: Direction) -> &Schedule {
+        self.schedules
+            .iter()
+            .find(|s| s.day == day && s.direction == direction)
+            .expect("every day type and direction is loaded")
+    }
+}
+
+pub struct Timetable {
+    // The base version first, then the dated ones in versions.csv order.
+    pub versions: Vec<Version>,
+    pub overlays: Vec<Overlay>,
+    pub events: Vec<Event>,
+    // Copies of versions with overlays applied, and the dates each is used on.
+    pub overlaid: Vec<Version>,
+    pub overlaid_on: HashMap<NaiveDate, usize>,
+}
+
+impl Timetable {
+    pub fn new(versions: Vec<Version>) -> Timetable {
+        Timetable {
+            versions,
+            overlays: Vec::new(),
+            events: Vec::new(),
+            overlaid: Vec::new(),
+            overlaid_on: HashMap::new(),
+        }
+    }
+
+    pub fn embedded() -> Result<Timetable, String> {
+        Timetable::load(None)
+    }
+
+    // Reads the schedule files from a directory, or from the data built into
+    // the binary: the base files and any dated versions in versions.csv.
+    pub fn load(dir: Option<&str>) -> Result<Timetable, String> {
+        let mut versions = vec![Version {
+            id: BASE_VERSION.to_string(),
+            effective_from: None,
+            effective_until: None,
+            schedules: Version::load(dir, None)?,
+        }];
+        for entry in read_versions(dir).map_err(|e| format!("{}: {}", VERSIONS_FILE, e))? {
+            let schedules = Version::load(dir, Some(&entry.version))
+                .map_err(|e| format!("{}: {}", entry.version, e))?;
+            versions.push(Version {
+                id: entry.version,
+                effective_from: Some(entry.effective_from),
+                effective_until: entry.effective_until,
+                schedules,
+            });
+        }
+        Ok(Timetable::new(versions))
+    }
+
+    pub fn base(&self) -> &Version {
+        &self.versions[0]
+    }
+
+    // The version in effect on a service date: of those covering it, the one
+    // that took effect last, so a new schedule can start without closing off
+    // the one before. The base version covers any date the others don't.
+    pub fn version_index(&self, date: NaiveDate) -> usize {
+        (0..self.versions.len())
+            .filter(|i| self.versions[*i].covers(date))
+            .max_by_key(|i| self.versions[*i].effective_from)
+            .unwrap_or(0)
+    }
+
+    // The last service date the version at `index` answers for from `date`
+    // on: when it ends, or the day before a later version takes over.
+    pub fn in_effect_until(&self, index: usize, date: NaiveDate) -> Option<NaiveDate> {
+        let version = &self.versions[index];
+        let taken_over = self
+            .versions
+            .iter()
+            .filter_map(|v| v.effective_from)
+            .filter(|from| *from > date && version.effective_from.is_none_or(|start| *from > start))
+            .min()
+            .map(|from| from - Duration::days(1));
+        version.effective_until.into_iter().chain(taken_over).min()
+    }
+
+    // The version in effect with the date's overlays applied, if any.
+    pub fn version(&self, date: NaiveDate) -> &Version {
+        match self.overlaid_on.get(&date) {
+            Some(i) => &self.overlaid[*i],
+            None => &self.versions[self.version_index(date)],
+        }
+    }
+
+    // The name of the event a trip runs for, which is what riders see.
+    pub fn event_name(&self, trip: &Trip) -> Option<String> {
+        let id = trip.event.as_deref()?;
+        self.events
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.name.clone())
+    }
+
+    // The overlay closing a station on a service date.
+    pub fn closure(&self, station: usize, date: NaiveDate) -> Option<&Overlay> {
+        self.overlays
+            .iter()
+            .find(|o| o.covers(date) && o.closes(station))
+    }
+
+    pub fn schedule(&self, date: NaiveDate, direction: Direction) -> &Schedule {
+        self.version(date)
+            .schedule(DayType::for_date(date), direction)
+    }
+
+    // Every train at a station in one direction from `from` up to `to`,
+    // across as many service days as that covers.
+    pub fn between(
+        &self,
+        station: usize,
+        direction: Direction,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<Departure> {
+        let (mut date, _) = service_day(from);
+        let (last, _) = service_day(to);
+        let mut found = Vec::new();
+        while date <= last {
+            let version = self.version(date);
+            let schedule = version.schedule(DayType::for_date(date), direction);
+            found.extend(
+                schedule
+                    .departures(station)
+                    .into_iter()
+                    .map(|(time, trip)| Departure {
+                        date,
+                        time,
+                        line: trip.line,
+                        terminal: schedule.terminal(trip).unwrap_or(station),
+                        version: version.id.clone(),
+                        trip: trip.row,
+                        delay: None,
+                        extra: trip.extra,
+                        event: self.event_name(trip),
+                        last: schedule.is_last(station, trip),
+                    })
+                    .filter(|d| d.datetime() >= from && d.datetime() < to),
+            );
+            date += Duration::days(1);
+        }
+        found
+    }
+
+    // The next `count` trains leaving a station in one direction from `at` on.
+    pub fn upcoming(
+        &self,
+        station: usize,
+        direction: Direction,
+        at: NaiveDateTime,
+        count: usize,
+    ) -> Vec<Departure> {
+        let (date, now) = service_day(at);
+        let version = self.version(date);
+        let schedule = version.schedule(DayType::for_date(date), direction);
+        schedule
+            .departures(station)
+            .into_iter()
+            .filter(|(t, _)| *t >= now)
+            .take(count)
+            .map(|(time, trip)| Departure {
+                date,
+                time,
+                line: trip.line,
+                terminal: schedule.terminal(trip).unwrap_or(station),
+                version: version.id.clone(),
+                trip: trip.row,
+                delay: None,
+                extra: trip.extra,
+                event: self.event_name(trip),
+                last: schedule.is_last(station, trip),
+            })
+            .collect()
+    }
+
+    // The first `count` trains of the next service day after `date` that has
+    // any at the station, which may be from a different day type's schedule.
+    // Gives up after a week.
+    pub fn next_service_day(
+        &self,
+        station: usize,
+        direction: Direction,
+        date: NaiveDate,
+        count: usize,
+    ) -> Vec<Departure> {
+        (1..=7)
+            .map(|days| date + Duration::days(days))
+            .map(|date| {
+                self.upcoming(
+                    station,
+                    direction,
+                    service_datetime(date, SERVICE_DAY_START),
+                    count,
+                )
+            })
+            .find(|found| !found.is_empty())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Some late eastbound Red line trains end at Civic Center. The last
+    // one flagged there is the last one going on, and one ending there isn't.
+    #[test]
+    fn last_train_leaves_the_station() {
+        let tt = Timetable::embedded().unwrap();
+        let civic_center = find_station("civic-center").unwrap();
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 19).unwrap();
+        let schedule = tt.schedule(monday, Direction::East);
+        let red: Vec<(u32, &Trip)> = schedule
+            .departures(civic_center)
+            .into_iter()
+            .filter(|(_, t)| t.line == Line::Red)
+            .collect();
+        let onward = red
+            .iter()
+            .rev()
+            .find(|(_, t)| schedule.terminal(t) != Some(civic_center))
+            .unwrap();
+        assert!(red
+            .iter()
+            .any(|(time, t)| schedule.terminal(t) == Some(civic_center) && *time > onward.0));
+        let flagged: Vec<usize> = red
+            .iter()
+            .filter(|(_, t)| schedule.is_last(civic_center, t))
+            .map(|(_, t)| t.row)
+            .collect();
+        assert_eq!(flagged, [onward.1.row]);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::alerts::Alerts;
+use crate::chat::{self, Response};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{Line, STATIONS};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Local, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+// Slack slash commands. Either register /next, /board and /last directly, or
+// one command such as /metro that takes "next cwe west" as its text.
+
+// Slack's replay window for signed requests, in seconds.
+const MAX_AGE: i64 = 5 * 60;
+
+pub struct Slack {
+    pub signing_secret: String,
+}
+
+#[derive(Deserialize)]
+struct SlashCommand {
+    command: String,
+    #[serde(default)]
+    text: String,
+}
+
+// https://api.slack.com/authentication/verifying-requests-from-slack
+fn verify(secret: &str, timestamp: &str, signature: &str, body: &[u8], now: i64) -> bool {
+    let fresh = timestamp
+        .parse::<i64>()
+        .is_ok_and(|t| (now - t).abs() <= MAX_AGE);
+    let expected = match signature
+        .strip_prefix("v0=")
+        .and_then(|h| hex::decode(h).ok())
+    {
+        Some(bytes) if fresh => bytes,
+        _ => return false,
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn header<'a>(http: &'a HttpRequest, name: &str) -> &'a str {
+    http.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+}
+
+fn color(line: Option<Line>) -> String {
+    format!("#{:06X}", line.map_or(0x808080, chat::line_color))
+}
+
+fn message(response: &Response) -> Value {
+    match response {
+        Response::Board(station, directions, alerts) => {
+            let mut blocks = vec![
+                json!({ "type": "header", "text": { "type": "plain_text", "text": STATIONS[*station].name } }),
+            ];
+            if !alerts.is_empty() {
+                let text: Vec<String> = alerts.iter().map(chat::alert_line).collect();
+                blocks.push(json!({ "type": "section", "text": { "type": "mrkdwn", "text": text.join("\n") } }));
+            }
+            let attachments: Vec<Value> = directions
+                .iter()
+                .map(|(direction, departures)| {
+                    let mut text = format!("*{}*", chat::bound(*direction));
+                    if departures.is_empty() {
+                        text.push_str("\nno more trains today");
+                    }
+                    for d in departures {
+                        text.push('\n');
+                        text.push_str(&chat::departure_line(d));
+                    }
+                    json!({
+                        "color": color(departures.first().map(|d| d.line)),
+                        "blocks": [{ "type": "section", "text": { "type": "mrkdwn", "text": text } }],
+                    })
+                })
+                .collect();
+            json!({
+                "response_type": "in_channel",
+                "text": response.text(),
+                "blocks": blocks,
+                "attachments": attachments,
+            })
+        }
+        Response::Answer(..) => json!({
+            "response_type": "in_channel",
+            "text": response.text(),
+            "attachments": [{
+                "color": color(response.line()),
+                "blocks": [{ "type": "section", "text": { "type": "mrkdwn", "text": response.text() } }],
+            }],
+        }),
+        _ => json!({ "response_type": "ephemeral", "text": response.text() }),
+    }
+}
+
+pub async fn command(
+    http: HttpRequest,
+    slack: web::Data<Slack>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let signed = verify(
+        &slack.signing_secret,
+        header(&http, "X-Slack-Request-Timestamp"),
+        header(&http, "X-Slack-Signature"),
+        &body,
+        Utc::now().timestamp(),
+    );
+    if !signed {
+        return HttpResponse::Unauthorized()
+            .reason("invalid Slack signature")
+            .finish();
+    }
+    let slash: SlashCommand = match serde_urlencoded::from_bytes(&body) {
+        Ok(s) => s,
+        Err(_) => {
+            return HttpResponse::BadRequest()
+                .reason("not a slash command")
+                .finish()
+        }
+    };
+    let now = Local::now().naive_local();
+    let (command, args) = match chat::split(&slash.command) {
+        ("", _) => chat::split(&slash.text),
+        (command, _) => (command, slash.text.trim()),
+    };
+    // Slash commands have no follow-up, so show both directions instead of
+    // asking which one.
+    let response = match chat::respond(&tt, &realtime, &alerts, command, args, now) {
+        Response::ChooseDirection(station) => chat::board(&tt, &realtime, &alerts, station, now),
+        response => response,
+    };
+    HttpResponse::Ok().json(message(&response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example request from Slack's guide to verifying requests.
+    const SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const TIMESTAMP: &str = "1531420618";
+    const SIGNATURE: &str = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+    const BODY: &str = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow\
+        &channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner\
+        &command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands\
+        %2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN\
+        &trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const SENT: i64 = 1531420618;
+
+    #[test]
+    fn accepts_slacks_example() {
+        assert!(verify(
+            SECRET,
+            TIMESTAMP,
+            SIGNATURE,
+            BODY.as_bytes(),
+            SENT + 30
+        ));
+    }
+
+    #[test]
+    fn rejects_a_changed_body_or_secret() {
+        let changed = BODY.replace("text=", "text=cwe");
+        assert!(!verify(
+            SECRET,
+            TIMESTAMP,
+            SIGNATURE,
+            changed.as_bytes(),
+            SENT
+        ));
+        assert!(!verify(
+            "not the secret",
+            TIMESTAMP,
+            SIGNATURE,
+            BODY.as_bytes(),
+            SENT
+        ));
+        assert!(!verify(
+            SECRET,
+            TIMESTAMP,
+            &SIGNATURE[3..],
+            BODY.as_bytes(),
+            SENT
+        ));
+    }
+
+    #[test]
+    fn rejects_a_timestamp_outside_the_window() {
+        assert!(verify(
+            SECRET,
+            TIMESTAMP,
+            SIGNATURE,
+            BODY.as_bytes(),
+            SENT + MAX_AGE
+        ));
+        assert!(!verify(
+            SECRET,
+            TIMESTAMP,
+            SIGNATURE,
+            BODY.as_bytes(),
+            SENT + MAX_AGE + 1
+        ));
+        assert!(!verify(
+            SECRET,
+            TIMESTAMP,
+            SIGNATURE,
+            BODY.as_bytes(),
+            SENT - MAX_AGE - 1
+        ));
+        assert!(!verify(SECRET, "", SIGNATURE, BODY.as_bytes(), SENT));
+    }
+}
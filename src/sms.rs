@@ -0,0 +1,393 @@
+use crate::alerts::Alerts;
+use crate::ask;
+use crate::chat;
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{self, Departure, Direction, Timetable, STATIONS};
+use crate::{overlay, speech, v2};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use base64::Engine;
+use chrono::{Local, NaiveDateTime};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::collections::BTreeMap;
+
+// Twilio-compatible inbound SMS. Riders text a station and maybe a direction
+// ("cwe w", "delmar eastbound") and get the next two trains and any service
+// alerts at the station back in a single SMS segment.
+
+const SMS_LENGTH: usize = 160;
+
+const USAGE: &str = "Text a station and direction, like: cwe west";
+
+// Signature checking is on when an auth token is configured. Behind a proxy
+// the URL Twilio signed can differ from the one we see, so it can be given.
+pub struct Sms {
+    pub auth_token: Option<String>,
+    pub url: Option<String>,
+}
+
+// https://www.twilio.com/docs/usage/security#validating-requests
+fn verify(auth_token: &str, url: &str, params: &BTreeMap<String, String>, signature: &str) -> bool {
+    let expected = match base64::engine::general_purpose::STANDARD.decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(auth_token.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(url.as_bytes());
+    for (key, value) in params {
+        mac.update(key.as_bytes());
+        mac.update(value.as_bytes());
+    }
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn twiml(message: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/xml; charset=utf-8")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response><Message>{}</Message></Response>",
+            quick_xml::escape::escape(message)
+        ))
+}
+
+// Single letters are common in texts, so "e" and "w" count as directions here.
+fn direction(text: &str, query: &ask::Query) -> Option<Direction> {
+    query.direction.or_else(|| {
+        text.split_whitespace()
+            .rev()
+            .find_map(|w| match w.to_lowercase().as_str() {
+                "e" => Some(Direction::East),
+                "w" => Some(Direction::West),
+                _ => None,
+            })
+    })
+}
+
+// Everything has to fit in one segment. Headsigns use the station's short
+// name and are the first thing dropped, then trains from the end, and only
+// then is the alert text cut short.
+fn reply(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    text: &str,
+    now: NaiveDateTime,
+) -> String {
+    let query = ask::parse(text, now.date());
+    let station = match query.from.or(query.to) {
+        Some(s) => s,
+        None => return USAGE.to_string(),
+    };
+    let direction = direction(text, &query);
+    let in_effect = alerts.at_stations(&[station], &[], direction, v2::local(now));
+    let choices = match tt.closure(station, schedule::service_day(now).0) {
+        Some(closure) => vec![overlay::closed_message(closure, station)],
+        None => trains(tt, realtime, station, direction, now),
+    };
+    fit(&choices, &speech::alert_sentences(&in_effect))
+}
+
+// The first choice that fits with the alerts after it, or else the last one
+// with the alerts cut to the room left.
+fn fit(choices: &[String], alerts: &str) -> String {
+    let with_stop = |message: &str| match alerts.is_empty() || message.ends_with('.') {
+        true => message.to_string(),
+        false => format!("{}.", message),
+    };
+    let fitting = choices
+        .iter()
+        .map(|c| with_stop(c) + alerts)
+        .find(|m| m.chars().count() <= SMS_LENGTH);
+    if let Some(message) = fitting {
+        return message;
+    }
+    let message = with_stop(choices.last().map_or("", String::as_str));
+    let room = SMS_LENGTH.checked_sub(message.chars().count());
+    match room {
+        Some(room) => message + &shorten(alerts, room),
+        None => shorten(&message, SMS_LENGTH),
+    }
+}
+
+// Cut at a space with a marker, or nothing at all if no word fits.
+fn shorten(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max.saturating_sub(3)).collect();
+    match cut.rfind(' ').map(|i| cut[..i].trim_end()) {
+        Some(kept) if !kept.trim().is_empty() => format!("{}...", kept),
+        _ => String::new(),
+    }
+}
+
+// The trains as messages from the fullest to the shortest.
+fn trains(
+    tt: &Timetable,
+    realtime: &Realtime,
+    station: usize,
+    direction: Option<Direction>,
+    now: NaiveDateTime,
+) -> Vec<String> {
+    let trains: Vec<(Direction, Departure)> = match direction {
+        Some(d) => upcoming(tt, realtime, station, d, now, 2)
+            .into_iter()
+            .map(|t| (d, t))
+            .collect(),
+        None => Direction::ALL
+            .iter()
+            .flat_map(|d| {
+                upcoming(tt, realtime, station, *d, now, 1)
+                    .into_iter()
+                    .map(move |t| (*d, t))
+            })
+            .collect(),
+    };
+    if trains.is_empty() {
+        return vec![format!(
+            "No more trains at {} today.",
+            speech::spoken_name(station)
+        )];
+    }
+    let render = |trains: &[(Direction, Departure)], headsigns: bool| {
+        let mut message = speech::spoken_name(station);
+        let mut last: Option<Direction> = None;
+        for (direction, d) in trains {
+            if last == Some(*direction) {
+                message.push_str(", ");
+            } else {
+                if last.is_some() {
+                    message.push(';');
+                }
+                message.push_str(&format!(" {}: ", chat::bound(*direction)));
+            }
+            message.push_str(&format!(
+                "{} {}",
+                schedule::format_time(d.expected_time()),
+                d.line.as_str()
+            ));
+            if headsigns {
+                message.push_str(&format!(" to {}", STATIONS[d.terminal].aliases[0]));
+            }
+            last = Some(*direction);
+        }
+        message
+    };
+    let mut choices = vec![render(&trains, true)];
+    for count in (1..=trains.len()).rev() {
+        choices.push(render(&trains[..count], false));
+    }
+    choices
+}
+
+fn upcoming(
+    tt: &Timetable,
+    realtime: &Realtime,
+    station: usize,
+    direction: Direction,
+    now: NaiveDateTime,
+    count: usize,
+) -> Vec<Departure> {
+    realtime
+        .upcoming(tt, station, direction, now, count + 1)
+        .into_iter()
+        .filter(|d| d.terminal != station)
+        .take(count)
+        .collect()
+}
+
+#[post("/sms")]
+pub async fn inbound(
+    http: HttpRequest,
+    sms: web::Data<Sms>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    form: web::Form<BTreeMap<String, String>>,
+) -> HttpResponse {
+    let params = form.into_inner();
+    if let Some(auth_token) = &sms.auth_token {
+        let url = match &sms.url {
+            Some(url) => url.clone(),
+            None => {
+                let info = http.connection_info();
+                format!("{}://{}{}", info.scheme(), info.host(), http.uri())
+            }
+        };
+        let signature = http
+            .headers()
+            .get("X-Twilio-Signature")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !verify(auth_token, &url, &params, signature) {
+            return HttpResponse::Forbidden()
+                .reason("invalid Twilio signature")
+                .finish();
+        }
+    }
+    let body = params.get("Body").map(String::as_str).unwrap_or("");
+    twiml(&reply(
+        &tt,
+        &realtime,
+        &alerts,
+        body,
+        Local::now().naive_local(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    // One alert at Central West End, too long to fit in a segment.
+    const ALERTS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/alerts/cwe.json"
+    );
+
+    fn noon() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 19)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn params() -> BTreeMap<String, String> {
+        [
+            ("CallSid", "CA1234567890ABCDE"),
+            ("Caller", "+12349013030"),
+            ("Digits", "1234"),
+            ("From", "+12349013030"),
+            ("To", "+18005551212"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    // The example request from Twilio's guide to validating requests.
+    const URL: &str = "https://mycompany.com/myapp.php?foo=1&bar=2";
+    const SIGNATURE: &str = "0/KCTR6DLpKmkAf8muzZqo1nDgQ=";
+
+    #[test]
+    fn accepts_twilios_example() {
+        assert!(verify("12345", URL, &params(), SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_a_changed_request() {
+        let mut changed = params();
+        changed.insert("Digits".to_string(), "4321".to_string());
+        assert!(!verify("12345", URL, &changed, SIGNATURE));
+        assert!(!verify(
+            "12345",
+            "https://mycompany.com/myapp.php",
+            &params(),
+            SIGNATURE
+        ));
+        assert!(!verify("54321", URL, &params(), SIGNATURE));
+        assert!(!verify("12345", URL, &params(), "not base64!"));
+    }
+
+    #[test]
+    fn reads_station_and_direction() {
+        let read = |text: &str| {
+            let query = ask::parse(text, noon().date());
+            (query.from.or(query.to), direction(text, &query))
+        };
+        let cwe = schedule::find_station("cwe");
+        let delmar = schedule::find_station("delmar");
+        assert_eq!(read("cwe w"), (cwe, Some(Direction::West)));
+        assert_eq!(read("CWE E"), (cwe, Some(Direction::East)));
+        assert_eq!(read("delmar eastbound"), (delmar, Some(Direction::East)));
+        assert_eq!(read("delmar"), (delmar, None));
+        assert_eq!(read("when is the train").0, None);
+    }
+
+    #[test]
+    fn answers_in_one_segment() {
+        let tt = Timetable::embedded().unwrap();
+        let realtime = Realtime::new(None);
+        let alerts = Alerts::new(None);
+        let message = reply(&tt, &realtime, &alerts, "cwe w", noon());
+        assert!(message.starts_with("Central West End Westbound: "));
+        assert!(message.chars().count() <= SMS_LENGTH);
+        assert_eq!(
+            reply(&tt, &realtime, &alerts, "hello", noon()),
+            USAGE.to_string()
+        );
+    }
+
+    #[test]
+    fn alerts_fit_in_the_segment() {
+        let tt = Timetable::embedded().unwrap();
+        let realtime = Realtime::new(None);
+        let alerts = Alerts::new(Some(ALERTS));
+        for text in ["cwe", "cwe w", "cwe east"].iter() {
+            let message = reply(&tt, &realtime, &alerts, text, noon());
+            assert!(message.chars().count() <= SMS_LENGTH, "{}", message);
+            assert!(
+                message.contains(". Service alert: The elevator"),
+                "{}",
+                message
+            );
+            assert!(message.ends_with("..."), "{}", message);
+        }
+    }
+
+    fn choices() -> Vec<String> {
+        [
+            "Cwe: 1:00P red to lambert, 1:10P blue to shrewsbury",
+            "Cwe: 1:00P red, 1:10P blue",
+            "Cwe: 1:00P red",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn drops_headsigns_then_trains() {
+        let choices = choices();
+        assert_eq!(fit(&choices, ""), choices[0]);
+        let padded = |width: usize| -> Vec<String> {
+            choices
+                .iter()
+                .map(|c| format!("{} {}", c, "x".repeat(width)))
+                .collect()
+        };
+        assert_eq!(fit(&padded(120), ""), padded(120)[1]);
+        assert_eq!(fit(&padded(140), ""), padded(140)[2]);
+    }
+
+    #[test]
+    fn cuts_the_alert_text_last() {
+        let choices = choices();
+        let alert = |words: usize| format!(" Service alert:{}.", " word".repeat(words));
+        let short = alert(10);
+        assert_eq!(fit(&choices, &short), format!("{}.{}", choices[0], short));
+        let longer = alert(20);
+        assert_eq!(fit(&choices, &longer), format!("{}.{}", choices[1], longer));
+        let longest = alert(25);
+        assert_eq!(
+            fit(&choices, &longest),
+            format!("{}.{}", choices[2], longest)
+        );
+
+        let cut = fit(&choices, &alert(40));
+        assert!(cut.starts_with(&format!("{}. Service alert: word", choices[2])));
+        assert!(cut.ends_with(" word..."));
+        assert!(cut.chars().count() <= SMS_LENGTH);
+    }
+
+    #[test]
+    fn long_messages_are_cut_between_words() {
+        let closure = "Closed for track work. ".repeat(10);
+        let message = fit(&[closure], "");
+        assert!(message.chars().count() <= SMS_LENGTH);
+        assert!(message.ends_with(" Closed for track..."));
+    }
+}
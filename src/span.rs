@@ -0,0 +1,226 @@
+use crate::negotiate::{self, Reply};
+use crate::openapi;
+use crate::reload::Current;
+use crate::schedule::{self, DayType, Direction, Line, Schedule, Timetable, MINUTES_PER_DAY};
+use crate::v2::{self, StationRef};
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::Local;
+use schemars::JsonSchema;
+
+// When service starts and ends at a station: the first and last train of
+// each line in each direction, for every day type, from the schedule version
+// in effect today. Overlays and events are left out since they only change
+// particular dates.
+
+#[derive(Serialize, JsonSchema)]
+pub struct SpanTime {
+    #[schemars(schema_with = "openapi::time_schema")]
+    time: String,
+    // Past midnight, so on the calendar day after the service day.
+    after_midnight: bool,
+    headsign: StationRef,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Span {
+    day: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    first: SpanTime,
+    last: SpanTime,
+    // The last train to each station the line ends at from here, so the
+    // last one to a branch shows even when a later one goes elsewhere.
+    last_to: Vec<SpanTime>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SpanResponse {
+    station: StationRef,
+    schedule_version: String,
+    spans: Vec<Span>,
+}
+
+impl Reply for SpanResponse {
+    const NAME: &'static str = "span";
+
+    fn text(&self) -> String {
+        let spans: Vec<String> = self
+            .spans
+            .iter()
+            .map(|s| {
+                let last_to: Vec<String> = s
+                    .last_to
+                    .iter()
+                    .map(|l| format!("to {} {}", l.headsign.name, l.time))
+                    .collect();
+                format!(
+                    "{} {}bound {}: {} to {} (last {})",
+                    s.day,
+                    s.direction,
+                    s.line,
+                    s.first.time,
+                    s.last.time,
+                    last_to.join(", ")
+                )
+            })
+            .collect();
+        format!("{}: {}", self.station.name, spans.join("; "))
+    }
+}
+
+fn span_time(time: u32, terminal: usize) -> SpanTime {
+    SpanTime {
+        time: schedule::format_time(time),
+        after_midnight: time >= MINUTES_PER_DAY,
+        headsign: v2::station_ref(terminal),
+    }
+}
+
+// Service-day minutes keep trains after midnight in order after the
+// evening ones. Trains ending at the station don't leave it.
+fn span(schedule: &Schedule, station: usize, line: Line) -> Option<Span> {
+    let departures: Vec<(u32, usize)> = schedule
+        .departures(station)
+        .into_iter()
+        .filter(|(_, trip)| trip.line == line)
+        .filter_map(|(time, trip)| Some((time, schedule.terminal(trip)?)))
+        .filter(|(_, terminal)| *terminal != station)
+        .collect();
+    let (first, last) = (departures.first()?, departures.last()?);
+    let mut last_to: Vec<(u32, usize)> = Vec::new();
+    for (time, terminal) in departures.iter().rev() {
+        if !last_to.iter().any(|(_, t)| t == terminal) {
+            last_to.push((*time, *terminal));
+        }
+    }
+    Some(Span {
+        day: schedule.day.as_str().to_string(),
+        direction: schedule.direction.as_str().to_string(),
+        line: line.as_str().to_string(),
+        first: span_time(first.0, first.1),
+        last: span_time(last.0, last.1),
+        last_to: last_to
+            .into_iter()
+            .map(|(time, terminal)| span_time(time, terminal))
+            .collect(),
+    })
+}
+
+// The spans of each line in one schedule.
+fn spans(schedule: &Schedule, station: usize) -> Vec<Span> {
+    Line::ALL
+        .iter()
+        .filter_map(|line| span(schedule, station, *line))
+        .collect()
+}
+
+// Looks up the station and collects `rows` from every day type and
+// direction of the version in effect today. The headways answer the same way.
+pub fn by_schedule<T>(
+    http: &HttpRequest,
+    tt: &Timetable,
+    id: &str,
+    rows: impl Fn(&Schedule, usize) -> Vec<T>,
+) -> Result<(StationRef, String, Vec<T>), HttpResponse> {
+    let station = match schedule::find_station(id) {
+        Some(s) => s,
+        None => {
+            return Err(v2::error(
+                http,
+                StatusCode::NOT_FOUND,
+                "unknown_station",
+                "that station is not in the schedule",
+            ))
+        }
+    };
+    let (today, _) = schedule::service_day(Local::now().naive_local());
+    let version = &tt.versions[tt.version_index(today)];
+    let mut found = Vec::new();
+    for day in DayType::ALL.iter().copied() {
+        for direction in Direction::ALL.iter().copied() {
+            found.extend(rows(version.schedule(day, direction), station));
+        }
+    }
+    Ok((v2::station_ref(station), version.id.clone(), found))
+}
+
+#[get("/stations/{id}/span")]
+pub async fn station_span(http: HttpRequest, tt: Current, path: web::Path<String>) -> HttpResponse {
+    match by_schedule(&http, &tt, &path, spans) {
+        Ok((station, schedule_version, spans)) => negotiate::reply(
+            &http,
+            StatusCode::OK,
+            &SpanResponse {
+                station,
+                schedule_version,
+                spans,
+            },
+        ),
+        Err(res) => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::find_station;
+
+    // Red trains run through to Fairview Heights or turn back at Civic
+    // Center, and the last of each leaves Central West End after midnight.
+    const EASTBOUND: &str = "\
+Central West End Station,Grand Station,Civic Center Station,Fairview Heights Station
+12:10AR,12:15AR,12:20AR,12:50AR
+6:00AR,6:05AR,6:10AR,6:40AR
+7:00AB,7:05AB,7:10AB,7:40AB
+11:30PR,11:35PR,11:40PR,
+12:40AR,12:45AR,12:50AR,
+";
+
+    fn schedule() -> Schedule {
+        Schedule::parse(DayType::Weekday, Direction::East, EASTBOUND.as_bytes()).unwrap()
+    }
+
+    fn shown(time: &SpanTime) -> (&str, bool, &str) {
+        (
+            time.time.as_str(),
+            time.after_midnight,
+            time.headsign.id.as_str(),
+        )
+    }
+
+    #[test]
+    fn last_trains_run_after_midnight() {
+        let schedule = schedule();
+        let span = span(&schedule, find_station("cwe").unwrap(), Line::Red).unwrap();
+        assert_eq!(shown(&span.first), ("6:00A", false, "fairview-heights"));
+        assert_eq!(shown(&span.last), ("12:40A", true, "civic-center"));
+        let last_to: Vec<_> = span.last_to.iter().map(shown).collect();
+        assert_eq!(
+            last_to,
+            [
+                ("12:40A", true, "civic-center"),
+                ("12:10A", true, "fairview-heights")
+            ]
+        );
+    }
+
+    #[test]
+    fn trains_ending_at_the_station_are_left_out() {
+        let schedule = schedule();
+        let spans = spans(&schedule, find_station("civic-center").unwrap());
+        let red = spans.iter().find(|s| s.line == "red").unwrap();
+        assert_eq!(shown(&red.last), ("12:20A", true, "fairview-heights"));
+        assert_eq!(red.last_to.len(), 1);
+        let blue = spans.iter().find(|s| s.line == "blue").unwrap();
+        assert_eq!(shown(&blue.first), shown(&blue.last));
+        assert!(span(
+            &schedule,
+            find_station("fairview-heights").unwrap(),
+            Line::Red
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::alerts::{Alert, Alerts};
+use crate::ask;
+use crate::negotiate::{self, Reply};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{self, Departure, Direction, Timetable, STATIONS};
+use crate::{overlay, v2};
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDateTime};
+use schemars::JsonSchema;
+
+// Sentences for chat bots and voice assistants, built from the official
+// station names rather than the short aliases riders type.
+
+#[derive(Serialize, JsonSchema)]
+pub struct SpeechResponse {
+    pub text: String,
+    pub ssml: String,
+    // Service alerts in effect at the station; their headers are read out
+    // after the trains.
+    pub alerts: Vec<Alert>,
+}
+
+impl SpeechResponse {
+    fn new(text: String, body: String, alerts: Vec<Alert>) -> SpeechResponse {
+        let spoken = alert_sentences(&alerts);
+        SpeechResponse {
+            ssml: format!("<speak>{}{}</speak>", body, escape(&spoken)),
+            text: text + &spoken,
+            alerts,
+        }
+    }
+}
+
+impl Reply for SpeechResponse {
+    const NAME: &'static str = "speech";
+
+    fn text(&self) -> String {
+        self.text.clone()
+    }
+}
+
+// "ShrewsburyLansdowne I44 Station" reads as "Shrewsbury Lansdowne I44".
+pub fn spoken_name(station: usize) -> String {
+    let name = STATIONS[station].name;
+    let name = name.strip_suffix(" Station").unwrap_or(name);
+    let mut out = String::with_capacity(name.len() + 4);
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        if let Some(p) = prev {
+            // Split words the CSV ran together, but keep names like DeBaliviere.
+            if p.is_lowercase() && c.is_uppercase() && !out.ends_with(" De") {
+                out.push(' ');
+            }
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+pub fn spoken_time(minutes: u32) -> String {
+    let hh = (minutes / 60) % 24;
+    let meridiem = if hh < 12 { "AM" } else { "PM" };
+    let hh = match hh % 12 {
+        0 => 12,
+        h => h,
+    };
+    format!("{}:{:02} {}", hh, minutes % 60, meridiem)
+}
+
+fn same_half_of_day(a: u32, b: u32) -> bool {
+    ((a / 60) % 24 < 12) == ((b / 60) % 24 < 12)
+}
+
+fn minutes_away(d: &Departure, now: NaiveDateTime) -> String {
+    let mins = (d.expected() - now).num_minutes().max(0);
+    let (hours, mins) = (mins / 60, mins % 60);
+    let plural = |n: i64, unit: &str| match n {
+        1 => format!("1 {}", unit),
+        n => format!("{} {}s", n, unit),
+    };
+    match (hours, mins) {
+        (0, 0) => "right now".to_string(),
+        (0, m) => format!("in {}", plural(m, "minute")),
+        (h, 0) => format!("in {}", plural(h, "hour")),
+        (h, m) => format!("in {} {}", plural(h, "hour"), plural(m, "minute")),
+    }
+}
+
+// " Service alert: Elevator out at Central West End." for each alert.
+pub fn alert_sentences(alerts: &[Alert]) -> String {
+    alerts
+        .iter()
+        .map(|a| {
+            let header = a.header.trim();
+            match header.ends_with(['.', '!', '?']) {
+                true => format!(" Service alert: {}", header),
+                false => format!(" Service alert: {}.", header),
+            }
+        })
+        .collect()
+}
+
+// A piece of a sentence: either plain words or a clock time, so the same
+// sentence can be rendered as text or with SSML time markup. Text may drop
+// AM/PM when it's obvious from the earlier time; speech always keeps it.
+enum Part {
+    Words(String),
+    Time(u32, bool),
+}
+
+fn parts(
+    station: usize,
+    direction: Direction,
+    departures: &[Departure],
+    now: NaiveDateTime,
+) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let first = match departures.first() {
+        Some(d) => d,
+        None => {
+            parts.push(Part::Words(format!(
+                "There are no more {}bound trains at {} today.",
+                direction.as_str(),
+                spoken_name(station)
+            )));
+            return parts;
+        }
+    };
+    parts.push(Part::Words(format!(
+        "The next {}bound {} line train at {} leaves at ",
+        direction.as_str(),
+        first.line.as_str(),
+        spoken_name(station)
+    )));
+    parts.push(Part::Time(first.expected_time(), false));
+    parts.push(Part::Words(format!(", {}.", minutes_away(first, now))));
+    if let Some(second) = departures.get(1) {
+        if second.line == first.line {
+            parts.push(Part::Words(" The one after is at ".to_string()));
+        } else {
+            parts.push(Part::Words(format!(
+                " The one after is a {} line train at ",
+                second.line.as_str()
+            )));
+        }
+        parts.push(Part::Time(
+            second.expected_time(),
+            same_half_of_day(first.expected_time(), second.expected_time()),
+        ));
+        parts.push(Part::Words(".".to_string()));
+    }
+    parts
+}
+
+pub fn sentence(
+    station: usize,
+    direction: Direction,
+    departures: &[Departure],
+    now: NaiveDateTime,
+) -> String {
+    parts(station, direction, departures, now)
+        .into_iter()
+        .map(|p| match p {
+            Part::Words(w) => w,
+            Part::Time(t, true) => schedule::format_time(t)
+                .trim_end_matches(['A', 'P'])
+                .to_string(),
+            Part::Time(t, false) => spoken_time(t),
+        })
+        .collect()
+}
+
+fn ssml_body(
+    station: usize,
+    direction: Direction,
+    departures: &[Departure],
+    now: NaiveDateTime,
+) -> String {
+    parts(station, direction, departures, now)
+        .into_iter()
+        .map(|p| match p {
+            Part::Words(w) => escape(&w),
+            Part::Time(t, _) => format!(
+                "<say-as interpret-as=\"time\" format=\"hms12\">{}</say-as>",
+                spoken_time(t)
+            ),
+        })
+        .collect()
+}
+
+// Voice platforms fill slots rather than passing on what was said. The slot
+// values go through the /ask grammar, so short names, "eastbound" and line
+// names all work, and an intent named like "LastTrain" asks for the last one.
+pub fn from_slots(
+    tt: &Timetable,
+    realtime: &Realtime,
+    store: &Alerts,
+    intent: &str,
+    slots: &[&str],
+    now: NaiveDateTime,
+) -> Option<SpeechResponse> {
+    let intent = intent.to_lowercase();
+    let mut words = slots.join(" ");
+    if intent.contains("last") {
+        words.insert_str(0, "last ");
+    } else if intent.contains("first") {
+        words.insert_str(0, "first ");
+    }
+    let query = ask::parse(&words, now.date());
+    let station = query.from.or(query.to)?;
+    let answer = ask::answer(tt, realtime, &query, now)?;
+    // Upcoming trains get time markup; anything else is read out as written.
+    let body = match (query.intent, &answer.trip) {
+        (ask::Intent::Next, None) if !answer.departures.is_empty() => Direction::ALL
+            .iter()
+            .filter_map(|direction| {
+                let departures: Vec<Departure> = answer
+                    .departures
+                    .iter()
+                    .filter(|(d, _)| d == direction)
+                    .map(|(_, d)| d.clone())
+                    .collect();
+                match departures.is_empty() {
+                    true => None,
+                    false => Some(ssml_body(station, *direction, &departures, now)),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => escape(&answer.text),
+    };
+    let alerts = ask::alerts(store, &query, &answer, now);
+    Some(SpeechResponse::new(answer.text, body, alerts))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[post("/next-arrival/speech")]
+pub async fn next_arrival_speech(
+    http: HttpRequest,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    req: web::Json<v2::NextArrivalRequest>,
+) -> HttpResponse {
+    let station = match schedule::find_station(&req.station) {
+        Some(s) => s,
+        None => {
+            return v2::error(
+                &http,
+                StatusCode::NOT_FOUND,
+                "unknown_station",
+                "that station is not in the schedule",
+            )
+        }
+    };
+    let direction = match Direction::parse(&req.direction) {
+        Some(d) => d,
+        None => {
+            return v2::error(
+                &http,
+                StatusCode::BAD_REQUEST,
+                "invalid_direction",
+                "direction must be 'east' or 'west'",
+            )
+        }
+    };
+    let now = Local::now().naive_local();
+    let in_effect = alerts.at_stations(&[station], &[], Some(direction), v2::local(now));
+    if let Some(closure) = tt.closure(station, schedule::service_day(now).0) {
+        let text = overlay::closed_message(closure, station);
+        let body = escape(&text);
+        return negotiate::reply(
+            &http,
+            StatusCode::OK,
+            &SpeechResponse::new(text, body, in_effect),
+        );
+    }
+    let departures = realtime.upcoming(&tt, station, direction, now, 2);
+    negotiate::reply(
+        &http,
+        StatusCode::OK,
+        &SpeechResponse::new(
+            sentence(station, direction, &departures, now),
+            ssml_body(station, direction, &departures, now),
+            in_effect,
+        ),
+    )
+}
@@ -0,0 +1,128 @@
+use crate::alerts::Alerts;
+use crate::chat::{self, Response};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{Direction, Timetable, STATIONS};
+use actix_web::client::Client;
+use actix_web::{web, HttpResponse};
+use chrono::{Local, NaiveDateTime};
+use serde_json::{json, Value};
+
+// Telegram Bot API webhook. Telegram posts every update to a secret path and
+// we answer by calling the Bot API back, so the chat bot no longer needs a
+// process of its own.
+
+pub const DEFAULT_API: &str = "https://api.telegram.org";
+
+#[derive(Clone)]
+pub struct Bot {
+    pub token: String,
+    pub api: String,
+}
+
+#[derive(Deserialize)]
+pub struct Update {
+    message: Option<Message>,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    id: String,
+    data: Option<String>,
+    message: Option<Message>,
+}
+
+fn respond(
+    tt: &Timetable,
+    realtime: &Realtime,
+    alerts: &Alerts,
+    text: &str,
+    now: NaiveDateTime,
+) -> Response {
+    let (command, args) = chat::split(text);
+    chat::respond(tt, realtime, alerts, command, args, now)
+}
+
+impl Bot {
+    async fn call(&self, method: &str, body: Value) {
+        let url = format!(
+            "{}/bot{}/{}",
+            self.api.trim_end_matches('/'),
+            self.token,
+            method
+        );
+        match Client::default().post(url).send_json(&body).await {
+            Ok(res) if !res.status().is_success() => {
+                println!("telegram {} failed: {}", method, res.status())
+            }
+            Err(e) => println!("telegram {} failed: {}", method, e),
+            Ok(_) => {}
+        }
+    }
+
+    // Choosing a direction is an inline keyboard whose buttons send the
+    // full command back.
+    async fn send(&self, chat: i64, response: Response) {
+        let mut body = json!({ "chat_id": chat, "text": response.text() });
+        if let Response::ChooseDirection(station) = response {
+            let row: Vec<Value> = Direction::ALL
+                .iter()
+                .map(|d| {
+                    let data = format!("/next {} {}", STATIONS[station].id, d.as_str());
+                    json!({ "text": chat::bound(*d), "callback_data": data })
+                })
+                .collect();
+            body["reply_markup"] = json!({ "inline_keyboard": [row] });
+        }
+        self.call("sendMessage", body).await;
+    }
+}
+
+// Telegram retries anything but a 2xx, so failures are logged and the update
+// is still acknowledged.
+pub async fn webhook(
+    bot: web::Data<Bot>,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    update: web::Json<Update>,
+) -> HttpResponse {
+    let now = Local::now().naive_local();
+    let update = update.into_inner();
+    if let Some(message) = update.message {
+        if let Some(text) = message.text {
+            bot.send(
+                message.chat.id,
+                respond(&tt, &realtime, &alerts, &text, now),
+            )
+            .await;
+        }
+    }
+    if let Some(callback) = update.callback_query {
+        bot.call(
+            "answerCallbackQuery",
+            json!({ "callback_query_id": callback.id }),
+        )
+        .await;
+        if let (Some(data), Some(message)) = (callback.data, callback.message) {
+            bot.send(
+                message.chat.id,
+                respond(&tt, &realtime, &alerts, &data, now),
+            )
+            .await;
+        }
+    }
+    HttpResponse::Ok().finish()
+}
@@ -0,0 +1,322 @@
+use crate::alerts::{Alert, Alerts};
+use crate::negotiate::{self, Reply};
+use crate::planner::{self, PlanError};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{self, DayType, Departure, Direction, Timetable, STATIONS};
+use crate::{ical, openapi, overlay, v2};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDate};
+use schemars::JsonSchema;
+
+// The original API. Field names and the "1:34P" time format are frozen here;
+// new fields belong in v2.
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct NextArrivalRequest {
+    #[schemars(schema_with = "openapi::station_schema")]
+    station: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct NextArrivalResponse {
+    station: String,
+    direction: String,
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    time: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ArriveByRequest {
+    #[schemars(schema_with = "openapi::station_schema")]
+    from: String,
+    #[schemars(schema_with = "openapi::station_schema")]
+    to: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    arrive_by: String,
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TripPlanResponse {
+    from: String,
+    to: String,
+    date: NaiveDate,
+    #[schemars(schema_with = "openapi::time_schema")]
+    departure: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    arrival: String,
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    legs: Vec<TripLegResponse>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TripLegResponse {
+    from: String,
+    to: String,
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    departure: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    arrival: String,
+}
+
+impl Reply for NextArrivalResponse {
+    const NAME: &'static str = "next-arrival";
+
+    fn text(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.station, self.direction, self.line, self.time
+        )
+    }
+}
+
+impl Reply for TripPlanResponse {
+    const NAME: &'static str = "trip-plan";
+
+    fn text(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            self.from, self.to, self.departure, self.arrival, self.line
+        )
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(next_arrival)
+        .service(arrive_by)
+        .service(arrive_by_ics)
+        .service(timetable_ics);
+}
+
+// A closed station is a 404 like any other missing service, with the
+// overlay's advice in a header.
+fn closed(tt: &Timetable, station: usize, date: NaiveDate) -> HttpResponse {
+    let mut res = HttpResponse::NotFound()
+        .reason(PlanError::Closed(station, date).message())
+        .finish();
+    let message = tt
+        .closure(station, date)
+        .map(|o| overlay::closed_message(o, station));
+    if let Some(value) = message.and_then(|m| HeaderValue::from_str(&m).ok()) {
+        res.headers_mut()
+            .insert(HeaderName::from_static("service-closure"), value);
+    }
+    res
+}
+
+// Like the version, alerts can't be fields here; their ids go in a header
+// and the details are at /alerts.
+fn alerts_header(res: &mut HttpResponse, alerts: &[Alert]) {
+    let ids: Vec<&str> = alerts.iter().map(|a| a.id.as_str()).collect();
+    if let (false, Ok(value)) = (ids.is_empty(), HeaderValue::from_str(&ids.join(", "))) {
+        res.headers_mut()
+            .insert(HeaderName::from_static("service-alerts"), value);
+    }
+}
+
+// The next train tonight as the real-time feed expects it, or else the first
+// one on the next service day.
+fn next_departure(
+    tt: &Timetable,
+    realtime: &Realtime,
+    station: usize,
+    direction: Direction,
+    date: NaiveDate,
+    now: u32,
+) -> Option<Departure> {
+    let at = schedule::service_datetime(date, now);
+    let mut found = realtime.upcoming(tt, station, direction, at, 1);
+    if found.is_empty() {
+        found = tt.next_service_day(station, direction, date, 1);
+    }
+    found.pop()
+}
+
+#[post("/next-arrival")]
+async fn next_arrival(
+    http: HttpRequest,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    req: web::Json<NextArrivalRequest>,
+) -> HttpResponse {
+    let input = req.into_inner();
+    let (date, now) = schedule::service_day(Local::now().naive_local());
+    match parse_request_pick_file(date, input.direction.as_str()) {
+        Some((_, direction)) => match schedule::find_station(&input.station) {
+            Some(station) if tt.closure(station, date).is_some() => closed(&tt, station, date),
+            Some(station) => match next_departure(&tt, &realtime, station, direction, date, now) {
+                Some(d) => {
+                    let mut res = negotiate::reply(
+                        &http,
+                        StatusCode::OK,
+                        &NextArrivalResponse {
+                            station: input.station,
+                            direction: input.direction,
+                            line: d.line.as_str().to_string(),
+                            time: schedule::format_time(d.expected_time()),
+                        },
+                    );
+                    // The fields are frozen, so the version goes in a header.
+                    if let Ok(version) = HeaderValue::from_str(&d.version) {
+                        res.headers_mut()
+                            .insert(HeaderName::from_static("schedule-version"), version);
+                    }
+                    // So does the date when the train is on a later service
+                    // day, and whether it's the last one tonight.
+                    if d.date != date {
+                        if let Ok(value) = HeaderValue::from_str(&d.date.to_string()) {
+                            res.headers_mut()
+                                .insert(HeaderName::from_static("next-service-day"), value);
+                        }
+                    }
+                    if d.last {
+                        res.headers_mut().insert(
+                            HeaderName::from_static("last-train"),
+                            HeaderValue::from_static("true"),
+                        );
+                    }
+                    let at = v2::local(d.datetime());
+                    alerts_header(
+                        &mut res,
+                        &alerts.at_stations(&[station], &[], Some(direction), at),
+                    );
+                    res
+                }
+                None => HttpResponse::NotFound()
+                    .reason("no trains are scheduled in the next week")
+                    .finish(),
+            },
+            None => HttpResponse::InternalServerError().into(),
+        },
+        None => HttpResponse::BadRequest()
+            .reason("direction must be 'east' or 'west'")
+            .finish(),
+    }
+}
+
+#[post("/arrive-by")]
+async fn arrive_by(
+    http: HttpRequest,
+    tt: Current,
+    alerts: web::Data<Alerts>,
+    req: web::Json<ArriveByRequest>,
+) -> HttpResponse {
+    let input = req.into_inner();
+    match plan_arrive_by(&tt, &input) {
+        Ok(plan) => {
+            let mut res = negotiate::reply(
+                &http,
+                StatusCode::OK,
+                &trip_plan_response(input.from, input.to, &plan),
+            );
+            alerts_header(&mut res, &alerts.on_trip(&plan));
+            res
+        }
+        Err(resp) => resp,
+    }
+}
+
+#[post("/arrive-by.ics")]
+async fn arrive_by_ics(tt: Current, req: web::Json<ArriveByRequest>) -> HttpResponse {
+    match plan_arrive_by(&tt, &req) {
+        Ok(plan) => HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .body(ical::trip_plan(&plan)),
+        Err(resp) => resp,
+    }
+}
+
+#[get("/timetable/{station}/{direction}/{day}.ics")]
+async fn timetable_ics(tt: Current, path: web::Path<(String, String, String)>) -> HttpResponse {
+    let (station, direction, day) = path.into_inner();
+    let station = match schedule::find_station(&station) {
+        Some(s) => s,
+        None => {
+            return HttpResponse::NotFound()
+                .reason("that station is not in the schedule")
+                .finish()
+        }
+    };
+    // A weekly rule can't leave out one day's overlays or events, so the
+    // files are used as they are, up to when the next version takes over.
+    let (today, _) = schedule::service_day(Local::now().naive_local());
+    let index = tt.version_index(today);
+    match (Direction::parse(&direction), DayType::parse(&day)) {
+        (Some(direction), Some(day)) => HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .body(ical::timetable(
+                tt.versions[index].schedule(day, direction),
+                station,
+                today,
+                tt.in_effect_until(index, today),
+            )),
+        (None, _) => HttpResponse::BadRequest()
+            .reason("direction must be 'east' or 'west'")
+            .finish(),
+        (_, None) => HttpResponse::BadRequest()
+            .reason("day must be 'weekday', 'saturday' or 'sunday'")
+            .finish(),
+    }
+}
+
+fn plan_arrive_by(tt: &Timetable, input: &ArriveByRequest) -> Result<planner::Plan, HttpResponse> {
+    let today = Local::now().date_naive();
+    planner::arrive_by_request(
+        tt,
+        &input.from,
+        &input.to,
+        &input.arrive_by,
+        input.date.unwrap_or(today),
+    )
+    .map_err(|e| match e {
+        PlanError::NoTrip => HttpResponse::NotFound().reason(e.message()).finish(),
+        PlanError::Closed(station, date) => closed(tt, station, date),
+        _ => HttpResponse::BadRequest().reason(e.message()).finish(),
+    })
+}
+
+fn trip_plan_response(from: String, to: String, plan: &planner::Plan) -> TripPlanResponse {
+    TripPlanResponse {
+        from,
+        to,
+        date: plan.date,
+        departure: schedule::format_time(plan.departure()),
+        arrival: schedule::format_time(plan.arrival()),
+        line: plan.legs[0].line.as_str().to_string(),
+        legs: plan
+            .legs
+            .iter()
+            .map(|leg| TripLegResponse {
+                from: STATIONS[leg.from].name.to_string(),
+                to: STATIONS[leg.to].name.to_string(),
+                line: leg.line.as_str().to_string(),
+                direction: leg.direction.as_str().to_string(),
+                departure: schedule::format_time(leg.departure),
+                arrival: schedule::format_time(leg.arrival),
+            })
+            .collect(),
+    }
+}
+
+fn parse_request_pick_file(date: NaiveDate, direction: &str) -> Option<(DayType, Direction)> {
+    match Direction::parse(direction) {
+        Some(d) => Some((DayType::for_date(date), d)),
+        None => {
+            println!("not east or west?");
+            None
+        }
+    }
+}
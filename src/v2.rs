@@ -0,0 +1,386 @@
+use crate::alerts::{Alert, Alerts};
+use crate::negotiate::{self, Reply};
+use crate::planner::{self, PlanError};
+use crate::realtime::Realtime;
+use crate::reload::Current;
+use crate::schedule::{self, Departure, Direction, STATIONS};
+use crate::{openapi, overlay, speech};
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use schemars::JsonSchema;
+
+// Structured responses: stations carry both id and official name, times are
+// full timestamps, and errors come back as a JSON body.
+
+const MAX_ARRIVALS: usize = 10;
+
+fn default_count() -> usize {
+    1
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "NextArrivalRequestV2")]
+pub struct NextArrivalRequest {
+    #[schemars(schema_with = "openapi::station_schema")]
+    pub station: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    pub direction: String,
+    // How many upcoming trains to return, at most 10.
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "ArriveByRequestV2")]
+pub struct ArriveByRequest {
+    #[schemars(schema_with = "openapi::station_schema")]
+    from: String,
+    #[schemars(schema_with = "openapi::station_schema")]
+    to: String,
+    #[schemars(schema_with = "openapi::time_schema")]
+    arrive_by: String,
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StationRef {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ScheduleTime {
+    timestamp: DateTime<Local>,
+    service_date: NaiveDate,
+    #[schemars(schema_with = "openapi::time_schema")]
+    display: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Arrival {
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    headsign: StationRef,
+    time: ScheduleTime,
+    minutes_away: i64,
+    // Which schedule set answered, for tracking down reports of wrong times.
+    schedule_version: String,
+    // "realtime" when the live feed moved this train, else "scheduled".
+    source: String,
+    // Seconds behind schedule, and when the train is now expected; realtime
+    // arrivals only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay_seconds: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<ScheduleTime>,
+    // A train added on top of the regular timetable, and the event it runs
+    // for, if any.
+    extra_service: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<String>,
+    // The last train of the night from this station on its line.
+    last_train: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "NextArrivalResponseV2")]
+pub struct NextArrivalResponse {
+    station: StationRef,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+    // The service date the arrivals are on. After the last train of the
+    // night it's the next one with service, and next_service_day is set.
+    service_date: NaiveDate,
+    next_service_day: bool,
+    arrivals: Vec<Arrival>,
+    // Service alerts in effect at the station.
+    alerts: Vec<Alert>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TripLeg {
+    from: StationRef,
+    to: StationRef,
+    #[schemars(schema_with = "openapi::line_schema")]
+    line: String,
+    #[schemars(schema_with = "openapi::direction_schema")]
+    direction: String,
+    headsign: StationRef,
+    departure: ScheduleTime,
+    arrival: ScheduleTime,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TripPlan {
+    from: StationRef,
+    to: StationRef,
+    departure: ScheduleTime,
+    arrival: ScheduleTime,
+    legs: Vec<TripLeg>,
+    schedule_version: String,
+    // Service alerts in effect along the way when the trip starts.
+    alerts: Vec<Alert>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorBody {
+    error: ErrorDetail,
+}
+
+impl Reply for NextArrivalResponse {
+    const NAME: &'static str = "next-arrival";
+
+    fn text(&self) -> String {
+        let arrivals: Vec<String> = self
+            .arrivals
+            .iter()
+            .map(|a| {
+                let time = a.expected.as_ref().unwrap_or(&a.time);
+                format!(
+                    "{} {} to {} ({} min)",
+                    time.display, a.line, a.headsign.name, a.minutes_away
+                )
+            })
+            .collect();
+        let on = match self.next_service_day {
+            true => format!(" on {}", self.service_date),
+            false => String::new(),
+        };
+        format!(
+            "{} {}bound{}: {}",
+            self.station.name,
+            self.direction,
+            on,
+            arrivals.join("; ")
+        )
+    }
+}
+
+impl Reply for TripPlan {
+    const NAME: &'static str = "trip-plan";
+
+    fn text(&self) -> String {
+        let lines: Vec<&str> = self.legs.iter().map(|l| l.line.as_str()).collect();
+        format!(
+            "{} {} -> {} {} ({})",
+            self.departure.display,
+            self.from.name,
+            self.arrival.display,
+            self.to.name,
+            lines.join(", ")
+        )
+    }
+}
+
+impl Reply for ErrorBody {
+    const NAME: &'static str = "error";
+
+    fn text(&self) -> String {
+        format!("error: {}: {}", self.error.code, self.error.message)
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(next_arrival)
+        .service(speech::next_arrival_speech)
+        .service(arrive_by);
+}
+
+pub fn error(http: &HttpRequest, status: StatusCode, code: &str, message: &str) -> HttpResponse {
+    negotiate::reply(
+        http,
+        status,
+        &ErrorBody {
+            error: ErrorDetail {
+                code: code.to_string(),
+                message: message.to_string(),
+            },
+        },
+    )
+}
+
+pub fn station_ref(station: usize) -> StationRef {
+    StationRef {
+        id: STATIONS[station].id.to_string(),
+        name: STATIONS[station].name.to_string(),
+    }
+}
+
+pub fn local(t: NaiveDateTime) -> DateTime<Local> {
+    match Local.from_local_datetime(&t).earliest() {
+        Some(t) => t,
+        // Only inside the spring-forward gap, where no train is scheduled.
+        None => Local.from_utc_datetime(&t),
+    }
+}
+
+fn schedule_time(date: NaiveDate, minutes: u32) -> ScheduleTime {
+    ScheduleTime {
+        timestamp: local(schedule::service_datetime(date, minutes)),
+        service_date: date,
+        display: schedule::format_time(minutes),
+    }
+}
+
+pub fn arrival(d: &Departure, now: NaiveDateTime) -> Arrival {
+    Arrival {
+        line: d.line.as_str().to_string(),
+        headsign: station_ref(d.terminal),
+        time: schedule_time(d.date, d.time),
+        minutes_away: (d.expected() - now).num_minutes(),
+        schedule_version: d.version.clone(),
+        source: match d.delay {
+            Some(_) => "realtime",
+            None => "scheduled",
+        }
+        .to_string(),
+        delay_seconds: d.delay,
+        expected: d.delay.map(|_| ScheduleTime {
+            timestamp: local(d.expected()),
+            service_date: d.date,
+            display: schedule::format_time(d.expected_time()),
+        }),
+        extra_service: d.extra,
+        event: d.event.clone(),
+        last_train: d.last,
+    }
+}
+
+#[post("/next-arrival")]
+async fn next_arrival(
+    http: HttpRequest,
+    tt: Current,
+    realtime: web::Data<Realtime>,
+    alerts: web::Data<Alerts>,
+    req: web::Json<NextArrivalRequest>,
+) -> HttpResponse {
+    let station = match schedule::find_station(&req.station) {
+        Some(s) => s,
+        None => {
+            return error(
+                &http,
+                StatusCode::NOT_FOUND,
+                "unknown_station",
+                "that station is not in the schedule",
+            )
+        }
+    };
+    let direction = match Direction::parse(&req.direction) {
+        Some(d) => d,
+        None => {
+            return error(
+                &http,
+                StatusCode::BAD_REQUEST,
+                "invalid_direction",
+                "direction must be 'east' or 'west'",
+            )
+        }
+    };
+    let now = Local::now().naive_local();
+    let (today, _) = schedule::service_day(now);
+    if let Some(closure) = tt.closure(station, today) {
+        return error(
+            &http,
+            StatusCode::NOT_FOUND,
+            "no_service",
+            &overlay::closed_message(closure, station),
+        );
+    }
+    let count = req.count.clamp(1, MAX_ARRIVALS);
+    let mut departures = realtime.upcoming(&tt, station, direction, now, count);
+    if departures.is_empty() {
+        departures = tt.next_service_day(station, direction, today, count);
+    }
+    let service_date = match departures.first() {
+        Some(d) => d.date,
+        None => {
+            return error(
+                &http,
+                StatusCode::NOT_FOUND,
+                "no_service",
+                "no trains are scheduled in the next week",
+            )
+        }
+    };
+    negotiate::reply(
+        &http,
+        StatusCode::OK,
+        &NextArrivalResponse {
+            station: station_ref(station),
+            direction: direction.as_str().to_string(),
+            service_date,
+            next_service_day: service_date != today,
+            arrivals: departures.iter().map(|d| arrival(d, now)).collect(),
+            alerts: alerts.at_stations(&[station], &[], Some(direction), local(now)),
+        },
+    )
+}
+
+#[post("/arrive-by")]
+async fn arrive_by(
+    http: HttpRequest,
+    tt: Current,
+    alerts: web::Data<Alerts>,
+    req: web::Json<ArriveByRequest>,
+) -> HttpResponse {
+    let date = req.date.unwrap_or_else(|| Local::now().date_naive());
+    match planner::arrive_by_request(&tt, &req.from, &req.to, &req.arrive_by, date) {
+        Ok(plan) => negotiate::reply(
+            &http,
+            StatusCode::OK,
+            &trip_plan(&plan, alerts.on_trip(&plan)),
+        ),
+        Err(e) => {
+            let (status, code) = match e {
+                PlanError::UnknownStation => (StatusCode::NOT_FOUND, "unknown_station"),
+                PlanError::BadTime => (StatusCode::BAD_REQUEST, "invalid_time"),
+                PlanError::NoTrip => (StatusCode::NOT_FOUND, "no_trip"),
+                PlanError::Closed(station, date) => {
+                    let message = tt
+                        .closure(station, date)
+                        .map(|o| overlay::closed_message(o, station));
+                    return error(
+                        &http,
+                        StatusCode::NOT_FOUND,
+                        "no_service",
+                        &message.unwrap_or_default(),
+                    );
+                }
+            };
+            error(&http, status, code, e.message())
+        }
+    }
+}
+
+pub fn trip_plan(plan: &planner::Plan, alerts: Vec<Alert>) -> TripPlan {
+    let first = &plan.legs[0];
+    let last = &plan.legs[plan.legs.len() - 1];
+    TripPlan {
+        from: station_ref(first.from),
+        to: station_ref(last.to),
+        departure: schedule_time(plan.date, plan.departure()),
+        arrival: schedule_time(plan.date, plan.arrival()),
+        legs: plan
+            .legs
+            .iter()
+            .map(|leg| TripLeg {
+                from: station_ref(leg.from),
+                to: station_ref(leg.to),
+                line: leg.line.as_str().to_string(),
+                direction: leg.direction.as_str().to_string(),
+                headsign: station_ref(leg.headsign),
+                departure: schedule_time(plan.date, leg.departure),
+                arrival: schedule_time(plan.date, leg.arrival),
+            })
+            .collect(),
+        schedule_version: plan.version.clone(),
+        alerts,
+    }
+}
@@ -0,0 +1,475 @@
+use crate::schedule::{self, DayType, Direction, Line, STATIONS};
+use csv::ReaderBuilder;
+use std::collections::HashMap;
+use std::fmt;
+
+// `metro-schedule-api validate [DIR]` checks the schedule CSVs before they
+// are shipped, since the server only notices bad data when a request hits it.
+// Without a directory it checks the data built into the binary.
+
+// Longest wait between trains at a station, in the middle of the day, before
+// it is worth a look.
+const MAX_HEADWAY: u32 = 60;
+
+// Running times between neighbouring stops above this many times the usual
+// time for that pair (and at least SLOW_MINUTES longer) are flagged.
+const SLOW_FACTOR: u32 = 2;
+const SLOW_MINUTES: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+pub struct Issue {
+    file: String,
+    row: Option<usize>,
+    col: Option<usize>,
+    severity: Severity,
+    message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(row) = self.row {
+            write!(f, ":{}", row)?;
+        }
+        if let Some(col) = self.col {
+            write!(f, ":{}", col)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+#[derive(Default)]
+pub struct Report {
+    files: usize,
+    issues: Vec<Issue>,
+}
+
+impl Report {
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Error)
+    }
+
+    fn add(
+        &mut self,
+        file: &str,
+        row: Option<usize>,
+        col: Option<usize>,
+        severity: Severity,
+        message: String,
+    ) {
+        self.issues.push(Issue {
+            file: file.to_string(),
+            row,
+            col,
+            severity,
+            message,
+        });
+    }
+
+    fn error(&mut self, file: &str, row: Option<usize>, col: Option<usize>, message: String) {
+        self.add(file, row, col, Severity::Error, message);
+    }
+
+    fn warning(&mut self, file: &str, row: Option<usize>, col: Option<usize>, message: String) {
+        self.add(file, row, col, Severity::Warning, message);
+    }
+}
+
+// One row of a file: its line number and, per column, the service-day minute
+// and line of each cell that parsed.
+struct Row {
+    number: usize,
+    cells: Vec<Option<(u32, Line)>>,
+}
+
+struct File {
+    name: String,
+    headers: Vec<String>,
+    rows: Vec<Row>,
+}
+
+// Strictly H:MM[AP][RB], so "01:34PR" or " 1:34PR" are caught too.
+fn valid_cell(cell: &str) -> bool {
+    if !cell.is_ascii() {
+        return false;
+    }
+    let bytes = cell.as_bytes();
+    let colon = match cell.find(':') {
+        Some(i @ 1..=2) => i,
+        _ => return false,
+    };
+    let hour = &cell[..colon];
+    bytes.len() == colon + 5
+        && !hour.starts_with('0')
+        && hour.parse::<u32>().is_ok_and(|h| (1..=12).contains(&h))
+        && cell[colon + 1..colon + 3]
+            .parse::<u32>()
+            .is_ok_and(|m| m < 60)
+        && bytes[colon + 1].is_ascii_digit()
+        && bytes[colon + 2].is_ascii_digit()
+        && matches!(bytes[colon + 3], b'A' | b'P')
+        && matches!(bytes[colon + 4], b'R' | b'B')
+}
+
+fn read(report: &mut Report, name: &str, contents: &[u8]) -> Option<File> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(contents);
+    let headers: Vec<String> = match reader.headers() {
+        Ok(h) => h.iter().map(String::from).collect(),
+        Err(e) => {
+            report.error(name, Some(1), None, e.to_string());
+            return None;
+        }
+    };
+    let mut stations = Vec::with_capacity(headers.len());
+    for (col, header) in headers.iter().enumerate() {
+        let station = STATIONS.iter().position(|s| s.name == header);
+        match station {
+            None => report.error(
+                name,
+                Some(1),
+                Some(col + 1),
+                format!("unknown station '{}'", header),
+            ),
+            Some(s) if stations.contains(&Some(s)) => report.error(
+                name,
+                Some(1),
+                Some(col + 1),
+                format!("'{}' appears twice", header),
+            ),
+            Some(_) => {}
+        }
+        stations.push(station);
+    }
+    for station in STATIONS.iter() {
+        if !headers.iter().any(|h| h == station.name) {
+            report.error(
+                name,
+                Some(1),
+                None,
+                format!("no column for '{}'", station.name),
+            );
+        }
+    }
+    let mut rows = Vec::new();
+    // Rows are numbered as in an editor, with the header on row 1.
+    for (i, record) in reader.records().enumerate() {
+        let number = i + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                report.error(name, Some(number), None, e.to_string());
+                continue;
+            }
+        };
+        if record.len() != headers.len() {
+            report.error(
+                name,
+                Some(number),
+                None,
+                format!(
+                    "{} cells but {} stations in the header",
+                    record.len(),
+                    headers.len()
+                ),
+            );
+        }
+        // Cells past the header have no station to check them against.
+        let mut cells = Vec::with_capacity(headers.len());
+        for (col, cell) in record.iter().take(headers.len()).enumerate() {
+            if cell.is_empty() {
+                cells.push(None);
+            } else if !valid_cell(cell) {
+                report.error(
+                    name,
+                    Some(number),
+                    Some(col + 1),
+                    format!("'{}' is not a time like 1:34PR", cell),
+                );
+                cells.push(None);
+            } else {
+                cells.push(schedule::parse_cell(cell));
+            }
+        }
+        if cells.iter().all(Option::is_none) {
+            report.warning(name, Some(number), None, "row has no times".to_string());
+        }
+        rows.push(Row { number, cells });
+    }
+    Some(File {
+        name: name.to_string(),
+        headers,
+        rows,
+    })
+}
+
+fn check_trips(report: &mut Report, file: &File) {
+    for row in file.rows.iter() {
+        let stops: Vec<(usize, u32, Line)> = row
+            .cells
+            .iter()
+            .enumerate()
+            .filter_map(|(col, c)| c.map(|(t, l)| (col, t, l)))
+            .collect();
+        if let Some((_, _, line)) = stops.first() {
+            if let Some((col, _, other)) = stops.iter().find(|(_, _, l)| l != line) {
+                report.error(
+                    &file.name,
+                    Some(row.number),
+                    Some(col + 1),
+                    format!(
+                        "trip changes from the {} line to the {} line",
+                        line.as_str(),
+                        other.as_str()
+                    ),
+                );
+            }
+        }
+        for pair in stops.windows(2) {
+            let ((_, before, _), (col, after, _)) = (pair[0], pair[1]);
+            if after < before {
+                report.error(
+                    &file.name,
+                    Some(row.number),
+                    Some(col + 1),
+                    format!(
+                        "{} is earlier than {} at the stop before",
+                        schedule::format_time(after),
+                        schedule::format_time(before)
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn check_columns(report: &mut Report, file: &File) {
+    for col in 0..file.headers.len() {
+        let times: Vec<(usize, u32)> = file
+            .rows
+            .iter()
+            .filter_map(|r| {
+                r.cells
+                    .get(col)
+                    .copied()
+                    .flatten()
+                    .map(|(t, _)| (r.number, t))
+            })
+            .collect();
+        for pair in times.windows(2) {
+            let ((_, before), (row, after)) = (pair[0], pair[1]);
+            if after < before {
+                report.error(
+                    &file.name,
+                    Some(row),
+                    Some(col + 1),
+                    format!(
+                        "{} is earlier than {} in the row above",
+                        schedule::format_time(after),
+                        schedule::format_time(before)
+                    ),
+                );
+            } else if after - before > MAX_HEADWAY {
+                report.warning(
+                    &file.name,
+                    Some(row),
+                    Some(col + 1),
+                    format!(
+                        "{} minute gap after {} at {}",
+                        after - before,
+                        schedule::format_time(before),
+                        file.headers[col]
+                    ),
+                );
+            }
+        }
+    }
+}
+
+// A blank cell between two stops is fine where the line doesn't serve that
+// station (the Red line skips the Shrewsbury branch), but not otherwise. And
+// a hop that takes far longer than usual is probably a typo.
+fn check_running_times(report: &mut Report, file: &File) {
+    let mut served: HashMap<Line, Vec<bool>> = HashMap::new();
+    let mut hops: HashMap<(usize, usize), Vec<u32>> = HashMap::new();
+    for row in file.rows.iter() {
+        let stops: Vec<(usize, u32, Line)> = row
+            .cells
+            .iter()
+            .enumerate()
+            .filter_map(|(col, c)| c.map(|(t, l)| (col, t, l)))
+            .collect();
+        for (col, _, line) in stops.iter() {
+            served
+                .entry(*line)
+                .or_insert_with(|| vec![false; file.headers.len()])[*col] = true;
+        }
+        for pair in stops.windows(2) {
+            hops.entry((pair[0].0, pair[1].0))
+                .or_default()
+                .push(pair[1].1.saturating_sub(pair[0].1));
+        }
+    }
+    let usual: HashMap<(usize, usize), u32> = hops
+        .into_iter()
+        .map(|(hop, mut minutes)| {
+            minutes.sort_unstable();
+            (hop, minutes[minutes.len() / 2])
+        })
+        .collect();
+    for row in file.rows.iter() {
+        let stops: Vec<(usize, u32, Line)> = row
+            .cells
+            .iter()
+            .enumerate()
+            .filter_map(|(col, c)| c.map(|(t, l)| (col, t, l)))
+            .collect();
+        for pair in stops.windows(2) {
+            let ((from, before, line), (to, after, _)) = (pair[0], pair[1]);
+            for skipped in (from + 1..to).filter(|col| served[&line][*col]) {
+                report.warning(
+                    &file.name,
+                    Some(row.number),
+                    Some(skipped + 1),
+                    format!(
+                        "{} line trip skips {}",
+                        line.as_str(),
+                        file.headers[skipped]
+                    ),
+                );
+            }
+            let minutes = after.saturating_sub(before);
+            let typical = usual[&(from, to)];
+            if minutes > typical * SLOW_FACTOR && minutes >= typical + SLOW_MINUTES {
+                report.warning(
+                    &file.name,
+                    Some(row.number),
+                    Some(to + 1),
+                    format!(
+                        "{} minutes from {} to {}, usually {}",
+                        minutes, file.headers[from], file.headers[to], typical
+                    ),
+                );
+            }
+        }
+    }
+}
+
+// One set of six files: the base set, or a dated version's subdirectory.
+fn check_set(report: &mut Report, dir: Option<&str>, subdir: Option<&str>) {
+    let mut files: HashMap<(DayType, Direction), File> = HashMap::new();
+    for day in DayType::ALL.iter().copied() {
+        for direction in Direction::ALL.iter().copied() {
+            let name = schedule::version_file(subdir, day, direction);
+            let contents = match schedule::read_file(dir, &name) {
+                Ok(c) => c,
+                Err(e) => {
+                    report.error(&name, None, None, e);
+                    continue;
+                }
+            };
+            if let Some(file) = read(report, &name, &contents) {
+                check_trips(report, &file);
+                check_columns(report, &file);
+                check_running_times(report, &file);
+                files.insert((day, direction), file);
+            }
+        }
+    }
+    for day in DayType::ALL.iter().copied() {
+        if let (Some(east), Some(west)) = (
+            files.get(&(day, Direction::East)),
+            files.get(&(day, Direction::West)),
+        ) {
+            let reversed: Vec<&String> = east.headers.iter().rev().collect();
+            if west.headers.iter().collect::<Vec<_>>() != reversed {
+                report.error(
+                    &west.name,
+                    Some(1),
+                    None,
+                    format!("header is not the reverse of {}", east.name),
+                );
+            }
+        }
+    }
+    report.files += files.len();
+}
+
+pub fn check(dir: Option<&str>) -> Report {
+    let mut report = Report::default();
+    check_set(&mut report, dir, None);
+    match schedule::read_versions(dir) {
+        Ok(versions) => {
+            for version in versions.iter() {
+                check_set(&mut report, dir, Some(&version.version));
+            }
+        }
+        Err(e) => report.error(schedule::VERSIONS_FILE, None, None, e),
+    }
+    report
+}
+
+pub fn run(dir: Option<&str>) -> bool {
+    let report = check(dir);
+    let errors = report.errors().count();
+    for issue in report.issues.iter() {
+        println!("{}", issue);
+    }
+    println!(
+        "{} files checked: {} errors, {} warnings",
+        report.files,
+        errors,
+        report.issues.len() - errors
+    );
+    errors == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(report: &Report) -> Vec<String> {
+        report.errors().map(|i| i.to_string()).collect()
+    }
+
+    // The embedded weekday eastbound file with its first trip changed by `edit`.
+    fn checked(edit: impl Fn(&str) -> String) -> Report {
+        let name = schedule::version_file(None, DayType::Weekday, Direction::East);
+        let contents = String::from_utf8(schedule::read_file(None, &name).unwrap()).unwrap();
+        let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+        lines[1] = edit(&lines[1]);
+        let mut report = Report::default();
+        let file = read(&mut report, &name, lines.join("\n").as_bytes()).unwrap();
+        check_trips(&mut report, &file);
+        check_columns(&mut report, &file);
+        check_running_times(&mut report, &file);
+        report
+    }
+
+    #[test]
+    fn reports_a_cell_that_is_not_ascii() {
+        let report = checked(|row| {
+            let first = row.split(',').find(|c| !c.is_empty()).unwrap();
+            row.replacen(first, "1:0\u{e9}R", 1)
+        });
+        assert!(messages(&report)
+            .iter()
+            .any(|m| m.ends_with("'1:0\u{e9}R' is not a time like 1:34PR")));
+    }
+
+    #[test]
+    fn reports_a_row_longer_than_the_header() {
+        let report = checked(|row| format!("{},5:00AR", row));
+        assert!(messages(&report)
+            .iter()
+            .any(|m| m.contains("cells but") && m.contains("stations in the header")));
+    }
+}
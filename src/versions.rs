@@ -0,0 +1,81 @@
+use crate::negotiate::{self, Reply};
+use crate::overlay::Overlay;
+use crate::reload::Current;
+use crate::schedule;
+use actix_web::http::StatusCode;
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDate};
+use schemars::JsonSchema;
+
+// Lists the schedule sets that are loaded, so it's easy to see which one a
+// date will be answered from. Arrivals carry the same ids. Overlays are
+// listed too, since they change what a version answers on their dates.
+
+#[derive(Serialize, JsonSchema)]
+pub struct ScheduleVersion {
+    id: String,
+    // Service dates, both inclusive; null is open-ended.
+    effective_from: Option<NaiveDate>,
+    effective_until: Option<NaiveDate>,
+    trips: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct VersionsResponse {
+    // The version answering for today's service.
+    in_effect: String,
+    versions: Vec<ScheduleVersion>,
+    overlays: Vec<Overlay>,
+}
+
+impl Reply for VersionsResponse {
+    const NAME: &'static str = "versions";
+
+    fn text(&self) -> String {
+        self.versions
+            .iter()
+            .map(|v| {
+                let date = |d: Option<NaiveDate>| d.map_or("...".to_string(), |d| d.to_string());
+                let current = if v.id == self.in_effect {
+                    " (in effect)"
+                } else {
+                    ""
+                };
+                format!(
+                    "{} {} to {}{}",
+                    v.id,
+                    date(v.effective_from),
+                    date(v.effective_until),
+                    current
+                )
+            })
+            .chain(self.overlays.iter().map(|o| {
+                format!(
+                    "overlay {} {} to {}",
+                    o.id, o.effective_from, o.effective_until
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[get("/schedule/versions")]
+pub async fn versions(http: HttpRequest, tt: Current) -> HttpResponse {
+    let (today, _) = schedule::service_day(Local::now().naive_local());
+    let response = VersionsResponse {
+        in_effect: tt.version(today).id.clone(),
+        versions: tt
+            .versions
+            .iter()
+            .map(|v| ScheduleVersion {
+                id: v.id.clone(),
+                effective_from: v.effective_from,
+                effective_until: v.effective_until,
+                trips: v.schedules.iter().map(|s| s.trips.len()).sum(),
+            })
+            .collect(),
+        overlays: tt.overlays.clone(),
+    };
+    negotiate::reply(&http, StatusCode::OK, &response)
+}
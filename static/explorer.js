@@ -0,0 +1,128 @@
+// A small explorer for /openapi.json, kept in the binary so /docs works
+// without reaching any other site.
+
+"use strict";
+
+const METHODS = ["get", "post", "put", "delete"];
+
+function element(tag, attributes, ...children) {
+  const node = document.createElement(tag);
+  Object.entries(attributes || {}).forEach(([k, v]) => node.setAttribute(k, v));
+  children.forEach((c) => node.append(c));
+  return node;
+}
+
+function resolve(doc, schema) {
+  while (schema && schema.$ref) {
+    schema = doc.components.schemas[schema.$ref.split("/").pop()];
+  }
+  return schema || {};
+}
+
+// A body to start from, with every required field filled in.
+function example(doc, schema, depth) {
+  schema = resolve(doc, schema);
+  if (depth > 5) return null;
+  if (schema.example !== undefined) return schema.example;
+  if (schema.enum) return schema.enum[0];
+  if (schema.allOf) return example(doc, schema.allOf[0], depth + 1);
+  switch (schema.type) {
+    case "object": {
+      const body = {};
+      (schema.required || []).forEach((name) => {
+        body[name] = example(doc, schema.properties[name], depth + 1);
+      });
+      return body;
+    }
+    case "array":
+      return [];
+    case "integer":
+    case "number":
+      return 0;
+    case "boolean":
+      return false;
+    case "string":
+      return schema.format === "date" ? new Date().toISOString().slice(0, 10) : "";
+    default:
+      return null;
+  }
+}
+
+function describe(doc, responses) {
+  const rows = Object.entries(responses || {}).map(([status, r]) =>
+    element("tr", {}, element("td", {}, status), element("td", {}, r.description || ""))
+  );
+  return element("table", {}, ...rows);
+}
+
+async function send(path, method, operation, form, output) {
+  let url = path;
+  const query = new URLSearchParams();
+  (operation.parameters || []).forEach((p) => {
+    const value = form.querySelector(`[name="${p.in}:${p.name}"]`).value;
+    if (p.in === "path") url = url.replace(`{${p.name}}`, encodeURIComponent(value));
+    else if (value !== "") query.set(p.name, value);
+  });
+  if ([...query].length) url += "?" + query;
+  const init = { method: method.toUpperCase(), headers: { Accept: form.querySelector("[name=accept]").value } };
+  const body = form.querySelector("textarea");
+  if (body) {
+    init.headers["Content-Type"] = "application/json";
+    init.body = body.value;
+  }
+  output.textContent = "…";
+  try {
+    const res = await fetch(url, init);
+    const text = await res.text();
+    output.textContent = `${res.status} ${res.statusText}\n\n${text}`;
+  } catch (e) {
+    output.textContent = String(e);
+  }
+}
+
+function operationView(doc, path, method, operation) {
+  const summary = element(
+    "summary",
+    {},
+    element("span", { class: `method ${method}` }, method),
+    " ",
+    element("span", { class: "path" }, path),
+    " ",
+    operation.summary || ""
+  );
+  const form = element("form");
+  (operation.parameters || []).forEach((p) => {
+    const input = element("input", { name: `${p.in}:${p.name}` });
+    form.append(element("label", {}, `${p.name} (${p.in}${p.required ? ", required" : ""}) `, input));
+  });
+  const content = operation.requestBody && operation.requestBody.content["application/json"];
+  if (content) {
+    const textarea = element("textarea");
+    textarea.value = JSON.stringify(example(doc, content.schema, 0), null, 2);
+    form.append(element("label", {}, "body", textarea));
+  }
+  const accept = element("select", { name: "accept" });
+  ["application/json", "application/xml", "text/csv", "application/msgpack", "text/plain"].forEach((type) =>
+    accept.append(element("option", {}, type))
+  );
+  form.append(element("label", {}, "Accept ", accept));
+  const output = element("pre");
+  form.append(element("button", { type: "submit" }, "Send"));
+  form.addEventListener("submit", (e) => {
+    e.preventDefault();
+    send(path, method, operation, form, output);
+  });
+  const details = element("details", {}, summary, element("div", {}, form, output, describe(doc, operation.responses)));
+  if (operation.deprecated) details.classList.add("deprecated");
+  return details;
+}
+
+async function load() {
+  const root = document.getElementById("explorer");
+  const doc = await (await fetch("/openapi.json")).json();
+  Object.entries(doc.paths).forEach(([path, item]) => {
+    METHODS.filter((m) => item[m]).forEach((m) => root.append(operationView(doc, path, m, item[m])));
+  });
+}
+
+load();
@@ -0,0 +1,30 @@
+#!/bin/sh
+# Regenerates the Alexa signing fixtures: a throwaway root, a signing
+# certificate for echo-api.amazon.com issued by it, and request.json signed
+# with that certificate's key. The keys are not kept.
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+openssl req -x509 -newkey rsa:2048 -nodes -sha256 -days 3650 \
+    -subj "/CN=Metro Schedule API test root" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -keyout "$tmp/root.key" -out root.pem
+
+openssl req -newkey rsa:2048 -nodes -sha256 -subj "/CN=echo-api.amazon.com" \
+    -keyout "$tmp/signing.key" -out "$tmp/signing.csr"
+cat > "$tmp/signing.ext" <<EXT
+basicConstraints=critical,CA:FALSE
+keyUsage=critical,digitalSignature,keyEncipherment
+extendedKeyUsage=serverAuth
+subjectAltName=DNS:echo-api.amazon.com
+EXT
+openssl x509 -req -sha256 -days 3650 -in "$tmp/signing.csr" \
+    -CA root.pem -CAkey "$tmp/root.key" -CAcreateserial -extfile "$tmp/signing.ext" \
+    -out "$tmp/signing.pem"
+cat "$tmp/signing.pem" root.pem > chain.pem
+
+openssl dgst -sha256 -sign "$tmp/signing.key" request.json | base64 -w0 > request.sig
+rm -f root.srl